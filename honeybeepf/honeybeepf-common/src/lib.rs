@@ -1,5 +1,10 @@
 #![no_std]
 
+#[cfg(feature = "user")]
+mod rwbs;
+#[cfg(feature = "user")]
+pub use rwbs::{Rwbs, RwbsFlag, RwbsFlags};
+
 #[repr(C)]
 #[derive(Clone, Copy, Default)]
 pub struct EventMetadata {
@@ -30,20 +35,177 @@ unsafe impl aya::Pod for GpuOpenEvent {}
 #[derive(Clone, Copy)]
 pub struct ConnectionEvent {
     pub metadata: EventMetadata,
-    pub dest_addr: u32,
+    /// Destination address as stored in the sockaddr, i.e. in network byte
+    /// order; IPv4 addresses take the first 4 bytes.
+    pub dest_addr: [u8; 16],
+    /// Interface index of a scoped (e.g. link-local) IPv6 destination.
+    pub scope_id: u32,
+    /// In network byte order.
     pub dest_port: u16,
     pub address_family: u16,
+    /// From the connect() call until the connection was established or failed.
+    pub latency_ns: u64,
+    /// Negative errno of a failed connect; 0 once connected.
+    pub error: i32,
+    pub _pad: u32,
 }
 
 #[cfg(feature = "user")]
 unsafe impl aya::Pod for ConnectionEvent {}
 
+#[repr(u8)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "user", derive(serde::Serialize))]
+#[cfg_attr(feature = "user", serde(rename_all = "snake_case"))]
+pub enum TcpEventType {
+    Unknown = 0,
+    /// A segment was sent again, from `tcp_retransmit_skb`.
+    Retransmit = 1,
+    /// The socket answered with a reset, from `tcp_send_reset`.
+    ResetSent = 2,
+    /// The peer reset the connection, from `tcp_receive_reset`.
+    ResetReceived = 3,
+}
+
+impl TcpEventType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Retransmit => "retransmit",
+            Self::ResetSent => "reset_sent",
+            Self::ResetReceived => "reset_received",
+            Self::Unknown => "unknown",
+        }
+    }
+}
+
+impl From<u8> for TcpEventType {
+    fn from(v: u8) -> Self {
+        match v {
+            1 => Self::Retransmit,
+            2 => Self::ResetSent,
+            3 => Self::ResetReceived,
+            _ => Self::Unknown,
+        }
+    }
+}
+
+/// Retransmit or reset on a TCP socket. Softirqs and timers send most of them,
+/// so the metadata is that of the task that connected the socket, and empty for
+/// sockets whose owner was not seen.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct TcpEvent {
+    pub metadata: EventMetadata,
+    /// Local address as IPv6, IPv4 ones mapped (`::ffff:a.b.c.d`).
+    pub saddr: [u8; 16],
+    /// Remote address, like `saddr`.
+    pub daddr: [u8; 16],
+    /// In host byte order.
+    pub sport: u16,
+    pub dport: u16,
+    pub address_family: u16,
+    /// `TCP_ESTABLISHED` and friends; 0 when the tracepoint does not record it.
+    pub state: u8,
+    pub event_type: u8, // Casts to TcpEventType
+    /// Retransmits the event stands for, as those throttled on the socket since
+    /// its previous event are folded into the next; 1 for resets.
+    pub count: u32,
+    pub _pad: u32,
+}
+
+#[cfg(feature = "user")]
+unsafe impl aya::Pod for TcpEvent {}
+
+/// Name of the array map holding a `TcpTraceLayout` per `TcpProgram`.
+pub const TCP_TRACE_LAYOUTS_MAP: &str = "TCP_TRACE_LAYOUTS";
+
+/// The TCP programs reading socket records, indexing `TCP_TRACE_LAYOUTS_MAP`.
+#[repr(u32)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TcpProgram {
+    Retransmit = 0,
+    SendReset = 1,
+    ReceiveReset = 2,
+    /// `sock:inet_sock_set_state`, where connections are picked up for byte accounting.
+    SetState = 3,
+}
+
+pub const TCP_PROGRAMS: u32 = 4;
+
+/// Marks a `TcpTraceLayout` field the tracepoint does not have.
+pub const TCP_FIELD_MISSING: u16 = u16::MAX;
+
+/// Offsets of the socket fields a TCP program reads from its tracepoint's
+/// record, taken from the tracefs format by user space. Most tracepoints record
+/// ports, family and IPv6 (or mapped) addresses separately; newer `tcp_send_reset`
+/// records whole `sockaddr_in`/`sockaddr_in6` addresses instead.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TcpTraceLayout {
+    pub skaddr: u16,
+    pub state: u16,
+    /// `TCP_FIELD_MISSING` for sockaddr addresses, which carry family and ports.
+    pub sport: u16,
+    pub dport: u16,
+    pub family: u16,
+    /// `saddr_v6`/`daddr_v6`, or the `saddr`/`daddr` sockaddrs.
+    pub saddr: u16,
+    pub daddr: u16,
+    /// Non-zero when `saddr` and `daddr` are sockaddrs.
+    pub sockaddr: u16,
+}
+
+#[cfg(feature = "user")]
+unsafe impl aya::Pod for TcpTraceLayout {}
+
+/// Name of the LRU map holding a `TcpConnection` per established TCP socket,
+/// keyed by the socket's kernel address.
+pub const TCP_CONNECTIONS_MAP: &str = "TCP_CONNECTIONS";
+
+/// Bytes a TCP connection moved so far. Kept in `TCP_CONNECTIONS_MAP` while the
+/// connection is open, and sent through the ringbuf once it closes.
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+pub struct TcpConnection {
+    /// Task that first sent or received on the connection, and when it was
+    /// established; together with `sk` this identifies the connection.
+    pub metadata: EventMetadata,
+    pub sk: u64,
+    /// Local and remote addresses, as in `TcpEvent`.
+    pub saddr: [u8; 16],
+    pub daddr: [u8; 16],
+    /// In host byte order.
+    pub sport: u16,
+    pub dport: u16,
+    pub address_family: u16,
+    pub _pad: u16,
+    /// Bytes tcp_sendmsg() accepted.
+    pub sent_bytes: u64,
+    /// Bytes read by the application.
+    pub received_bytes: u64,
+}
+
+#[cfg(feature = "user")]
+unsafe impl aya::Pod for TcpConnection {}
+
+/// Name of the single-entry array map holding the active `CommonConfig`.
+pub const COMMON_CONFIG_MAP: &str = "HONEYBEE_CONFIG";
+
+/// Agent settings visible to the eBPF programs. Written by user space after
+/// load and whenever the settings change.
 #[repr(C)]
 #[derive(Clone, Copy, Default)]
 pub struct CommonConfig {
     pub probe_block_io: u8,
     pub probe_network_latency: u8,
     pub probe_gpu_open: u8,
+    pub probe_tcp_retransmit: u8,
+    pub probe_tcp_traffic: u8,
+    /// Non-zero when block IO is aggregated into `BLOCK_IO_HISTOGRAMS_MAP`
+    /// instead of streamed through the ringbuf.
+    pub block_io_histograms: u8,
+    /// Seconds between the builtin probes' reports. Retransmits on a socket are
+    /// reported at most once per interval.
     pub probe_interval: u32,
 }
 
@@ -52,18 +214,34 @@ unsafe impl aya::Pod for CommonConfig {}
 
 #[repr(u8)]
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "user", derive(serde::Serialize))]
+#[cfg_attr(feature = "user", serde(rename_all = "lowercase"))]
 pub enum BlockIoEventType {
     Unknown = 0,
     Start = 1,
     Done = 2,
+    /// Completion that failed, from `block_rq_error` or an erroring `block_rq_complete`.
+    Error = 3,
     // Add future types here as needed
 }
 
+impl BlockIoEventType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Start => "start",
+            Self::Done => "done",
+            Self::Error => "error",
+            Self::Unknown => "unknown",
+        }
+    }
+}
+
 impl From<u8> for BlockIoEventType {
     fn from(v: u8) -> Self {
         match v {
             1 => Self::Start,
             2 => Self::Done,
+            3 => Self::Error,
             _ => Self::Unknown,
         }
     }
@@ -75,12 +253,385 @@ pub struct BlockIoEvent {
     pub metadata: EventMetadata,
     pub dev: u32,
     pub sector: u64,
+    /// Time since the matching start event; 0 for start events and unmatched completions.
+    pub latency_ns: u64,
     pub nr_sector: u32,
     pub bytes: u32,
     pub rwbs: [u8; 8],
     pub comm: [u8; 16],
+    /// Negative errno the request failed with, on error events; 0 otherwise.
+    pub error: i32,
     pub event_type: u8, // Casts to BlockIoEventType
 }
 
 #[cfg(feature = "user")]
 unsafe impl aya::Pod for BlockIoEvent {}
+
+/// Number of low bits used for the minor number in the kernel's internal dev_t.
+pub const MINORBITS: u32 = 20;
+
+pub const fn dev_major(dev: u32) -> u32 {
+    dev >> MINORBITS
+}
+
+pub const fn dev_minor(dev: u32) -> u32 {
+    dev & ((1 << MINORBITS) - 1)
+}
+
+impl BlockIoEvent {
+    pub fn major(&self) -> u32 {
+        dev_major(self.dev)
+    }
+
+    pub fn minor(&self) -> u32 {
+        dev_minor(self.dev)
+    }
+}
+
+/// Operation of a block request, as named by the first letters of its rwbs.
+#[repr(u8)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "user", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "user", serde(rename_all = "snake_case"))]
+pub enum BlockIoOp {
+    Other = 0,
+    Read = 1,
+    Write = 2,
+    Discard = 3,
+    Flush = 4,
+    SecureErase = 5,
+}
+
+impl BlockIoOp {
+    pub fn from_rwbs(rwbs: &[u8]) -> Self {
+        split_rwbs(rwbs).1
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Other => "other",
+            Self::Read => "read",
+            Self::Write => "write",
+            Self::Discard => "discard",
+            Self::Flush => "flush",
+            Self::SecureErase => "secure_erase",
+        }
+    }
+}
+
+/// Splits rwbs into whether it starts with a preflush, the operation, and the
+/// index where the trailing flags begin. Only looks at the first three bytes,
+/// so the eBPF programs can use it on the raw tracepoint field.
+fn split_rwbs(rwbs: &[u8]) -> (bool, BlockIoOp, usize) {
+    let at = |i: usize| rwbs.get(i).copied().unwrap_or(0);
+    // A leading F is a preflush when an operation follows; on its own it is a flush
+    let preflush = at(0) == b'F' && matches!(at(1), b'R' | b'W' | b'D' | b'F' | b'N');
+    let start = preflush as usize;
+    let (op, len) = match at(start) {
+        b'R' => (BlockIoOp::Read, 1),
+        b'W' => (BlockIoOp::Write, 1),
+        b'D' if at(start + 1) == b'E' => (BlockIoOp::SecureErase, 2),
+        b'D' => (BlockIoOp::Discard, 1),
+        b'F' => (BlockIoOp::Flush, 1),
+        b'N' => (BlockIoOp::Other, 1),
+        _ => (BlockIoOp::Other, 0),
+    };
+    (preflush, op, start + len)
+}
+
+impl From<u8> for BlockIoOp {
+    fn from(v: u8) -> Self {
+        match v {
+            1 => Self::Read,
+            2 => Self::Write,
+            3 => Self::Discard,
+            4 => Self::Flush,
+            5 => Self::SecureErase,
+            _ => Self::Other,
+        }
+    }
+}
+
+/// Name of the hash map where the block IO programs aggregate completed requests
+/// while `CommonConfig::block_io_histograms` is set.
+pub const BLOCK_IO_HISTOGRAMS_MAP: &str = "BLOCK_IO_HISTOGRAMS";
+
+/// Buckets per log2 histogram. Slot `i` counts values in `[2^i, 2^(i+1))`; the
+/// last slot also takes everything above.
+pub const LOG2_SLOTS: u32 = 40;
+
+/// Log2 histogram slot for `value`. Branch-free, so the eBPF verifier sees no loop.
+pub const fn log2_slot(value: u64) -> u32 {
+    let mut v = value;
+    let mut slot = ((v > 0xFFFF_FFFF) as u32) << 5;
+    v >>= slot;
+    let mut shift = ((v > 0xFFFF) as u32) << 4;
+    v >>= shift;
+    slot |= shift;
+    shift = ((v > 0xFF) as u32) << 3;
+    v >>= shift;
+    slot |= shift;
+    shift = ((v > 0xF) as u32) << 2;
+    v >>= shift;
+    slot |= shift;
+    shift = ((v > 0x3) as u32) << 1;
+    v >>= shift;
+    slot |= shift;
+    slot |= (v >> 1) as u32;
+    if slot < LOG2_SLOTS { slot } else { LOG2_SLOTS - 1 }
+}
+
+/// What a block IO histogram measures.
+#[repr(u8)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BlockIoMeasure {
+    /// Nanoseconds from start to completion.
+    Latency = 0,
+    /// Bytes transferred by the request.
+    Size = 1,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BlockIoHistogramKey {
+    pub cgroup_id: u64,
+    pub dev: u32,
+    pub op: u8,      // Casts to BlockIoOp
+    pub measure: u8, // Casts to BlockIoMeasure
+    pub slot: u8,
+    pub _pad: u8,
+}
+
+#[cfg(feature = "user")]
+unsafe impl aya::Pod for BlockIoHistogramKey {}
+
+/// Samples that fell into one histogram slot, and their total.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Log2Bucket {
+    pub count: u64,
+    pub sum: u64,
+}
+
+#[cfg(feature = "user")]
+unsafe impl aya::Pod for Log2Bucket {}
+
+/// Name of the array map holding a `BlockTraceLayout` per `BlockIoProgram`.
+pub const BLOCK_IO_LAYOUTS_MAP: &str = "BLOCK_IO_LAYOUTS";
+
+/// The block IO programs, indexing `BLOCK_IO_LAYOUTS_MAP`.
+#[repr(u32)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BlockIoProgram {
+    Start = 0,
+    Done = 1,
+    Error = 2,
+}
+
+pub const BLOCK_IO_PROGRAMS: u32 = 3;
+
+/// Marks a `BlockTraceLayout` field the tracepoint does not have.
+pub const BLOCK_FIELD_MISSING: u16 = u16::MAX;
+
+/// Offsets of the fields a block IO program reads from its tracepoint's record.
+/// They differ between the tracepoints a program can attach to and between
+/// kernel versions, so user space takes them from the tracefs format.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BlockTraceLayout {
+    pub dev: u16,
+    pub sector: u16,
+    pub nr_sector: u16,
+    /// Missing on the completion tracepoints; derived from `nr_sector` instead.
+    pub bytes: u16,
+    /// Only on the completion tracepoints.
+    pub error: u16,
+    pub rwbs: u16,
+    /// Missing on the completion tracepoints.
+    pub comm: u16,
+    pub _pad: u16,
+}
+
+#[cfg(feature = "user")]
+unsafe impl aya::Pod for BlockTraceLayout {}
+
+/// Name of the LRU map holding the issuer of every request between its start
+/// and done tracepoints, keyed by `BlockRequestKey`.
+pub const BLOCK_IO_INFLIGHT_MAP: &str = "BLOCK_IO_INFLIGHT";
+
+/// Identifies a request while it is in flight.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct BlockRequestKey {
+    pub dev: u32,
+    pub _pad: u32,
+    pub sector: u64,
+}
+
+#[cfg(feature = "user")]
+unsafe impl aya::Pod for BlockRequestKey {}
+
+/// Name of the per-CPU hash map of `BlockQueueCounters`, keyed by device.
+pub const BLOCK_IO_QUEUES_MAP: &str = "BLOCK_IO_QUEUES";
+
+/// One CPU's share of a device's request counters. Summed across CPUs,
+/// `started - completed` is the number of requests in flight.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct BlockQueueCounters {
+    pub started: u64,
+    pub completed: u64,
+    /// Total latency of the completed requests, i.e. the area under the queue
+    /// depth curve in nanoseconds.
+    pub queued_ns: u64,
+}
+
+#[cfg(feature = "user")]
+unsafe impl aya::Pod for BlockQueueCounters {}
+
+/// Maximum number of values a custom probe can extract per hit.
+pub const MAX_CUSTOM_ARGS: usize = 6;
+/// Number of generic eBPF programs compiled in for each custom probe kind.
+pub const MAX_CUSTOM_PROBES_PER_KIND: u32 = 4;
+pub const CUSTOM_PROBE_KINDS: u32 = 5;
+/// Size of the spec table shared by every custom probe program.
+pub const MAX_CUSTOM_PROBES: u32 = CUSTOM_PROBE_KINDS * MAX_CUSTOM_PROBES_PER_KIND;
+
+#[repr(u32)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CustomProbeKind {
+    Tracepoint = 0,
+    Kprobe = 1,
+    Kretprobe = 2,
+    Uprobe = 3,
+    Uretprobe = 4,
+}
+
+/// Index into the spec table for the program occupying `slot` of `kind`.
+pub const fn custom_probe_id(kind: CustomProbeKind, slot: u32) -> u32 {
+    kind as u32 * MAX_CUSTOM_PROBES_PER_KIND + slot
+}
+
+#[repr(u32)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CustomArgSource {
+    Unused = 0,
+    /// `offset`/`size` bytes into the tracepoint record.
+    TracepointField = 1,
+    /// Function argument number `offset` (kprobes and uprobes).
+    FunctionArg = 2,
+    /// Return value of the probed function (return probes).
+    ReturnValue = 3,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+pub struct CustomArgSpec {
+    pub source: u32, // Casts to CustomArgSource
+    pub offset: u32,
+    pub size: u32,
+    pub _pad: u32,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+pub struct CustomProbeSpec {
+    pub nr_args: u32,
+    pub _pad: u32,
+    pub args: [CustomArgSpec; MAX_CUSTOM_ARGS],
+}
+
+#[cfg(feature = "user")]
+unsafe impl aya::Pod for CustomProbeSpec {}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct CustomProbeEvent {
+    pub metadata: EventMetadata,
+    pub probe_id: u32,
+    pub nr_args: u32,
+    pub args: [u64; MAX_CUSTOM_ARGS],
+}
+
+#[cfg(feature = "user")]
+unsafe impl aya::Pod for CustomProbeEvent {}
+
+/// Name of the per-CPU array where the eBPF programs count ringbuf outcomes,
+/// indexed by `emit_stat_index`.
+pub const EMIT_STATS_MAP: &str = "EMIT_STATS";
+
+/// Probe families that report ringbuf outcomes into `EMIT_STATS_MAP`.
+#[repr(u32)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EmitProbe {
+    BlockIo = 0,
+    NetworkLatency = 1,
+    GpuOpen = 2,
+    Custom = 3,
+    TcpRetransmit = 4,
+    TcpTraffic = 5,
+}
+
+impl EmitProbe {
+    pub const ALL: [Self; 6] = [
+        Self::BlockIo,
+        Self::NetworkLatency,
+        Self::GpuOpen,
+        Self::Custom,
+        Self::TcpRetransmit,
+        Self::TcpTraffic,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::BlockIo => "block_io",
+            Self::NetworkLatency => "network_latency",
+            Self::GpuOpen => "gpu_open",
+            Self::Custom => "custom",
+            Self::TcpRetransmit => "tcp_retransmit",
+            Self::TcpTraffic => "tcp_traffic",
+        }
+    }
+}
+
+/// What happened to an event on its way into a ringbuf.
+#[repr(u32)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EmitCounter {
+    /// Space was reserved in the ringbuf.
+    Reserved = 0,
+    /// The reserved event was handed to user space.
+    Submitted = 1,
+    /// The reserved event was dropped, either filtered out or after a failed read.
+    Discarded = 2,
+    /// The ringbuf was full, so the event was lost.
+    ReserveFailed = 3,
+    /// Reading kernel or user memory for the event failed.
+    ReadFailed = 4,
+}
+
+impl EmitCounter {
+    pub const ALL: [Self; 5] = [
+        Self::Reserved,
+        Self::Submitted,
+        Self::Discarded,
+        Self::ReserveFailed,
+        Self::ReadFailed,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Reserved => "reserved",
+            Self::Submitted => "submitted",
+            Self::Discarded => "discarded",
+            Self::ReserveFailed => "reserve_failed",
+            Self::ReadFailed => "read_failed",
+        }
+    }
+}
+
+pub const EMIT_STATS_ENTRIES: u32 = (EmitProbe::ALL.len() * EmitCounter::ALL.len()) as u32;
+
+pub const fn emit_stat_index(probe: EmitProbe, counter: EmitCounter) -> u32 {
+    probe as u32 * EmitCounter::ALL.len() as u32 + counter as u32
+}
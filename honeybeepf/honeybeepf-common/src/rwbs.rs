@@ -0,0 +1,235 @@
+//! Typed view of the rwbs strings reported by the block tracepoints, as written
+//! by the kernel's `blk_fill_rwbs`: an optional preflush `F`, the operation
+//! (`R`, `W`, `D`, `DE`, `F` or `N`), then the FUA `F`, readahead `A`, sync `S`
+//! and metadata `M` flags, in that order.
+
+use core::fmt;
+
+use serde::{Deserialize, Serialize, Serializer};
+
+use crate::{BlockIoOp, split_rwbs};
+
+/// A request flag spelled out in rwbs.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RwbsFlag {
+    /// Flush the device cache before the request (`REQ_PREFLUSH`).
+    Preflush,
+    /// Forced unit access: bypass the device cache (`REQ_FUA`).
+    Fua,
+    Readahead,
+    Sync,
+    /// Filesystem metadata (`REQ_META`).
+    Meta,
+}
+
+impl RwbsFlag {
+    pub const ALL: [Self; 5] = [
+        Self::Preflush,
+        Self::Fua,
+        Self::Readahead,
+        Self::Sync,
+        Self::Meta,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Preflush => "preflush",
+            Self::Fua => "fua",
+            Self::Readahead => "readahead",
+            Self::Sync => "sync",
+            Self::Meta => "meta",
+        }
+    }
+
+    const fn bit(self) -> u8 {
+        1 << self as u8
+    }
+}
+
+/// Set of `RwbsFlag`s. Serializes as a list of flag names.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct RwbsFlags(u8);
+
+impl RwbsFlags {
+    pub fn contains(&self, flag: RwbsFlag) -> bool {
+        self.0 & flag.bit() != 0
+    }
+
+    /// Whether every flag of `other` is also set here.
+    pub fn contains_all(&self, other: RwbsFlags) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub fn insert(&mut self, flag: RwbsFlag) {
+        self.0 |= flag.bit();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0 == 0
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = RwbsFlag> + '_ {
+        RwbsFlag::ALL.into_iter().filter(|&flag| self.contains(flag))
+    }
+}
+
+impl FromIterator<RwbsFlag> for RwbsFlags {
+    fn from_iter<I: IntoIterator<Item = RwbsFlag>>(flags: I) -> Self {
+        let mut set = Self::default();
+        for flag in flags {
+            set.insert(flag);
+        }
+        set
+    }
+}
+
+impl Serialize for RwbsFlags {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(self.iter())
+    }
+}
+
+/// Comma-separated flag names, e.g. `sync,fua`; empty when no flag is set.
+impl fmt::Display for RwbsFlags {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, flag) in self.iter().enumerate() {
+            if i > 0 {
+                f.write_str(",")?;
+            }
+            f.write_str(flag.as_str())?;
+        }
+        Ok(())
+    }
+}
+
+/// A decoded rwbs string.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Rwbs {
+    pub op: BlockIoOp,
+    pub flags: RwbsFlags,
+}
+
+impl Rwbs {
+    /// Decodes a possibly NUL-padded rwbs string. Letters the kernel does not
+    /// write at their position are ignored.
+    pub fn decode(rwbs: &[u8]) -> Self {
+        let len = rwbs.iter().position(|&b| b == 0).unwrap_or(rwbs.len());
+        let rwbs = &rwbs[..len];
+        let (preflush, op, flags_start) = split_rwbs(rwbs);
+
+        let mut flags = RwbsFlags::default();
+        if preflush {
+            flags.insert(RwbsFlag::Preflush);
+        }
+        for &letter in &rwbs[flags_start.min(len)..] {
+            match letter {
+                b'F' => flags.insert(RwbsFlag::Fua),
+                b'A' => flags.insert(RwbsFlag::Readahead),
+                b'S' => flags.insert(RwbsFlag::Sync),
+                b'M' => flags.insert(RwbsFlag::Meta),
+                _ => {}
+            }
+        }
+        Self { op, flags }
+    }
+}
+
+/// Writes the rwbs string the kernel would produce for this request.
+impl fmt::Display for Rwbs {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.flags.contains(RwbsFlag::Preflush) {
+            f.write_str("F")?;
+        }
+        f.write_str(match self.op {
+            BlockIoOp::Read => "R",
+            BlockIoOp::Write => "W",
+            BlockIoOp::Discard => "D",
+            BlockIoOp::SecureErase => "DE",
+            BlockIoOp::Flush => "F",
+            BlockIoOp::Other => "N",
+        })?;
+        for (flag, letter) in [
+            (RwbsFlag::Fua, "F"),
+            (RwbsFlag::Readahead, "A"),
+            (RwbsFlag::Sync, "S"),
+            (RwbsFlag::Meta, "M"),
+        ] {
+            if self.flags.contains(flag) {
+                f.write_str(letter)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use std::{string::ToString, vec::Vec};
+
+    use super::*;
+
+    const OPS: [BlockIoOp; 6] = [
+        BlockIoOp::Read,
+        BlockIoOp::Write,
+        BlockIoOp::Discard,
+        BlockIoOp::SecureErase,
+        BlockIoOp::Flush,
+        BlockIoOp::Other,
+    ];
+
+    /// Every combination of flags.
+    fn all_flag_sets() -> Vec<RwbsFlags> {
+        (0u8..1 << RwbsFlag::ALL.len())
+            .map(|bits| {
+                RwbsFlag::ALL
+                    .into_iter()
+                    .filter(|flag| bits & flag.bit() != 0)
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_decode_every_kernel_combination() {
+        for op in OPS {
+            for flags in all_flag_sets() {
+                // The block layer rejects FUA on a flush, so FF only ever means preflush
+                if op == BlockIoOp::Flush && flags.contains(RwbsFlag::Fua) {
+                    continue;
+                }
+                let rwbs = Rwbs { op, flags };
+                let text = rwbs.to_string();
+                assert!(text.len() <= 8, "{} does not fit the tracepoint", text);
+
+                let mut padded = [0u8; 8];
+                padded[..text.len()].copy_from_slice(text.as_bytes());
+                assert_eq!(Rwbs::decode(&padded), rwbs, "decoding {}", text);
+                assert_eq!(BlockIoOp::from_rwbs(&padded), op, "op of {}", text);
+            }
+        }
+    }
+
+    #[test]
+    fn test_decode_ambiguous_letters() {
+        // F alone is a flush; in front of an operation it is a preflush, after it FUA
+        assert_eq!(Rwbs::decode(b"F").op, BlockIoOp::Flush);
+        assert!(Rwbs::decode(b"F").flags.is_empty());
+        let rwbs = Rwbs::decode(b"FF");
+        assert_eq!(rwbs.op, BlockIoOp::Flush);
+        assert_eq!(rwbs.flags.iter().collect::<Vec<_>>(), [RwbsFlag::Preflush]);
+        let rwbs = Rwbs::decode(b"FS");
+        assert_eq!(rwbs.op, BlockIoOp::Flush);
+        assert_eq!(rwbs.flags.iter().collect::<Vec<_>>(), [RwbsFlag::Sync]);
+        let rwbs = Rwbs::decode(b"FWFS\0\0\0\0");
+        assert_eq!(rwbs.op, BlockIoOp::Write);
+        assert_eq!(rwbs.flags.to_string(), "preflush,fua,sync");
+
+        assert_eq!(Rwbs::decode(b"DE").op, BlockIoOp::SecureErase);
+        assert_eq!(Rwbs::decode(b"RA").flags.to_string(), "readahead");
+        assert_eq!(Rwbs::decode(b"").op, BlockIoOp::Other);
+        assert_eq!(Rwbs::decode(b"?").op, BlockIoOp::Other);
+    }
+}
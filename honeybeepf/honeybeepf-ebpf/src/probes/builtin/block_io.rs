@@ -1,41 +1,162 @@
 use aya_ebpf::{
-    EbpfContext,
+    bindings::BPF_NOEXIST,
     macros::{map, tracepoint},
-    maps::RingBuf,
+    maps::{Array, LruHashMap, PerCpuHashMap, RingBuf},
     programs::TracePointContext,
 };
-use honeybeepf_common::BlockIoEvent;
+use honeybeepf_common::{
+    log2_slot, BlockIoEvent, BlockIoHistogramKey, BlockIoMeasure, BlockIoOp, BlockIoProgram,
+    BlockQueueCounters, BlockRequestKey, BlockTraceLayout, EmitCounter, EmitProbe, Log2Bucket,
+    BLOCK_FIELD_MISSING, BLOCK_IO_PROGRAMS,
+};
 
-use crate::probes::{emit_event, HoneyBeeEvent};
+use crate::probes::{count_emit, emit_event, probe_enabled, EmitStatus, HoneyBeeEvent};
 
 const MAX_EVENT_SIZE: u32 = 1024 * 1024;
+/// Requests whose completion was never seen are evicted once this many are tracked.
+const MAX_INFLIGHT_REQUESTS: u32 = 16384;
+/// Histogram slots across every (cgroup, device, op, measure) seen in one interval.
+const MAX_HISTOGRAM_BUCKETS: u32 = 8192;
+const MAX_DEVICES: u32 = 1024;
 
 #[map]
 pub static BLOCK_IO_EVENTS: RingBuf = RingBuf::with_byte_size(MAX_EVENT_SIZE, 0);
 
+/// Where each program finds the request fields in its tracepoint's record,
+/// written by user space before attaching.
+#[map]
+static BLOCK_IO_LAYOUTS: Array<BlockTraceLayout> = Array::with_max_entries(BLOCK_IO_PROGRAMS, 0);
+
+/// Issuer and start time of every request between its start and done tracepoints.
+#[map]
+static BLOCK_IO_INFLIGHT: LruHashMap<BlockRequestKey, EventMetadata> =
+    LruHashMap::with_max_entries(MAX_INFLIGHT_REQUESTS, 0);
+
+/// Requests started and completed per device, summed across CPUs by user space
+/// to sample queue depth. Only requests tracked in `BLOCK_IO_INFLIGHT` count;
+/// user space writes off those evicted from it, whose completion goes unseen.
+#[map]
+static BLOCK_IO_QUEUES: PerCpuHashMap<u32, BlockQueueCounters> =
+    PerCpuHashMap::with_max_entries(MAX_DEVICES, 0);
+
+/// Completed requests in histogram mode, summed across CPUs and cleared by user
+/// space every interval.
+#[map]
+static BLOCK_IO_HISTOGRAMS: PerCpuHashMap<BlockIoHistogramKey, Log2Bucket> =
+    PerCpuHashMap::with_max_entries(MAX_HISTOGRAM_BUCKETS, 0);
+
+#[inline(always)]
+fn request_key(event: &BlockIoEvent) -> BlockRequestKey {
+    BlockRequestKey {
+        dev: event.dev,
+        _pad: 0,
+        sector: event.sector,
+    }
+}
+
 #[tracepoint]
 pub fn honeybeepf_block_io_start(ctx: TracePointContext) -> u32 {
-    emit_event::<BlockIoStart>(&BLOCK_IO_EVENTS, &ctx)
+    if !probe_enabled(|c| c.probe_block_io) {
+        return EmitStatus::Success as u32;
+    }
+    if probe_enabled(|c| c.block_io_histograms) {
+        // Only the in-flight entry is needed to time the request
+        let mut start = BlockIoStart(unsafe { core::mem::zeroed() });
+        return fill_or_count(&mut start, &ctx);
+    }
+    emit_event::<BlockIoStart>(EmitProbe::BlockIo, &BLOCK_IO_EVENTS, &ctx)
 }
 
 #[tracepoint]
 pub fn honeybeepf_block_io_done(ctx: TracePointContext) -> u32 {
-    emit_event::<BlockIoDone>(&BLOCK_IO_EVENTS, &ctx)
+    if !probe_enabled(|c| c.probe_block_io) {
+        return EmitStatus::Success as u32;
+    }
+    if probe_enabled(|c| c.block_io_histograms) {
+        let mut done = BlockIoDone(unsafe { core::mem::zeroed() });
+        let status = fill_or_count(&mut done, &ctx);
+        if status == EmitStatus::Success as u32 {
+            record_histograms(&done.0);
+        }
+        return status;
+    }
+    emit_event::<BlockIoDone>(EmitProbe::BlockIo, &BLOCK_IO_EVENTS, &ctx)
+}
+
+/// Attached to `block_rq_error`, or to `block_rq_complete` on kernels without it;
+/// both share the completion layout and only failed requests are reported.
+#[tracepoint]
+pub fn honeybeepf_block_io_error(ctx: TracePointContext) -> u32 {
+    if !probe_enabled(|c| c.probe_block_io) {
+        return EmitStatus::Success as u32;
+    }
+    // Check the status before reserving, block_rq_complete fires for every request
+    let error: i32 = match BLOCK_IO_LAYOUTS
+        .get(BlockIoProgram::Error as u32)
+        .ok_or(1u32)
+        .and_then(|layout| read_field(&ctx, layout.error))
+    {
+        Ok(error) => error,
+        Err(_) => {
+            count_emit(EmitProbe::BlockIo, EmitCounter::ReadFailed);
+            return EmitStatus::Failure as u32;
+        }
+    };
+    if error == 0 {
+        return EmitStatus::Success as u32;
+    }
+    // Errors are rare enough to always go through the ringbuf, also in histogram mode
+    emit_event::<BlockIoError>(EmitProbe::BlockIo, &BLOCK_IO_EVENTS, &ctx)
+}
+
+#[inline(always)]
+fn fill_or_count<T: HoneyBeeEvent>(event: &mut T, ctx: &TracePointContext) -> u32 {
+    match event.fill(ctx) {
+        Ok(()) => EmitStatus::Success as u32,
+        Err(e) => {
+            count_emit(EmitProbe::BlockIo, EmitCounter::ReadFailed);
+            e
+        }
+    }
+}
+
+#[inline(always)]
+fn record_histograms(event: &BlockIoEvent) {
+    let op = BlockIoOp::from_rwbs(&event.rwbs);
+    if event.latency_ns != 0 {
+        observe(event, op, BlockIoMeasure::Latency, event.latency_ns);
+    }
+    observe(event, op, BlockIoMeasure::Size, event.bytes as u64);
 }
 
-#[repr(C)]
-struct BlockIoTrace {
-    common_type: u16,
-    common_flags: u8,
-    common_preempt_count: u8,
-    common_pid: i32,
-    dev: u32,
-    sector: u64,
-    nr_sector: u32,
-    bytes: u32,
-    rwbs: [u8; 8],
-    comm: [u8; 16],
-    cmd: [u8; 4], // Optional, often present
+#[inline(always)]
+fn observe(event: &BlockIoEvent, op: BlockIoOp, measure: BlockIoMeasure, value: u64) {
+    let key = BlockIoHistogramKey {
+        cgroup_id: event.metadata.cgroup_id,
+        dev: event.dev,
+        op: op as u8,
+        measure: measure as u8,
+        slot: log2_slot(value) as u8,
+        _pad: 0,
+    };
+    let Some(bucket) = per_cpu_entry(&BLOCK_IO_HISTOGRAMS, &key) else {
+        return;
+    };
+    unsafe {
+        (*bucket).count += 1;
+        (*bucket).sum += value;
+    }
+}
+
+/// This CPU's value for `key`, created zeroed on first use. No other CPU writes
+/// it, so callers update it in place.
+#[inline(always)]
+fn per_cpu_entry<K, V: Default>(map: &PerCpuHashMap<K, V>, key: &K) -> Option<*mut V> {
+    if let Some(value) = map.get_ptr_mut(key) {
+        return Some(value);
+    }
+    let _ = map.insert(key, &V::default(), BPF_NOEXIST as u64);
+    map.get_ptr_mut(key)
 }
 
 use honeybeepf_common::{BlockIoEventType, EventMetadata};
@@ -44,11 +165,23 @@ use honeybeepf_common::{BlockIoEventType, EventMetadata};
 pub struct BlockIoStart(BlockIoEvent);
 
 impl HoneyBeeEvent for BlockIoStart {
-    fn metadata(&mut self) -> &mut EventMetadata { self.0.metadata() }
+    fn metadata(&mut self) -> &mut EventMetadata { &mut self.0.metadata }
 
     fn fill(&mut self, ctx: &TracePointContext) -> Result<(), u32> {
-        self.0.fill(ctx)?;
+        self.init_base();
+        read_request(&mut self.0, ctx, BlockIoProgram::Start)?;
         self.0.event_type = BlockIoEventType::Start as u8;
+
+        // Remember who issued the request; completion usually runs in interrupt context.
+        // A requeued request is issued again under the same key and only counts once.
+        let key = request_key(&self.0);
+        let requeued = unsafe { BLOCK_IO_INFLIGHT.get(&key) }.is_some();
+        if BLOCK_IO_INFLIGHT.insert(&key, &self.0.metadata, 0).is_ok()
+            && !requeued
+            && let Some(queue) = per_cpu_entry(&BLOCK_IO_QUEUES, &self.0.dev)
+        {
+            unsafe { (*queue).started += 1 };
+        }
         Ok(())
     }
 }
@@ -57,56 +190,97 @@ impl HoneyBeeEvent for BlockIoStart {
 pub struct BlockIoDone(BlockIoEvent);
 
 impl HoneyBeeEvent for BlockIoDone {
-    fn metadata(&mut self) -> &mut EventMetadata { self.0.metadata() }
+    fn metadata(&mut self) -> &mut EventMetadata { &mut self.0.metadata }
 
     fn fill(&mut self, ctx: &TracePointContext) -> Result<(), u32> {
-        self.0.fill(ctx)?;
+        self.init_base();
+        read_request(&mut self.0, ctx, BlockIoProgram::Done)?;
         self.0.event_type = BlockIoEventType::Done as u8;
+
+        let key = request_key(&self.0);
+        if let Some(start) = unsafe { BLOCK_IO_INFLIGHT.get(&key) } {
+            let start = *start;
+            if BLOCK_IO_INFLIGHT.remove(&key).is_err() {
+                // Another CPU completed the same request key first
+                return Ok(());
+            }
+            self.0.latency_ns = self.0.metadata.timestamp.saturating_sub(start.timestamp);
+            if let Some(queue) = per_cpu_entry(&BLOCK_IO_QUEUES, &self.0.dev) {
+                unsafe {
+                    (*queue).completed += 1;
+                    (*queue).queued_ns += self.0.latency_ns;
+                }
+            }
+            // Attribute the completion to the task that issued the request
+            self.0.metadata.pid = start.pid;
+            self.0.metadata.cgroup_id = start.cgroup_id;
+        }
         Ok(())
     }
 }
 
-impl HoneyBeeEvent for BlockIoEvent {
-    fn metadata(&mut self) -> &mut EventMetadata { &mut self.metadata }
+#[repr(transparent)]
+pub struct BlockIoError(BlockIoEvent);
+
+impl HoneyBeeEvent for BlockIoError {
+    fn metadata(&mut self) -> &mut EventMetadata { &mut self.0.metadata }
 
     fn fill(&mut self, ctx: &TracePointContext) -> Result<(), u32> {
         self.init_base();
-        
-        let header_ptr = ctx.as_ptr() as *const BlockIoTrace;
-
-        self.dev = unsafe {
-            aya_ebpf::helpers::bpf_probe_read_kernel(&((*header_ptr).dev) as *const u32)
-                .map_err(|_| 1u32)?
-        };
-
-        self.sector = unsafe {
-            aya_ebpf::helpers::bpf_probe_read_kernel(&((*header_ptr).sector) as *const u64)
-                .map_err(|_| 1u32)?
-        };
-
-        self.nr_sector = unsafe {
-            aya_ebpf::helpers::bpf_probe_read_kernel(&((*header_ptr).nr_sector) as *const u32)
-                .map_err(|_| 1u32)?
-        };
-
-        self.bytes = unsafe {
-            aya_ebpf::helpers::bpf_probe_read_kernel(&((*header_ptr).bytes) as *const u32)
-                .map_err(|_| 1u32)?
-        };
-
-        self.rwbs = unsafe {
-            aya_ebpf::helpers::bpf_probe_read_kernel(&((*header_ptr).rwbs) as *const [u8; 8])
-                .map_err(|_| 1u32)?
-        };
-
-        self.comm = unsafe {
-            aya_ebpf::helpers::bpf_probe_read_kernel(&((*header_ptr).comm) as *const [u8; 16])
-                .map_err(|_| 1u32)?
-        };
-        
-        // Event type is set by the caller
-        self.event_type = BlockIoEventType::Unknown as u8;
-        
+        let event = &mut self.0;
+        read_request(event, ctx, BlockIoProgram::Error)?;
+        event.event_type = BlockIoEventType::Error as u8;
+
+        // The request is still in flight until it is done, which is reported after
+        // the error; keep the entry so the completion is timed as well. When both
+        // programs follow block_rq_complete the done one may run first, and the
+        // error stays with the completing task.
+        if let Some(start) = unsafe { BLOCK_IO_INFLIGHT.get(&request_key(event)) } {
+            event.latency_ns = event.metadata.timestamp.saturating_sub(start.timestamp);
+            event.metadata.pid = start.pid;
+            event.metadata.cgroup_id = start.cgroup_id;
+        }
         Ok(())
     }
 }
+
+/// Reads the request fields of `program`'s tracepoint record, at the offsets user
+/// space published. Fields the tracepoint lacks are derived or left empty.
+#[inline(always)]
+fn read_request(
+    event: &mut BlockIoEvent,
+    ctx: &TracePointContext,
+    program: BlockIoProgram,
+) -> Result<(), u32> {
+    let layout = BLOCK_IO_LAYOUTS.get(program as u32).ok_or(1u32)?;
+
+    event.dev = read_field(ctx, layout.dev)?;
+    event.sector = read_field(ctx, layout.sector)?;
+    event.nr_sector = read_field(ctx, layout.nr_sector)?;
+    event.rwbs = read_field(ctx, layout.rwbs)?;
+    event.bytes = if layout.bytes != BLOCK_FIELD_MISSING {
+        read_field(ctx, layout.bytes)?
+    } else {
+        event.nr_sector.saturating_mul(512)
+    };
+    event.comm = if layout.comm != BLOCK_FIELD_MISSING {
+        read_field(ctx, layout.comm)?
+    } else {
+        [0; 16]
+    };
+    event.error = if layout.error != BLOCK_FIELD_MISSING {
+        read_field(ctx, layout.error)?
+    } else {
+        0
+    };
+
+    // Event type and latency are set by the caller
+    event.event_type = BlockIoEventType::Unknown as u8;
+    event.latency_ns = 0;
+    Ok(())
+}
+
+#[inline(always)]
+fn read_field<T>(ctx: &TracePointContext, offset: u16) -> Result<T, u32> {
+    unsafe { ctx.read_at::<T>(offset as usize).map_err(|_| 1u32) }
+}
@@ -5,9 +5,9 @@ use aya_ebpf::{
     maps::RingBuf,
     programs::TracePointContext,
 };
-use honeybeepf_common::{EventMetadata, GpuOpenEvent};
+use honeybeepf_common::{EmitCounter, EmitProbe, EventMetadata, GpuOpenEvent};
 
-use crate::probes::HoneyBeeEvent;
+use crate::probes::{count_emit, probe_enabled, HoneyBeeEvent};
 use super::gpu_utils::get_gpu_index;
 
 const MAX_EVENT_SIZE: u32 = 1024 * 1024;
@@ -87,29 +87,40 @@ impl HoneyBeeEvent for GpuOpenEvent {
 }
 
 fn emit_gpu_event(ringbuf: &RingBuf, ctx: &TracePointContext) -> u32 {
+    const PROBE: EmitProbe = EmitProbe::GpuOpen;
+
     if let Some(mut slot) = ringbuf.reserve::<GpuOpenEvent>(0) {
+        count_emit(PROBE, EmitCounter::Reserved);
         let event = unsafe { &mut *slot.as_mut_ptr() };
 
         match event.fill(ctx) {
             Ok(_) => {
                 slot.submit(0);
+                count_emit(PROBE, EmitCounter::Submitted);
                 EmitGpuStatus::Success as u32
             }
             Err(e) if e == EmitGpuStatus::NotGpuDevice as u32 => {
                 slot.discard(0);
+                count_emit(PROBE, EmitCounter::Discarded);
                 EmitGpuStatus::Success as u32  // Silent discard for non-GPU devices
             }
             Err(e) => {
                 slot.discard(0);
+                count_emit(PROBE, EmitCounter::ReadFailed);
+                count_emit(PROBE, EmitCounter::Discarded);
                 e
             }
         }
     } else {
+        count_emit(PROBE, EmitCounter::ReserveFailed);
         EmitGpuStatus::Failure as u32
     }
 }
 
 #[tracepoint]
 pub fn honeybeepf_gpu_open_enter(ctx: TracePointContext) -> u32 {
+    if !probe_enabled(|c| c.probe_gpu_open) {
+        return EmitGpuStatus::Success as u32;
+    }
     emit_gpu_event(&GPU_OPEN_EVENTS, &ctx)
 }
@@ -2,3 +2,10 @@ pub mod network;
 pub mod block_io;
 pub mod gpu_open;
 pub mod gpu_utils;
+pub mod tcp;
+
+/// Fields of `sock:inet_sock_set_state`, which have kept their place since the
+/// tracepoint was added.
+const SKADDR_OFFSET: usize = 8;
+const OLDSTATE_OFFSET: usize = 16;
+const NEWSTATE_OFFSET: usize = 20;
@@ -1,13 +1,24 @@
 use aya_ebpf::{
     macros::{map, tracepoint},
-    maps::RingBuf,
+    maps::{LruHashMap, RingBuf},
     programs::TracePointContext,
-    helpers::{bpf_probe_read_user},
+    helpers::{bpf_get_current_pid_tgid, bpf_ktime_get_ns, bpf_probe_read_user},
 };
-use honeybeepf_common::ConnectionEvent;
+use honeybeepf_common::{ConnectionEvent, EmitCounter, EmitProbe, TcpProgram};
 
 const AF_INET: u16 = 2;
+const AF_INET6: u16 = 10;
 const MAX_EVENT_SIZE: u32 = 1024 * 1024;
+/// Connects tracked at once, across threads in the syscall and sockets in the handshake.
+const MAX_PENDING_CONNECTS: u32 = 16384;
+
+const ETIMEDOUT: i32 = 110;
+const ECONNREFUSED: i32 = 111;
+const EINPROGRESS: i64 = 115;
+
+const TCP_ESTABLISHED: i32 = 1;
+const TCP_SYN_SENT: i32 = 2;
+const TCP_CLOSE: i32 = 7;
 
 #[repr(C)]
 struct SockaddrIn {
@@ -17,14 +28,193 @@ struct SockaddrIn {
     sin_zero: [u8; 8],
 }
 
-use crate::probes::{emit_event, HoneyBeeEvent};
+#[repr(C)]
+struct SockaddrIn6 {
+    sin6_family: u16,
+    sin6_port: u16,
+    sin6_flowinfo: u32,
+    sin6_addr: [u8; 16],
+    sin6_scope_id: u32,
+}
+
+/// A connect whose outcome is not known yet.
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct PendingConnect {
+    event: ConnectionEvent,
+    /// Socket the connect put into SYN_SENT; 0 for other protocols.
+    sk: u64,
+    /// When the handshake finished, with `event.error` holding its outcome.
+    finished_at: u64,
+    /// Set once the syscall returned EINPROGRESS and left the outcome to the socket.
+    in_progress: u64,
+}
+
+use crate::probes::{count_emit, init_metadata, probe_enabled, EmitStatus, HoneyBeeEvent};
+use crate::probes::builtin::{NEWSTATE_OFFSET, OLDSTATE_OFFSET, SKADDR_OFFSET};
+use crate::probes::builtin::tcp::TCP_TRACE_LAYOUTS;
 
 #[map]
 static NETWORK_EVENTS: RingBuf = RingBuf::with_byte_size(MAX_EVENT_SIZE, 0);
 
+/// Connects between syscall entry and exit, by thread.
+#[map]
+static CONNECT_CALLS: LruHashMap<u64, PendingConnect> =
+    LruHashMap::with_max_entries(MAX_PENDING_CONNECTS, 0);
+
+/// TCP connects from SYN_SENT until the handshake finishes and the syscall has
+/// returned, by socket.
+#[map]
+static CONNECT_SOCKETS: LruHashMap<u64, PendingConnect> =
+    LruHashMap::with_max_entries(MAX_PENDING_CONNECTS, 0);
+
 #[tracepoint]
 pub fn honeybeepf(ctx: TracePointContext) -> u32 {
-    emit_event::<ConnectionEvent>(&NETWORK_EVENTS, &ctx)
+    if !probe_enabled(|c| c.probe_network_latency) {
+        return EmitStatus::Success as u32;
+    }
+    let mut pending: PendingConnect = unsafe { core::mem::zeroed() };
+    if let Err(e) = pending.event.fill(&ctx) {
+        count_emit(EmitProbe::NetworkLatency, EmitCounter::ReadFailed);
+        return e;
+    }
+    let thread = bpf_get_current_pid_tgid();
+    let _ = CONNECT_CALLS.insert(&thread, &pending, 0);
+    EmitStatus::Success as u32
+}
+
+#[tracepoint]
+pub fn honeybeepf_connect_exit(ctx: TracePointContext) -> u32 {
+    let thread = bpf_get_current_pid_tgid();
+    let Some(call) = (unsafe { CONNECT_CALLS.get(&thread) }) else {
+        return EmitStatus::Success as u32;
+    };
+    let call = *call;
+    let _ = CONNECT_CALLS.remove(&thread);
+
+    let ret: i64 = match unsafe { ctx.read_at(16) } {
+        Ok(ret) => ret,
+        Err(_) => {
+            count_emit(EmitProbe::NetworkLatency, EmitCounter::ReadFailed);
+            return EmitStatus::Failure as u32;
+        }
+    };
+    let now = unsafe { bpf_ktime_get_ns() };
+
+    if call.sk != 0
+        && let Some(socket) = CONNECT_SOCKETS.get_ptr_mut(&call.sk)
+    {
+        let socket = unsafe { &mut *socket };
+        if ret == -EINPROGRESS {
+            // Loopback handshakes can finish before the syscall returns
+            if socket.finished_at == 0 {
+                socket.in_progress = 1;
+                return EmitStatus::Success as u32;
+            }
+            let finished_at = socket.finished_at;
+            let mut event = socket.event;
+            let _ = CONNECT_SOCKETS.remove(&call.sk);
+            return submit(&mut event, finished_at);
+        }
+        let _ = CONNECT_SOCKETS.remove(&call.sk);
+    }
+
+    // Blocking connects, other protocols, and non-blocking ones whose socket
+    // could not be followed, which are reported as EINPROGRESS
+    let mut event = call.event;
+    event.error = ret as i32;
+    submit(&mut event, now)
+}
+
+#[tracepoint]
+pub fn honeybeepf_connect_state(ctx: TracePointContext) -> u32 {
+    let read = || -> Result<(u64, i32, i32), i64> {
+        unsafe {
+            Ok((
+                ctx.read_at(SKADDR_OFFSET)?,
+                ctx.read_at(OLDSTATE_OFFSET)?,
+                ctx.read_at(NEWSTATE_OFFSET)?,
+            ))
+        }
+    };
+    let Ok((sk, oldstate, newstate)) = read() else {
+        count_emit(EmitProbe::NetworkLatency, EmitCounter::ReadFailed);
+        return EmitStatus::Failure as u32;
+    };
+
+    if oldstate == TCP_CLOSE && newstate == TCP_SYN_SENT {
+        // Runs inside the connect() syscall of the thread that owns the socket
+        let thread = bpf_get_current_pid_tgid();
+        if let Some(call) = CONNECT_CALLS.get_ptr_mut(&thread) {
+            let call = unsafe { &mut *call };
+            call.sk = sk;
+            let _ = CONNECT_SOCKETS.insert(&sk, call, 0);
+        }
+        return EmitStatus::Success as u32;
+    }
+
+    if oldstate != TCP_SYN_SENT || (newstate != TCP_ESTABLISHED && newstate != TCP_CLOSE) {
+        return EmitStatus::Success as u32;
+    }
+    let Some(socket) = CONNECT_SOCKETS.get_ptr_mut(&sk) else {
+        return EmitStatus::Success as u32;
+    };
+    let socket = unsafe { &mut *socket };
+    let now = unsafe { bpf_ktime_get_ns() };
+    socket.finished_at = now;
+    socket.event.error = if newstate == TCP_ESTABLISHED {
+        0
+    } else if socket.event.error == -ECONNREFUSED {
+        -ECONNREFUSED
+    } else {
+        // Unanswered SYNs, unreachable hosts and connects the caller gave up on
+        -ETIMEDOUT
+    };
+
+    if socket.in_progress == 0 {
+        // The syscall is still running and reports the outcome when it returns
+        return EmitStatus::Success as u32;
+    }
+    let mut event = socket.event;
+    let _ = CONNECT_SOCKETS.remove(&sk);
+    submit(&mut event, now)
+}
+
+#[tracepoint]
+pub fn honeybeepf_connect_reset(ctx: TracePointContext) -> u32 {
+    let Some(layout) = TCP_TRACE_LAYOUTS.get(TcpProgram::ReceiveReset as u32) else {
+        count_emit(EmitProbe::NetworkLatency, EmitCounter::ReadFailed);
+        return EmitStatus::Failure as u32;
+    };
+    let Ok(sk) = (unsafe { ctx.read_at::<u64>(layout.skaddr as usize) }) else {
+        count_emit(EmitProbe::NetworkLatency, EmitCounter::ReadFailed);
+        return EmitStatus::Failure as u32;
+    };
+    // A reset answering our SYN: the move to TCP_CLOSE that follows is a refusal
+    if let Some(socket) = CONNECT_SOCKETS.get_ptr_mut(&sk) {
+        let socket = unsafe { &mut *socket };
+        if socket.finished_at == 0 {
+            socket.event.error = -ECONNREFUSED;
+        }
+    }
+    EmitStatus::Success as u32
+}
+
+/// Reports a connect that finished at `finished_at`.
+#[inline(always)]
+fn submit(event: &mut ConnectionEvent, finished_at: u64) -> u32 {
+    event.latency_ns = finished_at.saturating_sub(event.metadata.timestamp);
+    match NETWORK_EVENTS.output(event, 0) {
+        Ok(()) => {
+            count_emit(EmitProbe::NetworkLatency, EmitCounter::Reserved);
+            count_emit(EmitProbe::NetworkLatency, EmitCounter::Submitted);
+            EmitStatus::Success as u32
+        }
+        Err(_) => {
+            count_emit(EmitProbe::NetworkLatency, EmitCounter::ReserveFailed);
+            EmitStatus::Failure as u32
+        }
+    }
 }
 
 use honeybeepf_common::EventMetadata;
@@ -33,7 +223,7 @@ impl HoneyBeeEvent for ConnectionEvent {
     fn metadata(&mut self) -> &mut EventMetadata { &mut self.metadata }
 
     fn fill(&mut self, ctx: &TracePointContext) -> Result<(), u32> {
-        self.init_base();
+        init_metadata(&mut self.metadata);
 
         let sockaddr_ptr: u64 = unsafe {
             ctx.read_at(24).map_err(|_| 1u32)?
@@ -49,8 +239,11 @@ impl HoneyBeeEvent for ConnectionEvent {
         };
 
         self.address_family = sa_family;
-        self.dest_addr = 0;
+        self.dest_addr = [0; 16];
+        self.scope_id = 0;
         self.dest_port = 0;
+        self.latency_ns = 0;
+        self.error = 0;
 
         if sa_family == AF_INET {
             let sockaddr = unsafe {
@@ -58,7 +251,20 @@ impl HoneyBeeEvent for ConnectionEvent {
                     .map_err(|_| 1u32)?
             };
             self.dest_port = sockaddr.sin_port;
-            self.dest_addr = sockaddr.sin_addr;
+            // sin_addr was read as stored, so its native bytes are in network order
+            let addr = sockaddr.sin_addr.to_ne_bytes();
+            self.dest_addr[0] = addr[0];
+            self.dest_addr[1] = addr[1];
+            self.dest_addr[2] = addr[2];
+            self.dest_addr[3] = addr[3];
+        } else if sa_family == AF_INET6 {
+            let sockaddr = unsafe {
+                bpf_probe_read_user(sockaddr_ptr as *const SockaddrIn6)
+                    .map_err(|_| 1u32)?
+            };
+            self.dest_port = sockaddr.sin6_port;
+            self.dest_addr = sockaddr.sin6_addr;
+            self.scope_id = sockaddr.sin6_scope_id;
         }
 
         Ok(())
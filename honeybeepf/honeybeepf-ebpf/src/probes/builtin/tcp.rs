@@ -0,0 +1,445 @@
+use core::intrinsics::{AtomicOrdering, atomic_xadd};
+
+use aya_ebpf::{
+    helpers::{bpf_get_current_pid_tgid, bpf_ktime_get_ns},
+    macros::{kprobe, kretprobe, map, tracepoint},
+    maps::{Array, LruHashMap, RingBuf},
+    programs::{ProbeContext, RetProbeContext, TracePointContext},
+};
+use honeybeepf_common::{
+    EmitCounter, EmitProbe, EventMetadata, TcpConnection, TcpEvent, TcpEventType, TcpProgram,
+    TcpTraceLayout, TCP_FIELD_MISSING, TCP_PROGRAMS,
+};
+
+use crate::probes::{common_config, count_emit, init_metadata, probe_enabled, EmitStatus};
+use crate::probes::builtin::{NEWSTATE_OFFSET, OLDSTATE_OFFSET, SKADDR_OFFSET};
+
+const AF_INET: u16 = 2;
+const MAX_EVENT_SIZE: u32 = 1024 * 1024;
+/// Sockets whose owner is remembered; the least recently used are forgotten.
+const MAX_SOCKETS: u32 = 65536;
+/// Open connections whose bytes are counted; connections whose close went unseen
+/// are evicted once this many are tracked.
+const MAX_CONNECTIONS: u32 = 65536;
+const MAX_PENDING_SENDS: u32 = 16384;
+const NS_PER_SECOND: u64 = 1_000_000_000;
+
+const TCP_ESTABLISHED: i32 = 1;
+const TCP_SYN_SENT: i32 = 2;
+const TCP_SYN_RECV: i32 = 3;
+const TCP_CLOSE: i32 = 7;
+
+/// Offsets within `sockaddr_in` and `sockaddr_in6`.
+const SOCKADDR_PORT: u16 = 2;
+const SOCKADDR_IN_ADDR: u16 = 4;
+const SOCKADDR_IN6_ADDR: u16 = 8;
+
+/// Retransmits of a socket since its latest retransmit event.
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct RetransmitWindow {
+    /// When the event was sent; later retransmits are folded into the next event
+    /// until a probe interval has passed.
+    started: u64,
+    throttled: u64,
+}
+
+#[map]
+static TCP_EVENTS: RingBuf = RingBuf::with_byte_size(MAX_EVENT_SIZE, 0);
+
+/// Where each program finds the socket fields in its tracepoint's record,
+/// written by user space before attaching.
+#[map]
+pub(crate) static TCP_TRACE_LAYOUTS: Array<TcpTraceLayout> = Array::with_max_entries(TCP_PROGRAMS, 0);
+
+#[map]
+static TCP_TRAFFIC_EVENTS: RingBuf = RingBuf::with_byte_size(MAX_EVENT_SIZE, 0);
+
+/// Bytes of every connection established while byte accounting is on.
+#[map]
+static TCP_CONNECTIONS: LruHashMap<u64, TcpConnection> =
+    LruHashMap::with_max_entries(MAX_CONNECTIONS, 0);
+
+/// Socket each thread is inside tcp_sendmsg() for.
+#[map]
+static TCP_SENDS: LruHashMap<u64, u64> = LruHashMap::with_max_entries(MAX_PENDING_SENDS, 0);
+
+/// Task that connected or accepted each socket, for events raised outside of it.
+#[map]
+static TCP_SOCKET_OWNERS: LruHashMap<u64, EventMetadata> =
+    LruHashMap::with_max_entries(MAX_SOCKETS, 0);
+
+/// Sockets that retransmitted, so a retransmit storm costs one event per socket
+/// and probe interval.
+#[map]
+static TCP_RETRANSMIT_WINDOWS: LruHashMap<u64, RetransmitWindow> =
+    LruHashMap::with_max_entries(MAX_SOCKETS, 0);
+
+#[tracepoint]
+pub fn honeybeepf_tcp_retransmit(ctx: TracePointContext) -> u32 {
+    emit(&ctx, TcpProgram::Retransmit, TcpEventType::Retransmit)
+}
+
+#[tracepoint]
+pub fn honeybeepf_tcp_send_reset(ctx: TracePointContext) -> u32 {
+    emit(&ctx, TcpProgram::SendReset, TcpEventType::ResetSent)
+}
+
+#[tracepoint]
+pub fn honeybeepf_tcp_receive_reset(ctx: TracePointContext) -> u32 {
+    emit(&ctx, TcpProgram::ReceiveReset, TcpEventType::ResetReceived)
+}
+
+/// Keeps `TCP_SOCKET_OWNERS` up to date from socket state changes, and reports
+/// the retransmits still throttled on sockets that close.
+#[tracepoint]
+pub fn honeybeepf_tcp_owner(ctx: TracePointContext) -> u32 {
+    if !probe_enabled(|c| c.probe_tcp_retransmit) {
+        return EmitStatus::Success as u32;
+    }
+    let read = || -> Result<(u64, i32, i32), i64> {
+        unsafe {
+            Ok((
+                ctx.read_at(SKADDR_OFFSET)?,
+                ctx.read_at(OLDSTATE_OFFSET)?,
+                ctx.read_at(NEWSTATE_OFFSET)?,
+            ))
+        }
+    };
+    let Ok((sk, oldstate, newstate)) = read() else {
+        count_emit(EmitProbe::TcpRetransmit, EmitCounter::ReadFailed);
+        return EmitStatus::Failure as u32;
+    };
+
+    if oldstate == TCP_CLOSE && newstate == TCP_SYN_SENT {
+        // Runs inside the connect() of the task that owns the socket
+        let mut owner: EventMetadata = unsafe { core::mem::zeroed() };
+        init_metadata(&mut owner);
+        let _ = TCP_SOCKET_OWNERS.insert(&sk, &owner, 0);
+    } else if newstate == TCP_SYN_RECV {
+        // Sockets for incoming connections are created in softirq context,
+        // where the owner is unknown until accept() returns them; forget
+        // whoever used the address before
+        let _ = TCP_SOCKET_OWNERS.remove(&sk);
+    } else if newstate == TCP_CLOSE
+        && let Some(window) = unsafe { TCP_RETRANSMIT_WINDOWS.get(&sk) }
+    {
+        let throttled = window.throttled;
+        let _ = TCP_RETRANSMIT_WINDOWS.remove(&sk);
+        if throttled > 0 {
+            let Some(layout) = TCP_TRACE_LAYOUTS.get(TcpProgram::SetState as u32) else {
+                count_emit(EmitProbe::TcpRetransmit, EmitCounter::ReadFailed);
+                return EmitStatus::Failure as u32;
+            };
+            return report(&ctx, layout, sk, TcpEventType::Retransmit, throttled as u32);
+        }
+    }
+    EmitStatus::Success as u32
+}
+
+/// Records the task accepting an incoming connection as its owner.
+#[kretprobe]
+pub fn honeybeepf_tcp_accept(ctx: RetProbeContext) -> u32 {
+    if !probe_enabled(|c| c.probe_tcp_retransmit) {
+        return EmitStatus::Success as u32;
+    }
+    // inet_csk_accept() returns the new socket, or NULL on error
+    let sk = ctx.ret::<u64>().unwrap_or(0);
+    if sk != 0 {
+        let mut owner: EventMetadata = unsafe { core::mem::zeroed() };
+        init_metadata(&mut owner);
+        let _ = TCP_SOCKET_OWNERS.insert(&sk, &owner, 0);
+    }
+    EmitStatus::Success as u32
+}
+
+/// Starts counting the bytes of established connections, and reports them once
+/// the connection closes.
+#[tracepoint]
+pub fn honeybeepf_tcp_traffic_state(ctx: TracePointContext) -> u32 {
+    if !probe_enabled(|c| c.probe_tcp_traffic) {
+        return EmitStatus::Success as u32;
+    }
+    let read = || -> Result<(u64, i32), i64> {
+        unsafe { Ok((ctx.read_at(SKADDR_OFFSET)?, ctx.read_at(NEWSTATE_OFFSET)?)) }
+    };
+    let Ok((sk, newstate)) = read() else {
+        count_emit(EmitProbe::TcpTraffic, EmitCounter::ReadFailed);
+        return EmitStatus::Failure as u32;
+    };
+
+    if newstate == TCP_ESTABLISHED {
+        let mut socket: TcpEvent = unsafe { core::mem::zeroed() };
+        let read = TCP_TRACE_LAYOUTS
+            .get(TcpProgram::SetState as u32)
+            .ok_or(1u32)
+            .and_then(|layout| read_socket(&mut socket, &ctx, layout));
+        if let Err(e) = read {
+            count_emit(EmitProbe::TcpTraffic, EmitCounter::ReadFailed);
+            return e;
+        }
+        // The owner is filled in by whoever first sends or reads; handshakes
+        // complete in softirq context
+        let mut connection: TcpConnection = unsafe { core::mem::zeroed() };
+        connection.metadata.timestamp = unsafe { bpf_ktime_get_ns() };
+        connection.sk = sk;
+        connection.saddr = socket.saddr;
+        connection.daddr = socket.daddr;
+        connection.sport = socket.sport;
+        connection.dport = socket.dport;
+        connection.address_family = socket.address_family;
+        let _ = TCP_CONNECTIONS.insert(&sk, &connection, 0);
+        return EmitStatus::Success as u32;
+    }
+
+    if newstate != TCP_CLOSE {
+        return EmitStatus::Success as u32;
+    }
+    let Some(connection) = (unsafe { TCP_CONNECTIONS.get(&sk) }) else {
+        return EmitStatus::Success as u32;
+    };
+    let connection = *connection;
+    let _ = TCP_CONNECTIONS.remove(&sk);
+    if connection.sent_bytes == 0 && connection.received_bytes == 0 {
+        return EmitStatus::Success as u32;
+    }
+    match TCP_TRAFFIC_EVENTS.output(&connection, 0) {
+        Ok(()) => {
+            count_emit(EmitProbe::TcpTraffic, EmitCounter::Reserved);
+            count_emit(EmitProbe::TcpTraffic, EmitCounter::Submitted);
+            EmitStatus::Success as u32
+        }
+        Err(_) => {
+            count_emit(EmitProbe::TcpTraffic, EmitCounter::ReserveFailed);
+            EmitStatus::Failure as u32
+        }
+    }
+}
+
+#[kprobe]
+pub fn honeybeepf_tcp_sendmsg(ctx: ProbeContext) -> u32 {
+    if !probe_enabled(|c| c.probe_tcp_traffic) {
+        return EmitStatus::Success as u32;
+    }
+    let Some(sk) = ctx.arg::<u64>(0) else {
+        count_emit(EmitProbe::TcpTraffic, EmitCounter::ReadFailed);
+        return EmitStatus::Failure as u32;
+    };
+    // Only connections established since accounting started are counted
+    if unsafe { TCP_CONNECTIONS.get(&sk) }.is_some() {
+        let _ = TCP_SENDS.insert(&bpf_get_current_pid_tgid(), &sk, 0);
+    }
+    EmitStatus::Success as u32
+}
+
+#[kretprobe]
+pub fn honeybeepf_tcp_sendmsg_return(ctx: RetProbeContext) -> u32 {
+    let thread = bpf_get_current_pid_tgid();
+    let Some(sk) = (unsafe { TCP_SENDS.get(&thread) }) else {
+        return EmitStatus::Success as u32;
+    };
+    let sk = *sk;
+    let _ = TCP_SENDS.remove(&thread);
+    // tcp_sendmsg() returns an int: the bytes queued, or a negative errno
+    let sent = ctx.ret::<u64>().unwrap_or(0) as i32;
+    if sent > 0 {
+        add_traffic(sk, sent as u64, 0);
+    }
+    EmitStatus::Success as u32
+}
+
+/// Called with the bytes the application just read off the socket.
+#[kprobe]
+pub fn honeybeepf_tcp_cleanup_rbuf(ctx: ProbeContext) -> u32 {
+    if !probe_enabled(|c| c.probe_tcp_traffic) {
+        return EmitStatus::Success as u32;
+    }
+    let (Some(sk), Some(copied)) = (ctx.arg::<u64>(0), ctx.arg::<u64>(1)) else {
+        count_emit(EmitProbe::TcpTraffic, EmitCounter::ReadFailed);
+        return EmitStatus::Failure as u32;
+    };
+    let copied = copied as i32;
+    if copied > 0 {
+        add_traffic(sk, 0, copied as u64);
+    }
+    EmitStatus::Success as u32
+}
+
+#[inline(always)]
+fn add_traffic(sk: u64, sent: u64, received: u64) {
+    let Some(connection) = TCP_CONNECTIONS.get_ptr_mut(&sk) else {
+        return;
+    };
+    let connection = unsafe { &mut *connection };
+    // Owned before any bytes are counted, so user space can leave connections
+    // without an owner for a later scan
+    if connection.metadata.pid == 0 && connection.metadata.cgroup_id == 0 {
+        // Sending and reading run in the task using the connection
+        let mut owner: EventMetadata = unsafe { core::mem::zeroed() };
+        init_metadata(&mut owner);
+        connection.metadata.pid = owner.pid;
+        connection.metadata.cgroup_id = owner.cgroup_id;
+    }
+    // The tcp_sendmsg() return probe runs after the socket lock is released, so
+    // it can race a read on another CPU
+    if sent > 0 {
+        atomic_add(&mut connection.sent_bytes, sent);
+    }
+    if received > 0 {
+        atomic_add(&mut connection.received_bytes, received);
+    }
+}
+
+/// The target has no atomic integer types, only the instruction behind them.
+#[inline(always)]
+fn atomic_add(counter: &mut u64, value: u64) {
+    unsafe { atomic_xadd::<_, _, { AtomicOrdering::Relaxed }>(counter, value) };
+}
+
+#[inline(always)]
+fn emit(ctx: &TracePointContext, program: TcpProgram, event_type: TcpEventType) -> u32 {
+    if !probe_enabled(|c| c.probe_tcp_retransmit) {
+        return EmitStatus::Success as u32;
+    }
+    let Some(layout) = TCP_TRACE_LAYOUTS.get(program as u32) else {
+        count_emit(EmitProbe::TcpRetransmit, EmitCounter::ReadFailed);
+        return EmitStatus::Failure as u32;
+    };
+    // Resets answering segments for no socket belong to no workload
+    let sk: u64 = match read_field(ctx, layout.skaddr) {
+        Ok(0) => return EmitStatus::Success as u32,
+        Ok(sk) => sk,
+        Err(e) => {
+            count_emit(EmitProbe::TcpRetransmit, EmitCounter::ReadFailed);
+            return e;
+        }
+    };
+    let count = match event_type {
+        TcpEventType::Retransmit => match throttle_retransmit(sk) {
+            Some(count) => count,
+            None => return EmitStatus::Success as u32,
+        },
+        _ => 1,
+    };
+    report(ctx, layout, sk, event_type, count)
+}
+
+/// Folds the retransmit into the socket's latest event while that is younger
+/// than the probe interval. Otherwise returns how many retransmits the next
+/// event stands for, this one included.
+#[inline(always)]
+fn throttle_retransmit(sk: u64) -> Option<u32> {
+    let interval = common_config().map_or(0, |c| u64::from(c.probe_interval)) * NS_PER_SECOND;
+    let now = unsafe { bpf_ktime_get_ns() };
+    let mut count = 1;
+    if let Some(window) = TCP_RETRANSMIT_WINDOWS.get_ptr_mut(&sk) {
+        let window = unsafe { &mut *window };
+        if now.wrapping_sub(window.started) < interval {
+            // Retransmits and the close all run with the socket locked
+            window.throttled += 1;
+            return None;
+        }
+        count += window.throttled as u32;
+    }
+    let window = RetransmitWindow {
+        started: now,
+        throttled: 0,
+    };
+    let _ = TCP_RETRANSMIT_WINDOWS.insert(&sk, &window, 0);
+    Some(count)
+}
+
+/// Sends an event for `sk`, attributed to the task that owns it.
+#[inline(always)]
+fn report(
+    ctx: &TracePointContext,
+    layout: &TcpTraceLayout,
+    sk: u64,
+    event_type: TcpEventType,
+    count: u32,
+) -> u32 {
+    let Some(mut slot) = TCP_EVENTS.reserve::<TcpEvent>(0) else {
+        count_emit(EmitProbe::TcpRetransmit, EmitCounter::ReserveFailed);
+        return EmitStatus::Failure as u32;
+    };
+    count_emit(EmitProbe::TcpRetransmit, EmitCounter::Reserved);
+    let event = unsafe { &mut *slot.as_mut_ptr() };
+
+    match read_socket(event, ctx, layout) {
+        Ok(()) => {
+            event.event_type = event_type as u8;
+            event.count = count;
+            event._pad = 0;
+            init_metadata(&mut event.metadata);
+            match unsafe { TCP_SOCKET_OWNERS.get(&sk) } {
+                Some(owner) => {
+                    event.metadata.pid = owner.pid;
+                    event.metadata.cgroup_id = owner.cgroup_id;
+                }
+                None => {
+                    event.metadata.pid = 0;
+                    event.metadata.cgroup_id = 0;
+                }
+            }
+            slot.submit(0);
+            count_emit(EmitProbe::TcpRetransmit, EmitCounter::Submitted);
+            EmitStatus::Success as u32
+        }
+        Err(e) => {
+            slot.discard(0);
+            count_emit(EmitProbe::TcpRetransmit, EmitCounter::ReadFailed);
+            count_emit(EmitProbe::TcpRetransmit, EmitCounter::Discarded);
+            e
+        }
+    }
+}
+
+/// Reads the socket fields of a tracepoint record at the offsets user space
+/// published.
+#[inline(always)]
+fn read_socket(
+    event: &mut TcpEvent,
+    ctx: &TracePointContext,
+    layout: &TcpTraceLayout,
+) -> Result<(), u32> {
+    event.state = if layout.state != TCP_FIELD_MISSING {
+        read_field::<i32>(ctx, layout.state)? as u8
+    } else {
+        0
+    };
+
+    if layout.sockaddr == 0 {
+        event.sport = read_field(ctx, layout.sport)?;
+        event.dport = read_field(ctx, layout.dport)?;
+        event.address_family = read_field(ctx, layout.family)?;
+        // IPv4 sockets have their addresses mapped here as well
+        event.saddr = read_field(ctx, layout.saddr)?;
+        event.daddr = read_field(ctx, layout.daddr)?;
+        return Ok(());
+    }
+
+    event.address_family = read_field(ctx, layout.saddr)?;
+    event.sport = u16::from_be(read_field(ctx, layout.saddr + SOCKADDR_PORT)?);
+    event.dport = u16::from_be(read_field(ctx, layout.daddr + SOCKADDR_PORT)?);
+    if event.address_family == AF_INET {
+        event.saddr = mapped(read_field(ctx, layout.saddr + SOCKADDR_IN_ADDR)?);
+        event.daddr = mapped(read_field(ctx, layout.daddr + SOCKADDR_IN_ADDR)?);
+    } else {
+        event.saddr = read_field(ctx, layout.saddr + SOCKADDR_IN6_ADDR)?;
+        event.daddr = read_field(ctx, layout.daddr + SOCKADDR_IN6_ADDR)?;
+    }
+    Ok(())
+}
+
+/// `::ffff:a.b.c.d` for an IPv4 address in network order.
+#[inline(always)]
+fn mapped(addr: [u8; 4]) -> [u8; 16] {
+    [
+        0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0xff, 0xff, addr[0], addr[1], addr[2], addr[3],
+    ]
+}
+
+#[inline(always)]
+fn read_field<T>(ctx: &TracePointContext, offset: u16) -> Result<T, u32> {
+    unsafe { ctx.read_at::<T>(offset as usize).map_err(|_| 1u32) }
+}
@@ -1 +1,165 @@
-// Future custom probes will go here
+//! Generic programs backing user-defined probes.
+//!
+//! Each program owns a fixed slot in `CUSTOM_PROBE_SPECS`; user space writes the
+//! argument layout for a slot before attaching its program to the configured target.
+
+use aya_ebpf::{
+    macros::{kprobe, kretprobe, map, tracepoint, uprobe, uretprobe},
+    maps::{Array, RingBuf},
+    programs::{ProbeContext, RetProbeContext, TracePointContext},
+};
+use honeybeepf_common::{
+    custom_probe_id, CustomArgSource, CustomArgSpec, CustomProbeEvent, CustomProbeKind,
+    CustomProbeSpec, EmitCounter, EmitProbe, MAX_CUSTOM_ARGS, MAX_CUSTOM_PROBES,
+};
+
+use crate::probes::{count_emit, init_metadata, EmitStatus};
+
+const MAX_EVENT_SIZE: u32 = 1024 * 1024;
+
+#[map]
+pub static CUSTOM_EVENTS: RingBuf = RingBuf::with_byte_size(MAX_EVENT_SIZE, 0);
+
+#[map]
+pub static CUSTOM_PROBE_SPECS: Array<CustomProbeSpec> =
+    Array::with_max_entries(MAX_CUSTOM_PROBES, 0);
+
+#[inline(always)]
+fn emit_custom<F>(probe_id: u32, read_arg: F) -> u32
+where
+    F: Fn(&CustomArgSpec) -> Option<u64>,
+{
+    let Some(spec) = CUSTOM_PROBE_SPECS.get(probe_id) else {
+        return EmitStatus::Failure as u32;
+    };
+    let Some(mut slot) = CUSTOM_EVENTS.reserve::<CustomProbeEvent>(0) else {
+        count_emit(EmitProbe::Custom, EmitCounter::ReserveFailed);
+        return EmitStatus::Failure as u32;
+    };
+    count_emit(EmitProbe::Custom, EmitCounter::Reserved);
+
+    let event = unsafe { &mut *slot.as_mut_ptr() };
+    init_metadata(&mut event.metadata);
+    event.probe_id = probe_id;
+
+    let nr_args = if (spec.nr_args as usize) < MAX_CUSTOM_ARGS {
+        spec.nr_args as usize
+    } else {
+        MAX_CUSTOM_ARGS
+    };
+    event.nr_args = nr_args as u32;
+
+    // Unreadable values are reported as 0 rather than dropping the whole hit
+    let mut read_failed = false;
+    for i in 0..MAX_CUSTOM_ARGS {
+        event.args[i] = if i < nr_args {
+            match read_arg(&spec.args[i]) {
+                Some(value) => value,
+                None => {
+                    read_failed = true;
+                    0
+                }
+            }
+        } else {
+            0
+        };
+    }
+    if read_failed {
+        count_emit(EmitProbe::Custom, EmitCounter::ReadFailed);
+    }
+
+    slot.submit(0);
+    count_emit(EmitProbe::Custom, EmitCounter::Submitted);
+    EmitStatus::Success as u32
+}
+
+#[inline(always)]
+fn read_field(ctx: &TracePointContext, arg: &CustomArgSpec) -> Option<u64> {
+    if arg.source != CustomArgSource::TracepointField as u32 {
+        return None;
+    }
+    let offset = arg.offset as usize;
+    unsafe {
+        match arg.size {
+            1 => ctx.read_at::<u8>(offset).ok().map(u64::from),
+            2 => ctx.read_at::<u16>(offset).ok().map(u64::from),
+            4 => ctx.read_at::<u32>(offset).ok().map(u64::from),
+            _ => ctx.read_at::<u64>(offset).ok(),
+        }
+    }
+}
+
+#[inline(always)]
+fn read_function_arg(ctx: &ProbeContext, arg: &CustomArgSpec) -> Option<u64> {
+    if arg.source != CustomArgSource::FunctionArg as u32 {
+        return None;
+    }
+    ctx.arg::<u64>(arg.offset as usize)
+}
+
+#[inline(always)]
+fn read_return_value(ctx: &RetProbeContext, arg: &CustomArgSpec) -> Option<u64> {
+    if arg.source != CustomArgSource::ReturnValue as u32 {
+        return None;
+    }
+    ctx.ret::<u64>()
+}
+
+macro_rules! custom_tracepoint {
+    ($name:ident, $slot:expr) => {
+        #[tracepoint]
+        pub fn $name(ctx: TracePointContext) -> u32 {
+            emit_custom(custom_probe_id(CustomProbeKind::Tracepoint, $slot), |arg| {
+                read_field(&ctx, arg)
+            })
+        }
+    };
+}
+
+macro_rules! custom_probe {
+    ($attr:ident, $kind:ident, $name:ident, $slot:expr) => {
+        #[$attr]
+        pub fn $name(ctx: ProbeContext) -> u32 {
+            emit_custom(custom_probe_id(CustomProbeKind::$kind, $slot), |arg| {
+                read_function_arg(&ctx, arg)
+            })
+        }
+    };
+}
+
+macro_rules! custom_retprobe {
+    ($attr:ident, $kind:ident, $name:ident, $slot:expr) => {
+        #[$attr]
+        pub fn $name(ctx: RetProbeContext) -> u32 {
+            emit_custom(custom_probe_id(CustomProbeKind::$kind, $slot), |arg| {
+                read_return_value(&ctx, arg)
+            })
+        }
+    };
+}
+
+// One program per slot; keep in sync with MAX_CUSTOM_PROBES_PER_KIND
+custom_tracepoint!(honeybeepf_custom_tracepoint_0, 0);
+custom_tracepoint!(honeybeepf_custom_tracepoint_1, 1);
+custom_tracepoint!(honeybeepf_custom_tracepoint_2, 2);
+custom_tracepoint!(honeybeepf_custom_tracepoint_3, 3);
+
+custom_probe!(kprobe, Kprobe, honeybeepf_custom_kprobe_0, 0);
+custom_probe!(kprobe, Kprobe, honeybeepf_custom_kprobe_1, 1);
+custom_probe!(kprobe, Kprobe, honeybeepf_custom_kprobe_2, 2);
+custom_probe!(kprobe, Kprobe, honeybeepf_custom_kprobe_3, 3);
+
+custom_retprobe!(kretprobe, Kretprobe, honeybeepf_custom_kretprobe_0, 0);
+custom_retprobe!(kretprobe, Kretprobe, honeybeepf_custom_kretprobe_1, 1);
+custom_retprobe!(kretprobe, Kretprobe, honeybeepf_custom_kretprobe_2, 2);
+custom_retprobe!(kretprobe, Kretprobe, honeybeepf_custom_kretprobe_3, 3);
+
+custom_probe!(uprobe, Uprobe, honeybeepf_custom_uprobe_0, 0);
+custom_probe!(uprobe, Uprobe, honeybeepf_custom_uprobe_1, 1);
+custom_probe!(uprobe, Uprobe, honeybeepf_custom_uprobe_2, 2);
+custom_probe!(uprobe, Uprobe, honeybeepf_custom_uprobe_3, 3);
+
+custom_retprobe!(uretprobe, Uretprobe, honeybeepf_custom_uretprobe_0, 0);
+custom_retprobe!(uretprobe, Uretprobe, honeybeepf_custom_uretprobe_1, 1);
+custom_retprobe!(uretprobe, Uretprobe, honeybeepf_custom_uretprobe_2, 2);
+custom_retprobe!(uretprobe, Uretprobe, honeybeepf_custom_uretprobe_3, 3);
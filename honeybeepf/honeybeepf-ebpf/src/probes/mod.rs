@@ -1,13 +1,43 @@
 use aya_ebpf::{
     helpers::{bpf_get_current_cgroup_id, bpf_get_current_pid_tgid, bpf_ktime_get_ns},
-    maps::RingBuf,
+    macros::map,
+    maps::{Array, PerCpuArray, RingBuf},
     programs::TracePointContext,
 };
 
 pub mod builtin;
 pub mod custom;
 
-use honeybeepf_common::EventMetadata;
+use honeybeepf_common::{
+    emit_stat_index, CommonConfig, EmitCounter, EmitProbe, EventMetadata, EMIT_STATS_ENTRIES,
+};
+
+/// Written by user space under `COMMON_CONFIG_MAP`; all zeroes until then.
+#[map]
+pub static HONEYBEE_CONFIG: Array<CommonConfig> = Array::with_max_entries(1, 0);
+
+/// Ringbuf outcomes per probe, summed across CPUs by user space under `EMIT_STATS_MAP`.
+/// Each CPU only writes its own slots, so they are added to without atomics.
+#[map]
+pub static EMIT_STATS: PerCpuArray<u64> = PerCpuArray::with_max_entries(EMIT_STATS_ENTRIES, 0);
+
+#[inline(always)]
+pub fn count_emit(probe: EmitProbe, counter: EmitCounter) {
+    if let Some(value) = EMIT_STATS.get_ptr_mut(emit_stat_index(probe, counter)) {
+        unsafe { *value += 1 };
+    }
+}
+
+#[inline(always)]
+pub fn common_config() -> Option<&'static CommonConfig> {
+    HONEYBEE_CONFIG.get(0)
+}
+
+/// Whether the probe selected by `flag` is enabled in the current config
+#[inline(always)]
+pub fn probe_enabled<F: Fn(&CommonConfig) -> u8>(flag: F) -> bool {
+    common_config().is_some_and(|config| flag(config) != 0)
+}
 
 /// Trait defining the lifecycle of an eBPF event
 pub trait HoneyBeeEvent {
@@ -19,12 +49,16 @@ pub trait HoneyBeeEvent {
 
     /// Common logic to populate base metadata
     fn init_base(&mut self) {
-        unsafe {
-            let m = self.metadata();
-            m.pid = (bpf_get_current_pid_tgid() >> 32) as u32;
-            m.cgroup_id = bpf_get_current_cgroup_id();
-            m.timestamp = bpf_ktime_get_ns();
-        }
+        init_metadata(self.metadata());
+    }
+}
+
+/// Fills pid, cgroup and timestamp for the current task
+pub fn init_metadata(m: &mut EventMetadata) {
+    unsafe {
+        m.pid = (bpf_get_current_pid_tgid() >> 32) as u32;
+        m.cgroup_id = bpf_get_current_cgroup_id();
+        m.timestamp = bpf_ktime_get_ns();
     }
 }
 
@@ -35,22 +69,31 @@ pub enum EmitStatus {
 }
 
 /// A generic reporter function to reduce boilerplate
-pub fn emit_event<T: HoneyBeeEvent + 'static>(ringbuf: &RingBuf, ctx: &TracePointContext) -> u32 {
+pub fn emit_event<T: HoneyBeeEvent + 'static>(
+    probe: EmitProbe,
+    ringbuf: &RingBuf,
+    ctx: &TracePointContext,
+) -> u32 {
     if let Some(mut slot) = ringbuf.reserve::<T>(0) {
+        count_emit(probe, EmitCounter::Reserved);
         let event = unsafe { &mut *slot.as_mut_ptr() };
         
         // Populate event data
         match event.fill(ctx) {
             Ok(_) => {
                 slot.submit(0);
+                count_emit(probe, EmitCounter::Submitted);
                 EmitStatus::Success as u32
             }
             Err(e) => {
                 slot.discard(0);
+                count_emit(probe, EmitCounter::ReadFailed);
+                count_emit(probe, EmitCounter::Discarded);
                 e
             }
         }
     } else {
+        count_emit(probe, EmitCounter::ReserveFailed);
         EmitStatus::Failure as u32
     }
 }
@@ -0,0 +1,428 @@
+use std::{
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6},
+    sync::Arc,
+};
+
+use honeybeepf_common::{
+    BlockIoEvent, BlockIoEventType, BlockIoOp, ConnectionEvent, CustomProbeEvent, EventMetadata,
+    Rwbs, RwbsFlags, TcpEvent, TcpEventType,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    probes::{builtin::block_device::BlockDevice, custom::CustomProbeInfo},
+    telemetry::ktime_to_unix_nanos,
+};
+
+/// Decoded, user-space view of everything the probes report.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum HoneyBeeEvent {
+    BlockIo(BlockIo),
+    Connection(Connection),
+    Tcp(Tcp),
+    TcpTraffic(TcpTraffic),
+    GpuOpen(GpuOpen),
+    Custom(Custom),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EventKind {
+    BlockIo,
+    Connection,
+    Tcp,
+    TcpTraffic,
+    GpuOpen,
+    Custom,
+}
+
+impl HoneyBeeEvent {
+    pub fn kind(&self) -> EventKind {
+        match self {
+            Self::BlockIo(_) => EventKind::BlockIo,
+            Self::Connection(_) => EventKind::Connection,
+            Self::Tcp(_) => EventKind::Tcp,
+            Self::TcpTraffic(_) => EventKind::TcpTraffic,
+            Self::GpuOpen(_) => EventKind::GpuOpen,
+            Self::Custom(_) => EventKind::Custom,
+        }
+    }
+
+    pub fn metadata(&self) -> &Metadata {
+        match self {
+            Self::BlockIo(e) => &e.metadata,
+            Self::Connection(e) => &e.metadata,
+            Self::Tcp(e) => &e.metadata,
+            Self::TcpTraffic(e) => &e.metadata,
+            Self::GpuOpen(e) => &e.metadata,
+            Self::Custom(e) => &e.metadata,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct Metadata {
+    pub pid: u32,
+    pub cgroup_id: u64,
+    /// Nanoseconds since the Unix epoch.
+    pub timestamp: u64,
+}
+
+impl From<&EventMetadata> for Metadata {
+    fn from(m: &EventMetadata) -> Self {
+        Self {
+            pid: m.pid,
+            cgroup_id: m.cgroup_id,
+            timestamp: ktime_to_unix_nanos(m.timestamp),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BlockIo {
+    pub metadata: Metadata,
+    pub event_type: BlockIoEventType,
+    pub major: u32,
+    pub minor: u32,
+    /// What sysfs knows about `major:minor`, when it could be resolved.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub block_device: Option<Arc<BlockDevice>>,
+    pub sector: u64,
+    pub nr_sector: u32,
+    pub bytes: u32,
+    pub rwbs: String,
+    /// Operation and flags decoded from `rwbs`.
+    pub op: BlockIoOp,
+    pub flags: RwbsFlags,
+    pub comm: String,
+    /// Completion latency, on done events whose start was seen by the probe.
+    pub latency_ns: Option<u64>,
+    /// Negative errno of a failed request, on error events.
+    pub error: Option<i32>,
+}
+
+impl BlockIo {
+    pub fn device(&self) -> String {
+        format!("{}:{}", self.major, self.minor)
+    }
+
+    /// Symbolic name of `error`, e.g. `EIO` or `ETIMEDOUT` for a timed out request.
+    pub fn error_name(&self) -> Option<String> {
+        self.error.map(errno_name)
+    }
+}
+
+/// Names the negative errno a probe reports a failure with: those the block
+/// layer maps every `blk_status_t` to, and those connect() fails with. Anything
+/// else is kept numeric.
+pub fn errno_name(error: i32) -> String {
+    let name = match -error {
+        libc::EIO => "EIO",
+        libc::ETIMEDOUT => "ETIMEDOUT",
+        libc::ENODATA => "ENODATA",
+        libc::EREMOTEIO => "EREMOTEIO",
+        libc::ENOLINK => "ENOLINK",
+        libc::EBADE => "EBADE",
+        libc::EILSEQ => "EILSEQ",
+        libc::ENOSPC => "ENOSPC",
+        libc::EOPNOTSUPP => "EOPNOTSUPP",
+        libc::ENOMEM => "ENOMEM",
+        libc::EBUSY => "EBUSY",
+        libc::EAGAIN => "EAGAIN",
+        libc::ENODEV => "ENODEV",
+        libc::ETIME => "ETIME",
+        libc::EINVAL => "EINVAL",
+        libc::ETOOMANYREFS => "ETOOMANYREFS",
+        libc::EOVERFLOW => "EOVERFLOW",
+        libc::ECONNREFUSED => "ECONNREFUSED",
+        libc::ECONNRESET => "ECONNRESET",
+        libc::EHOSTUNREACH => "EHOSTUNREACH",
+        libc::ENETUNREACH => "ENETUNREACH",
+        libc::EADDRNOTAVAIL => "EADDRNOTAVAIL",
+        libc::EADDRINUSE => "EADDRINUSE",
+        libc::EAFNOSUPPORT => "EAFNOSUPPORT",
+        libc::EINPROGRESS => "EINPROGRESS",
+        libc::EALREADY => "EALREADY",
+        libc::EISCONN => "EISCONN",
+        libc::EINTR => "EINTR",
+        libc::EACCES => "EACCES",
+        libc::EPERM => "EPERM",
+        libc::ENOENT => "ENOENT",
+        _ => return error.to_string(),
+    };
+    name.to_string()
+}
+
+impl From<&BlockIoEvent> for BlockIo {
+    fn from(event: &BlockIoEvent) -> Self {
+        let Rwbs { op, flags } = Rwbs::decode(&event.rwbs);
+        Self {
+            metadata: (&event.metadata).into(),
+            event_type: BlockIoEventType::from(event.event_type),
+            major: event.major(),
+            minor: event.minor(),
+            block_device: None,
+            sector: event.sector,
+            nr_sector: event.nr_sector,
+            bytes: event.bytes,
+            rwbs: c_str(&event.rwbs).to_string(),
+            op,
+            flags,
+            comm: c_str(&event.comm).to_string(),
+            latency_ns: (event.latency_ns != 0).then_some(event.latency_ns),
+            error: (event.event_type == BlockIoEventType::Error as u8).then_some(event.error),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Connection {
+    pub metadata: Metadata,
+    pub address_family: u16,
+    /// Only decoded for address families the kernel probe understands. IPv4
+    /// destinations reached through an IPv4-mapped IPv6 address show up as IPv4.
+    pub destination: Option<SocketAddr>,
+    /// Configured network zone of the destination, set by the pipeline.
+    pub zone: Option<Arc<str>>,
+    /// From connect() until it returned or, for non-blocking TCP connects, until
+    /// the handshake finished.
+    pub latency_ns: u64,
+    /// Negative errno the connect failed with, e.g. `-ECONNREFUSED`.
+    pub error: Option<i32>,
+}
+
+impl Connection {
+    /// `ok`, or the symbolic name of the errno the connect failed with.
+    pub fn result(&self) -> String {
+        self.error.map_or_else(|| "ok".to_string(), errno_name)
+    }
+}
+
+impl From<&ConnectionEvent> for Connection {
+    fn from(event: &ConnectionEvent) -> Self {
+        let port = u16::from_be(event.dest_port);
+        let destination = match event.address_family as i32 {
+            libc::AF_INET => {
+                let [a, b, c, d, ..] = event.dest_addr;
+                let ip = Ipv4Addr::new(a, b, c, d);
+                Some(SocketAddr::V4(SocketAddrV4::new(ip, port)))
+            }
+            libc::AF_INET6 => Some(ipv6_socket_addr(event.dest_addr, port, event.scope_id)),
+            _ => None,
+        };
+        Self {
+            metadata: (&event.metadata).into(),
+            address_family: event.address_family,
+            destination,
+            zone: None,
+            latency_ns: event.latency_ns,
+            error: (event.error != 0).then_some(event.error),
+        }
+    }
+}
+
+/// IPv6 socket address, or the IPv4 one it maps.
+pub(crate) fn ipv6_socket_addr(addr: [u8; 16], port: u16, scope_id: u32) -> SocketAddr {
+    let ip = Ipv6Addr::from(addr);
+    match ip.to_ipv4_mapped() {
+        Some(ip) => SocketAddr::V4(SocketAddrV4::new(ip, port)),
+        None => SocketAddr::V6(SocketAddrV6::new(ip, port, 0, scope_id)),
+    }
+}
+
+/// Retransmit or reset on a TCP connection.
+#[derive(Debug, Clone, Serialize)]
+pub struct Tcp {
+    pub metadata: Metadata,
+    pub event_type: TcpEventType,
+    /// Socket state at the time, e.g. `ESTABLISHED`, when the kernel recorded it.
+    pub state: Option<&'static str>,
+    /// Retransmits the event stands for, as the kernel reports those of a socket
+    /// at most once per probe interval; 1 for resets.
+    pub count: u32,
+    pub source: SocketAddr,
+    pub destination: SocketAddr,
+    /// Configured network zone of the destination, set by the pipeline.
+    pub zone: Option<Arc<str>>,
+}
+
+impl From<&TcpEvent> for Tcp {
+    fn from(event: &TcpEvent) -> Self {
+        Self {
+            metadata: (&event.metadata).into(),
+            event_type: TcpEventType::from(event.event_type),
+            state: tcp_state_name(event.state),
+            count: event.count,
+            source: ipv6_socket_addr(event.saddr, event.sport, 0),
+            destination: ipv6_socket_addr(event.daddr, event.dport, 0),
+            zone: None,
+        }
+    }
+}
+
+/// Bytes a cgroup exchanged with one peer over TCP during a reporting interval.
+#[derive(Debug, Clone, Serialize)]
+pub struct TcpTraffic {
+    /// The cgroup and the end of the interval; the pid is 0, as the connections
+    /// summarized may belong to several tasks.
+    pub metadata: Metadata,
+    /// Peer address without the port, which on accepted connections is the
+    /// client's ephemeral one.
+    pub destination: IpAddr,
+    /// Configured network zone of the destination, set by the pipeline.
+    pub zone: Option<Arc<str>>,
+    pub sent_bytes: u64,
+    pub received_bytes: u64,
+    /// Connections with the peer that closed during the interval.
+    pub connections_closed: u64,
+}
+
+/// Name of a `TCP_*` socket state.
+pub fn tcp_state_name(state: u8) -> Option<&'static str> {
+    let name = match state {
+        1 => "ESTABLISHED",
+        2 => "SYN_SENT",
+        3 => "SYN_RECV",
+        4 => "FIN_WAIT1",
+        5 => "FIN_WAIT2",
+        6 => "TIME_WAIT",
+        7 => "CLOSE",
+        8 => "CLOSE_WAIT",
+        9 => "LAST_ACK",
+        10 => "LISTEN",
+        11 => "CLOSING",
+        12 => "NEW_SYN_RECV",
+        _ => return None,
+    };
+    Some(name)
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct GpuOpen {
+    pub metadata: Metadata,
+    pub gpu_index: i32,
+    pub gpu_type: &'static str,
+    pub flags: i32,
+    pub comm: String,
+    pub filename: String,
+}
+
+/// Hit on a user-defined probe, with its extracted values.
+#[derive(Debug, Clone, Serialize)]
+pub struct Custom {
+    pub metadata: Metadata,
+    pub probe: String,
+    pub args: Vec<CustomArg>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CustomArg {
+    pub name: String,
+    pub value: u64,
+}
+
+impl Custom {
+    pub fn decode(info: &CustomProbeInfo, event: &CustomProbeEvent) -> Self {
+        let args = info
+            .arg_names
+            .iter()
+            .zip(event.args.iter().take(event.nr_args as usize))
+            .map(|(name, &value)| CustomArg {
+                name: name.clone(),
+                value,
+            })
+            .collect();
+        Self {
+            metadata: (&event.metadata).into(),
+            probe: info.name.clone(),
+            args,
+        }
+    }
+}
+
+/// Decodes a fixed-size, NUL-padded kernel string.
+pub fn c_str(bytes: &[u8]) -> &str {
+    let end = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+    std::str::from_utf8(&bytes[..end]).unwrap_or("<invalid>")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn connection(address_family: i32, dest_addr: [u8; 16], scope_id: u32) -> Connection {
+        Connection::from(&ConnectionEvent {
+            metadata: EventMetadata::default(),
+            dest_addr,
+            scope_id,
+            dest_port: 443u16.to_be(),
+            address_family: address_family as u16,
+            latency_ns: 0,
+            error: 0,
+            _pad: 0,
+        })
+    }
+
+    #[test]
+    fn test_decode_connection_destinations() {
+        let mut v4 = [0; 16];
+        v4[..4].copy_from_slice(&[10, 0, 0, 1]);
+        let event = connection(libc::AF_INET, v4, 0);
+        assert_eq!(event.destination.unwrap().to_string(), "10.0.0.1:443");
+
+        let v6 = "2001:db8::1".parse::<Ipv6Addr>().unwrap().octets();
+        let event = connection(libc::AF_INET6, v6, 0);
+        assert_eq!(event.destination.unwrap().to_string(), "[2001:db8::1]:443");
+
+        let link_local = "fe80::1".parse::<Ipv6Addr>().unwrap().octets();
+        let event = connection(libc::AF_INET6, link_local, 2);
+        assert_eq!(event.destination.unwrap().to_string(), "[fe80::1%2]:443");
+
+        // Dual-stack sockets reach IPv4 peers through mapped addresses
+        let mapped = "::ffff:192.0.2.7".parse::<Ipv6Addr>().unwrap().octets();
+        let event = connection(libc::AF_INET6, mapped, 0);
+        assert_eq!(event.destination.unwrap().to_string(), "192.0.2.7:443");
+        assert_eq!(event.address_family, libc::AF_INET6 as u16);
+
+        assert!(connection(libc::AF_UNIX, [0; 16], 0).destination.is_none());
+    }
+
+    #[test]
+    fn test_decode_tcp_event() {
+        let mut daddr = [0; 16];
+        daddr[10..].copy_from_slice(&[0xff, 0xff, 10, 0, 0, 9]);
+        let saddr = "2001:db8::2".parse::<Ipv6Addr>().unwrap().octets();
+        let event = Tcp::from(&TcpEvent {
+            metadata: EventMetadata::default(),
+            saddr,
+            daddr,
+            sport: 40000,
+            dport: 29500,
+            address_family: libc::AF_INET as u16,
+            state: 1,
+            event_type: TcpEventType::Retransmit as u8,
+            count: 2,
+            _pad: 0,
+        });
+        assert_eq!(event.destination.to_string(), "10.0.0.9:29500");
+        assert_eq!(event.source.to_string(), "[2001:db8::2]:40000");
+        assert_eq!(event.state, Some("ESTABLISHED"));
+        assert_eq!(event.event_type, TcpEventType::Retransmit);
+        assert_eq!(event.count, 2);
+        assert_eq!(tcp_state_name(0), None);
+    }
+
+    #[test]
+    fn test_decode_connection_outcome() {
+        let mut event = connection(libc::AF_INET, [0; 16], 0);
+        assert_eq!((event.error, event.result().as_str()), (None, "ok"));
+
+        event.error = Some(-libc::ECONNREFUSED);
+        assert_eq!(event.result(), "ECONNREFUSED");
+        event.error = Some(-libc::ETIMEDOUT);
+        assert_eq!(event.result(), "ETIMEDOUT");
+        event.error = Some(-4095);
+        assert_eq!(event.result(), "-4095");
+    }
+}
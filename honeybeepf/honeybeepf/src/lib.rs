@@ -1,58 +1,322 @@
+pub mod events;
+pub mod pipeline;
 pub mod settings;
+pub mod telemetry;
+use std::collections::HashSet;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
 use anyhow::Result;
-use aya::Ebpf;  // Bpf → Ebpf
+use aya::{Ebpf, EbpfLoader};  // Bpf → Ebpf
 use aya_log::EbpfLogger;  // BpfLogger → EbpfLogger
-use log::{info, warn};
+use log::{error, info, warn};
 use tokio::signal;
+use tokio::signal::unix::{signal as unix_signal, SignalKind};
 
-use crate::settings::Settings;
+use crate::settings::{ConfigWatcher, Settings};
 
 pub mod probes;
-use crate::probes::builtin::network::NetworkLatencyProbe;
-use crate::probes::builtin::block_io::BlockIoProbe;
-use crate::probes::builtin::gpu_open::GpuOpenProbe;
-use crate::probes::Probe;
+use crate::probes::custom::CustomProbes;
+use crate::probes::builtin::block_io::{BlockIoHistograms, BlockIoQueues};
+use crate::probes::builtin::tcp_traffic::TcpTrafficReports;
+use crate::probes::stats::EmitStats;
+use crate::probes::{ring_buffer_size, Probe, ProbeRegistry};
+use crate::pipeline::sinks::{
+    file::FileSink, log::LogSink, metrics::MetricsSink, otlp::OtlpSink,
+};
+use crate::pipeline::{EventSender, Pipeline, RunningPipeline, DEFAULT_INGRESS_CAPACITY};
+use crate::telemetry::metrics::ProbeMetrics;
+use crate::telemetry::otlp::{OtlpConfig, OtlpExporter, OtlpProtocol};
+
+/// How often the config file is checked for changes.
+const CONFIG_POLL_INTERVAL: Duration = Duration::from_secs(5);
 
 pub struct HoneyBeeEngine {
     pub settings: Settings,
     bpf: Ebpf,
+    metrics: Arc<ProbeMetrics>,
+    config_file: Option<PathBuf>,
+    registry: ProbeRegistry,
+    /// Registered probes currently attached, by name.
+    attached: HashSet<&'static str>,
+    custom: CustomProbes,
 }
 
 impl HoneyBeeEngine {
     pub fn new(settings: Settings, bytecode: &[u8]) -> Result<Self> {
         bump_memlock_rlimit()?;
-        let mut bpf = Ebpf::load(bytecode)?;
+        let registry = ProbeRegistry::builtin();
+        let custom = CustomProbes::new(Default::default());
+
+        let mut loader = EbpfLoader::new();
+        for probe in registry.iter().chain([&custom as &dyn Probe]) {
+            if let Some(size) = settings.ring_buffers.size(probe.name()) {
+                loader.set_max_entries(probe.ring_buffer(), size);
+            }
+        }
+        let mut bpf = loader.load(bytecode)?;
         if let Err(e) = EbpfLogger::init(&mut bpf) {
             warn!("Failed to initialize eBPF logger: {}", e);
         }
-        Ok(Self { settings, bpf })
+        for probe in registry.iter().chain([&custom as &dyn Probe]) {
+            match ring_buffer_size(&bpf, probe.ring_buffer()) {
+                Some(size) => info!(
+                    "Ringbuf {} for {} probe: {} KiB",
+                    probe.ring_buffer(),
+                    probe.name(),
+                    size / 1024
+                ),
+                None => warn!("Ringbuf {} not found", probe.ring_buffer()),
+            }
+        }
+
+        probes::write_common_config(&mut bpf, settings.to_common_config())?;
+        let metrics = Arc::new(ProbeMetrics::new()?);
+        Ok(Self {
+            settings,
+            bpf,
+            metrics,
+            config_file: None,
+            registry,
+            attached: HashSet::new(),
+            custom,
+        })
+    }
+
+    /// Config file to re-read on SIGHUP or when its contents change.
+    pub fn with_config_file(mut self, config_file: Option<PathBuf>) -> Self {
+        self.config_file = config_file;
+        self
     }
 
     pub async fn run(mut self) -> Result<()> {
-        self.attach_probes()?;
+        let pipeline = self.start_pipeline()?;
+        let events = pipeline.sender().clone();
+        self.apply_probes(&events)?;
+        self.spawn_emit_stats(&events);
+        self.spawn_block_io_histograms(&events);
+        self.spawn_block_io_queues(&events);
+        self.spawn_tcp_traffic(&events);
+        self.spawn_metrics_server();
+
+        let mut hangup = unix_signal(SignalKind::hangup())?;
+        let mut terminate = unix_signal(SignalKind::terminate())?;
+        let mut watcher = self.config_file.as_deref().map(ConfigWatcher::new);
+        let mut poll = tokio::time::interval(CONFIG_POLL_INTERVAL);
 
         info!("Monitoring active. Press Ctrl-C to exit.");
-        signal::ctrl_c().await?;
-        info!("Exiting...");
+        loop {
+            tokio::select! {
+                result = signal::ctrl_c() => {
+                    result?;
+                    info!("Received SIGINT");
+                    break;
+                }
+                _ = terminate.recv() => {
+                    info!("Received SIGTERM");
+                    break;
+                }
+                _ = hangup.recv() => self.reload(&events, "SIGHUP"),
+                _ = poll.tick(), if watcher.is_some() => {
+                    if watcher.as_mut().is_some_and(ConfigWatcher::changed) {
+                        self.reload(&events, "config file changed");
+                    }
+                }
+            }
+        }
+        drop(events);
+        self.shutdown(pipeline).await;
 
         Ok(())
     }
 
-    fn attach_probes(&mut self) -> Result<()> {
-        if self.settings.builtin_probes.network_latency.unwrap_or(false) {
-            NetworkLatencyProbe.attach(&mut self.bpf)?;
+    /// Detaches every probe, then gives the ringbuf readers and sinks until the
+    /// configured deadline to publish and flush what they still hold.
+    async fn shutdown(mut self, pipeline: RunningPipeline) {
+        let deadline = self.settings.shutdown_timeout();
+        info!("Shutting down within {:?}...", deadline);
+
+        // Detach first so the final drain sees every event the programs produced
+        for probe in self.registry.iter() {
+            if self.attached.remove(probe.name())
+                && let Err(e) = probe.detach(&mut self.bpf)
+            {
+                warn!("Failed to detach {} probe: {:#}", probe.name(), e);
+            }
+        }
+        if let Err(e) = self.custom.detach(&mut self.bpf) {
+            warn!("Failed to detach custom probes: {:#}", e);
+        }
+
+        match tokio::time::timeout(deadline, pipeline.shutdown()).await {
+            Ok(()) => info!("Exiting..."),
+            Err(_) => warn!(
+                "Shutdown did not finish within {:?}; exiting with events still queued",
+                deadline
+            ),
+        }
+    }
+
+    /// Re-reads the settings and brings the attached probes in line with them.
+    /// Sinks, the metrics server and the OTLP exporter keep their startup settings.
+    fn reload(&mut self, events: &EventSender, reason: &str) {
+        info!("Reloading configuration ({})", reason);
+        let settings = match Settings::load_validated(self.config_file.as_deref()) {
+            Ok(settings) => settings,
+            Err(e) => {
+                error!("Keeping the current configuration: {}", e);
+                return;
+            }
+        };
+
+        if settings.ring_buffers != self.settings.ring_buffers {
+            warn!("Ringbuf size changes take effect after a restart");
+        }
+        if settings.builtin_probes.interval != self.settings.builtin_probes.interval {
+            warn!("Probe interval changes take effect after a restart");
+        }
+        if settings.network_zones != self.settings.network_zones {
+            warn!("Network zone changes take effect after a restart");
+        }
+        self.settings = settings;
+        if let Err(e) = self.apply_probes(events) {
+            error!("Failed to apply reloaded configuration: {:#}", e);
+        }
+    }
+
+    fn start_pipeline(&self) -> Result<RunningPipeline> {
+        let sinks = &self.settings.sinks;
+        let zones = self.settings.zone_table()?;
+        let mut pipeline = Pipeline::new(self.metrics.clone()).with_zones(zones);
+
+        if sinks.log.enabled.unwrap_or(true) {
+            pipeline.add_configured_sink(Box::new(LogSink), &sinks.log);
+        }
+
+        if sinks.metrics.enabled.unwrap_or(true) {
+            pipeline.add_configured_sink(
+                Box::new(MetricsSink::new(self.metrics.clone())),
+                &sinks.metrics,
+            );
         }
 
-        if self.settings.builtin_probes.block_io.unwrap_or(false) {
-            BlockIoProbe.attach(&mut self.bpf)?;
+        if sinks.otlp.enabled.unwrap_or(true)
+            && let Some(exporter) = self.start_otlp_exporter()?
+        {
+            pipeline.add_configured_sink(Box::new(OtlpSink::new(exporter)), &sinks.otlp);
         }
 
-        if self.settings.builtin_probes.gpu_open.unwrap_or(false) {
-            GpuOpenProbe.attach(&mut self.bpf)?;
+        // The file sink is opt-in: it only runs once a path has been configured
+        if let Some(path) = sinks.file.path.as_deref()
+            && sinks.file.enabled.unwrap_or(true)
+        {
+            let sink = FileSink::open(Path::new(path))?;
+            pipeline.add_configured_sink(Box::new(sink), &sinks.file);
         }
 
+        Ok(pipeline.start(DEFAULT_INGRESS_CAPACITY))
+    }
+
+    fn start_otlp_exporter(&self) -> Result<Option<OtlpExporter>> {
+        let endpoint = match self.settings.otel_exporter_otlp_endpoint.as_deref() {
+            Some(endpoint) if !endpoint.trim().is_empty() => endpoint,
+            _ => return Ok(None),
+        };
+
+        let protocol: OtlpProtocol = self
+            .settings
+            .otel_exporter_otlp_protocol
+            .as_deref()
+            .unwrap_or("grpc")
+            .parse()?;
+        let exporter = OtlpExporter::spawn(OtlpConfig::new(endpoint, protocol), self.metrics.clone())?;
+        Ok(Some(exporter))
+    }
+
+    /// Attaches newly enabled probes and detaches disabled ones, leaving the rest running.
+    fn apply_probes(&mut self, events: &EventSender) -> Result<()> {
+        self.registry
+            .apply(&mut self.bpf, &self.settings, &mut self.attached, events)?;
+
+        let custom = self.settings.custom_probes()?.unwrap_or_default();
+        if custom != *self.custom.config() {
+            self.custom.detach(&mut self.bpf)?;
+            self.custom = self.custom.with_config(custom);
+            self.custom.attach(&mut self.bpf, events)?;
+        }
+
+        self.report_status();
         Ok(())
     }
+
+    fn report_status(&self) {
+        for probe in self.registry.iter().chain([&self.custom as &dyn Probe]) {
+            if probe.enabled(&self.settings) {
+                let path = probe
+                    .attach_path()
+                    .map(|path| format!(" through {}", path))
+                    .unwrap_or_default();
+                info!(
+                    "Probe {} ({}): {}{}",
+                    probe.name(),
+                    probe.config_section(),
+                    probe.status(&self.bpf),
+                    path
+                );
+            } else {
+                info!("Probe {} ({}): disabled", probe.name(), probe.config_section());
+            }
+        }
+    }
+
+    fn spawn_emit_stats(&mut self, events: &EventSender) {
+        match EmitStats::new(&mut self.bpf, self.metrics.clone()) {
+            Ok(stats) => stats.spawn(events),
+            Err(e) => warn!("Kernel event accounting unavailable: {:#}", e),
+        }
+    }
+
+    fn spawn_block_io_histograms(&mut self, events: &EventSender) {
+        match BlockIoHistograms::new(&mut self.bpf, self.metrics.clone()) {
+            Ok(histograms) => histograms.spawn(events, self.settings.probe_interval()),
+            Err(e) => warn!("Block IO histograms unavailable: {:#}", e),
+        }
+    }
+
+    fn spawn_block_io_queues(&mut self, events: &EventSender) {
+        match BlockIoQueues::new(&self.bpf, self.metrics.clone()) {
+            Ok(queues) => queues.spawn(events, self.settings.probe_interval()),
+            Err(e) => warn!("Block IO queue depth unavailable: {:#}", e),
+        }
+    }
+
+    fn spawn_tcp_traffic(&mut self, events: &EventSender) {
+        let interval = self.settings.probe_interval();
+        let spawned = TcpTrafficReports::new(&mut self.bpf)
+            .and_then(|reports| reports.spawn(&mut self.bpf, events, interval));
+        if let Err(e) = spawned {
+            warn!("TCP traffic reports unavailable: {:#}", e);
+        }
+    }
+
+    fn spawn_metrics_server(&self) {
+        let metrics_settings = &self.settings.metrics;
+        if !metrics_settings.is_enabled() {
+            info!("Prometheus metrics endpoint disabled");
+            return;
+        }
+
+        let addr = SocketAddr::from((Ipv4Addr::UNSPECIFIED, metrics_settings.port()));
+        let path = metrics_settings.path();
+        let metrics = self.metrics.clone();
+        tokio::spawn(async move {
+            if let Err(e) = telemetry::server::serve(addr, path, metrics).await {
+                error!("Metrics server stopped: {:#}", e);
+            }
+        });
+    }
 }
 
 fn bump_memlock_rlimit() -> Result<()> {
@@ -1,12 +1,28 @@
+use std::path::PathBuf;
+
 use anyhow::{Context, Result};
 use aya::include_bytes_aligned;
-use clap::Parser;
+use clap::{Parser, Subcommand};
+use honeybeepf::settings::{CONFIG_FILE_ENV, Settings};
 
 #[derive(Debug, Parser)]
 struct Opt {
     /// Verbose output
-    #[clap(short, long)]
+    #[clap(short, long, global = true)]
     verbose: bool,
+
+    /// TOML or YAML config file; environment variables override its values
+    #[clap(short, long, global = true, env = CONFIG_FILE_ENV)]
+    config: Option<PathBuf>,
+
+    #[clap(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Validate the configuration and exit without loading any eBPF programs
+    CheckConfig,
 }
 
 #[tokio::main]
@@ -19,14 +35,25 @@ async fn main() -> Result<()> {
     .init();
 
     // Initialize Settings and Engine
-    let settings = honeybeepf::settings::Settings::new().context("Failed to load settings")?;
+    let settings = match Settings::load_validated(opt.config.as_deref()) {
+        Ok(settings) => settings,
+        Err(e) if matches!(opt.command, Some(Command::CheckConfig)) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+        Err(e) => return Err(e).context("Failed to load settings"),
+    };
+
+    if let Some(Command::CheckConfig) = opt.command {
+        println!("Configuration OK");
+        return Ok(());
+    }
 
     let engine = honeybeepf::HoneyBeeEngine::new(
         settings,
         include_bytes_aligned!(concat!(env!("OUT_DIR"), "/honeybeepf")),
-    )?;
-
-    engine.run().await?;
+    )?
+    .with_config_file(opt.config);
 
-    std::process::exit(0);
+    engine.run().await
 }
@@ -0,0 +1,530 @@
+use std::{collections::HashSet, future::Future, pin::Pin, sync::Arc};
+
+use honeybeepf_common::{BlockIoOp, RwbsFlags};
+use log::{debug, info, warn};
+use prometheus::IntCounter;
+use tokio::{runtime::Handle, sync::mpsc};
+use tokio_util::{sync::CancellationToken, task::TaskTracker};
+
+use crate::{
+    events::{EventKind, HoneyBeeEvent},
+    pipeline::zones::ZoneTable,
+    settings::SinkSettings,
+    telemetry::metrics::ProbeMetrics,
+};
+
+pub mod sinks;
+pub mod zones;
+
+pub const DEFAULT_INGRESS_CAPACITY: usize = 8192;
+pub const DEFAULT_SINK_QUEUE_SIZE: usize = 1024;
+
+const INGRESS_STAGE: &str = "ingress";
+
+pub type CloseFuture = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+/// Destination for decoded probe events. Each sink runs on its own task.
+pub trait Sink: Send + 'static {
+    fn name(&self) -> &str;
+
+    fn handle(&mut self, event: &HoneyBeeEvent);
+
+    /// Called whenever the sink's queue has been drained.
+    fn flush(&mut self) {}
+
+    /// Whether `handle` and `flush` wait on IO, such as writes to a disk. Such sinks
+    /// run on the blocking thread pool, where they cannot stall the ringbuf readers.
+    fn blocking(&self) -> bool {
+        false
+    }
+
+    /// Called once at shutdown after the final `flush`. Sinks that hand events to a
+    /// background task return a future that waits for it to finish.
+    fn close(self: Box<Self>) -> CloseFuture {
+        Box::pin(async {})
+    }
+}
+
+/// Decides which events a sink receives. An empty filter accepts everything.
+#[derive(Debug, Clone, Default)]
+pub struct EventFilter {
+    kinds: Option<HashSet<EventKind>>,
+    block_io_ops: Option<HashSet<BlockIoOp>>,
+    /// Flags every delivered block IO request must carry.
+    block_io_flags: RwbsFlags,
+}
+
+impl EventFilter {
+    pub fn kinds(kinds: impl IntoIterator<Item = EventKind>) -> Self {
+        Self {
+            kinds: Some(kinds.into_iter().collect()),
+            ..Default::default()
+        }
+    }
+
+    pub fn from_settings(settings: &SinkSettings) -> Self {
+        Self {
+            kinds: settings
+                .events
+                .as_ref()
+                .map(|kinds| kinds.iter().copied().collect()),
+            block_io_ops: settings
+                .block_io_ops
+                .as_ref()
+                .map(|ops| ops.iter().copied().collect()),
+            block_io_flags: settings.block_io_flags.iter().flatten().copied().collect(),
+        }
+    }
+
+    pub fn matches(&self, event: &HoneyBeeEvent) -> bool {
+        if !self
+            .kinds
+            .as_ref()
+            .is_none_or(|kinds| kinds.contains(&event.kind()))
+        {
+            return false;
+        }
+        match event {
+            HoneyBeeEvent::BlockIo(e) => {
+                self.block_io_ops
+                    .as_ref()
+                    .is_none_or(|ops| ops.contains(&e.op))
+                    && e.flags.contains_all(self.block_io_flags)
+            }
+            _ => true,
+        }
+    }
+}
+
+/// Cheap, cloneable handle used by ringbuf handlers to publish events.
+#[derive(Clone)]
+pub struct EventSender {
+    tx: mpsc::Sender<HoneyBeeEvent>,
+    dropped: IntCounter,
+    producers: Producers,
+}
+
+/// Tasks feeding the pipeline, which shutdown stops and waits for before closing it.
+#[derive(Clone, Default)]
+struct Producers {
+    stop: CancellationToken,
+    tasks: TaskTracker,
+}
+
+impl EventSender {
+    /// Never blocks; when the pipeline is saturated the event is counted and dropped.
+    pub fn send(&self, event: HoneyBeeEvent) {
+        if self.tx.try_send(event).is_err() {
+            record_drop(&self.dropped, INGRESS_STAGE);
+        }
+    }
+
+    /// Runs a producer task on the runtime. The token is cancelled at shutdown;
+    /// the producer should then publish whatever it still has buffered and return.
+    pub fn spawn_producer<F, Fut>(&self, producer: F)
+    where
+        F: FnOnce(CancellationToken) -> Fut,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        self.producers
+            .tasks
+            .spawn(producer(self.producers.stop.clone()));
+    }
+}
+
+struct Route {
+    name: String,
+    filter: EventFilter,
+    tx: mpsc::Sender<Arc<HoneyBeeEvent>>,
+    dropped: IntCounter,
+}
+
+/// Fans events from a single bounded ingress channel out to every registered sink.
+pub struct Pipeline {
+    metrics: Arc<ProbeMetrics>,
+    routes: Vec<Route>,
+    /// Network zones events are labelled with before they reach the sinks.
+    zones: ZoneTable,
+    /// The fan-out task and every sink task.
+    tasks: TaskTracker,
+}
+
+impl Pipeline {
+    pub fn new(metrics: Arc<ProbeMetrics>) -> Self {
+        Self {
+            metrics,
+            routes: Vec::new(),
+            zones: ZoneTable::default(),
+            tasks: TaskTracker::new(),
+        }
+    }
+
+    pub fn with_zones(mut self, zones: ZoneTable) -> Self {
+        self.zones = zones;
+        self
+    }
+
+    /// Spawns the sink on its own task behind a queue of `queue_size` events.
+    pub fn add_sink(&mut self, mut sink: Box<dyn Sink>, filter: EventFilter, queue_size: usize) {
+        let name = sink.name().to_string();
+        let (tx, mut rx) = mpsc::channel::<Arc<HoneyBeeEvent>>(queue_size.max(1));
+
+        if sink.blocking() {
+            let runtime = Handle::current();
+            self.tasks.spawn_blocking(move || {
+                while let Some(event) = rx.blocking_recv() {
+                    sink.handle(&event);
+                    if rx.is_empty() {
+                        sink.flush();
+                    }
+                }
+                sink.flush();
+                debug!("Sink {} stopped", sink.name());
+                runtime.block_on(sink.close());
+            });
+        } else {
+            self.tasks.spawn(async move {
+                while let Some(event) = rx.recv().await {
+                    sink.handle(&event);
+                    if rx.is_empty() {
+                        sink.flush();
+                    }
+                }
+                sink.flush();
+                debug!("Sink {} stopped", sink.name());
+                sink.close().await;
+            });
+        }
+
+        info!("Registered event sink {}", name);
+        self.routes.push(Route {
+            dropped: self.metrics.pipeline_dropped(&name),
+            name,
+            filter,
+            tx,
+        });
+    }
+
+    pub fn add_configured_sink(&mut self, sink: Box<dyn Sink>, settings: &SinkSettings) {
+        self.add_sink(
+            sink,
+            EventFilter::from_settings(settings),
+            settings.queue_size.unwrap_or(DEFAULT_SINK_QUEUE_SIZE),
+        );
+    }
+
+    /// Starts the fan-out task.
+    pub fn start(self, capacity: usize) -> RunningPipeline {
+        let (tx, mut rx) = mpsc::channel::<HoneyBeeEvent>(capacity.max(1));
+        let dropped = self.metrics.pipeline_dropped(INGRESS_STAGE);
+        let routes = self.routes;
+        let zones = self.zones;
+        let close = CancellationToken::new();
+
+        let closed = close.clone();
+        self.tasks.spawn(async move {
+            let mut closing = false;
+            loop {
+                let event = tokio::select! {
+                    event = rx.recv() => event,
+                    _ = closed.cancelled(), if !closing => {
+                        // Refuse new events but still deliver the ones already queued
+                        closing = true;
+                        rx.close();
+                        continue;
+                    }
+                };
+                let Some(mut event) = event else { break };
+                if !zones.is_empty() {
+                    zones.annotate(&mut event);
+                }
+                let event = Arc::new(event);
+                for route in &routes {
+                    if !route.filter.matches(&event) {
+                        continue;
+                    }
+                    if route.tx.try_send(event.clone()).is_err() {
+                        record_drop(&route.dropped, &route.name);
+                    }
+                }
+            }
+            // Dropping the routes lets every sink task finish once its queue is empty
+        });
+        self.tasks.close();
+
+        RunningPipeline {
+            sender: EventSender {
+                tx,
+                dropped,
+                producers: Producers::default(),
+            },
+            close,
+            tasks: self.tasks,
+        }
+    }
+}
+
+/// A started pipeline: the handle probes publish into, plus its shutdown control.
+pub struct RunningPipeline {
+    sender: EventSender,
+    close: CancellationToken,
+    tasks: TaskTracker,
+}
+
+impl RunningPipeline {
+    pub fn sender(&self) -> &EventSender {
+        &self.sender
+    }
+
+    /// Stops the producers and waits for them to publish what they have buffered,
+    /// then delivers every queued event and waits for each sink to flush and close.
+    pub async fn shutdown(self) {
+        let producers = &self.sender.producers;
+        producers.stop.cancel();
+        producers.tasks.close();
+        producers.tasks.wait().await;
+        debug!("Event producers stopped");
+
+        self.close.cancel();
+        self.tasks.wait().await;
+        debug!("Event sinks stopped");
+    }
+}
+
+fn record_drop(counter: &IntCounter, stage: &str) {
+    counter.inc();
+    let dropped = counter.get();
+    if dropped.is_power_of_two() {
+        warn!(
+            "Event pipeline stage {} is falling behind; {} events dropped so far",
+            stage, dropped
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Mutex, time::Duration};
+
+    use honeybeepf_common::{BlockIoEventType, Rwbs, RwbsFlag};
+
+    use super::*;
+    use crate::events::{BlockIo, Connection, Metadata};
+
+    struct CollectingSink {
+        received: Arc<Mutex<Vec<EventKind>>>,
+    }
+
+    impl Sink for CollectingSink {
+        fn name(&self) -> &str {
+            "collecting"
+        }
+
+        fn handle(&mut self, event: &HoneyBeeEvent) {
+            self.received.lock().unwrap().push(event.kind());
+        }
+    }
+
+    /// Records how many events it had seen when it was closed.
+    struct ClosingSink {
+        received: usize,
+        closed_after: Arc<Mutex<Option<usize>>>,
+    }
+
+    impl Sink for ClosingSink {
+        fn name(&self) -> &str {
+            "closing"
+        }
+
+        fn handle(&mut self, _event: &HoneyBeeEvent) {
+            self.received += 1;
+        }
+
+        fn close(self: Box<Self>) -> CloseFuture {
+            *self.closed_after.lock().unwrap() = Some(self.received);
+            Box::pin(async {})
+        }
+    }
+
+    /// Records the threads it handled events on.
+    struct BlockingSink {
+        threads: Arc<Mutex<Vec<std::thread::ThreadId>>>,
+    }
+
+    impl Sink for BlockingSink {
+        fn name(&self) -> &str {
+            "blocking"
+        }
+
+        fn handle(&mut self, _event: &HoneyBeeEvent) {
+            self.threads.lock().unwrap().push(std::thread::current().id());
+        }
+
+        fn blocking(&self) -> bool {
+            true
+        }
+    }
+
+    fn connection_event() -> HoneyBeeEvent {
+        HoneyBeeEvent::Connection(Connection {
+            metadata: Metadata {
+                pid: 1,
+                cgroup_id: 1,
+                timestamp: 0,
+            },
+            address_family: libc::AF_INET as u16,
+            destination: None,
+            zone: None,
+            latency_ns: 0,
+            error: None,
+        })
+    }
+
+    fn block_io_event(rwbs: &str) -> HoneyBeeEvent {
+        let Rwbs { op, flags } = Rwbs::decode(rwbs.as_bytes());
+        HoneyBeeEvent::BlockIo(BlockIo {
+            metadata: Metadata {
+                pid: 1,
+                cgroup_id: 1,
+                timestamp: 0,
+            },
+            event_type: BlockIoEventType::Done,
+            major: 259,
+            minor: 0,
+            block_device: None,
+            sector: 0,
+            nr_sector: 8,
+            bytes: 4096,
+            rwbs: rwbs.to_string(),
+            op,
+            flags,
+            comm: String::new(),
+            latency_ns: None,
+            error: None,
+        })
+    }
+
+    #[test]
+    fn test_block_io_filter() {
+        let filter = EventFilter::from_settings(&SinkSettings {
+            block_io_ops: Some(vec![BlockIoOp::Write, BlockIoOp::Flush]),
+            block_io_flags: Some(vec![RwbsFlag::Sync]),
+            ..Default::default()
+        });
+
+        assert!(filter.matches(&block_io_event("WS")));
+        assert!(filter.matches(&block_io_event("FWFS")));
+        assert!(!filter.matches(&block_io_event("W")));
+        assert!(!filter.matches(&block_io_event("RS")));
+        // Other kinds are not subject to the block IO criteria
+        assert!(filter.matches(&connection_event()));
+    }
+
+    #[tokio::test]
+    async fn test_fan_out_respects_filters() {
+        let metrics = Arc::new(ProbeMetrics::new().unwrap());
+        let mut pipeline = Pipeline::new(metrics);
+
+        let accepted = Arc::new(Mutex::new(Vec::new()));
+        let rejected = Arc::new(Mutex::new(Vec::new()));
+        pipeline.add_sink(
+            Box::new(CollectingSink {
+                received: accepted.clone(),
+            }),
+            EventFilter::kinds([EventKind::Connection]),
+            16,
+        );
+        pipeline.add_sink(
+            Box::new(CollectingSink {
+                received: rejected.clone(),
+            }),
+            EventFilter::kinds([EventKind::BlockIo]),
+            16,
+        );
+
+        let running = pipeline.start(16);
+        let sender = running.sender();
+        sender.send(connection_event());
+        sender.send(connection_event());
+
+        for _ in 0..50 {
+            if accepted.lock().unwrap().len() == 2 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(accepted.lock().unwrap().len(), 2);
+        assert!(rejected.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_drains_producers_and_sinks() {
+        let metrics = Arc::new(ProbeMetrics::new().unwrap());
+        let mut pipeline = Pipeline::new(metrics);
+        let closed_after = Arc::new(Mutex::new(None));
+        pipeline.add_sink(
+            Box::new(ClosingSink {
+                received: 0,
+                closed_after: closed_after.clone(),
+            }),
+            EventFilter::default(),
+            16,
+        );
+
+        let running = pipeline.start(16);
+        let sender = running.sender().clone();
+        running.sender().spawn_producer(move |stop| async move {
+            sender.send(connection_event());
+            stop.cancelled().await;
+            // Whatever was still buffered when shutdown began
+            sender.send(connection_event());
+        });
+
+        tokio::time::timeout(Duration::from_secs(5), running.shutdown())
+            .await
+            .unwrap();
+        assert_eq!(*closed_after.lock().unwrap(), Some(2));
+    }
+
+    #[tokio::test]
+    async fn test_blocking_sink_runs_off_the_runtime() {
+        let metrics = Arc::new(ProbeMetrics::new().unwrap());
+        let mut pipeline = Pipeline::new(metrics);
+        let threads = Arc::new(Mutex::new(Vec::new()));
+        pipeline.add_sink(
+            Box::new(BlockingSink {
+                threads: threads.clone(),
+            }),
+            EventFilter::default(),
+            16,
+        );
+
+        let running = pipeline.start(16);
+        running.sender().send(connection_event());
+        tokio::time::timeout(Duration::from_secs(5), running.shutdown())
+            .await
+            .unwrap();
+
+        // The test's runtime runs on this thread only
+        let threads = threads.lock().unwrap();
+        assert_eq!(threads.len(), 1);
+        assert_ne!(threads[0], std::thread::current().id());
+    }
+
+    #[test]
+    fn test_full_ingress_counts_drops() {
+        let metrics = ProbeMetrics::new().unwrap();
+        let (tx, _rx) = mpsc::channel(1);
+        let sender = EventSender {
+            tx,
+            dropped: metrics.pipeline_dropped(INGRESS_STAGE),
+            producers: Producers::default(),
+        };
+
+        sender.send(connection_event());
+        sender.send(connection_event());
+        sender.send(connection_event());
+
+        assert_eq!(metrics.pipeline_dropped(INGRESS_STAGE).get(), 2);
+    }
+}
@@ -0,0 +1,89 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::{BufWriter, Write},
+    path::Path,
+};
+
+use anyhow::{Context, Result};
+use log::warn;
+
+use crate::{events::HoneyBeeEvent, pipeline::Sink};
+
+/// Appends events to a file as JSON lines, from the blocking thread pool.
+pub struct FileSink {
+    writer: BufWriter<File>,
+}
+
+impl FileSink {
+    pub fn open(path: &Path) -> Result<Self> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("Failed to open event file {}", path.display()))?;
+        Ok(Self {
+            writer: BufWriter::new(file),
+        })
+    }
+}
+
+impl Sink for FileSink {
+    fn name(&self) -> &str {
+        "file"
+    }
+
+    fn handle(&mut self, event: &HoneyBeeEvent) {
+        let result = serde_json::to_writer(&mut self.writer, event)
+            .map_err(std::io::Error::from)
+            .and_then(|_| self.writer.write_all(b"\n"));
+        if let Err(e) = result {
+            warn!("Failed to write event to file sink: {}", e);
+        }
+    }
+
+    fn flush(&mut self) {
+        if let Err(e) = self.writer.flush() {
+            warn!("Failed to flush file sink: {}", e);
+        }
+    }
+
+    fn blocking(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::{GpuOpen, Metadata};
+
+    #[test]
+    fn test_writes_json_lines() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("events.jsonl");
+        let mut sink = FileSink::open(&path).unwrap();
+
+        let event = HoneyBeeEvent::GpuOpen(GpuOpen {
+            metadata: Metadata {
+                pid: 7,
+                cgroup_id: 3,
+                timestamp: 0,
+            },
+            gpu_index: 0,
+            gpu_type: "NVIDIA",
+            flags: 0,
+            comm: "python".to_string(),
+            filename: "/dev/nvidia0".to_string(),
+        });
+        sink.handle(&event);
+        sink.handle(&event);
+        sink.flush();
+
+        let contents = std::fs::read_to_string(&path).unwrap();
+        let lines: Vec<&str> = contents.lines().collect();
+        assert_eq!(lines.len(), 2);
+        let parsed: serde_json::Value = serde_json::from_str(lines[0]).unwrap();
+        assert_eq!(parsed["kind"], "gpu_open");
+        assert_eq!(parsed["filename"], "/dev/nvidia0");
+    }
+}
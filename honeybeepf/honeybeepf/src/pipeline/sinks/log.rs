@@ -0,0 +1,113 @@
+use std::sync::Arc;
+
+use log::info;
+
+use crate::{events::HoneyBeeEvent, pipeline::Sink};
+
+fn zone(zone: &Option<Arc<str>>) -> String {
+    zone.as_ref()
+        .map(|zone| format!(" zone={}", zone))
+        .unwrap_or_default()
+}
+
+/// Writes one human-readable line per event to the agent log.
+pub struct LogSink;
+
+impl Sink for LogSink {
+    fn name(&self) -> &str {
+        "log"
+    }
+
+    fn handle(&mut self, event: &HoneyBeeEvent) {
+        match event {
+            HoneyBeeEvent::BlockIo(e) => {
+                let latency = e
+                    .latency_ns
+                    .map(|ns| format!(" latency_us={}", ns / 1000))
+                    .unwrap_or_default();
+                let error = e
+                    .error_name()
+                    .map(|name| format!(" error={}", name))
+                    .unwrap_or_default();
+                let name = e
+                    .block_device
+                    .as_ref()
+                    .map(|device| format!(" ({})", device.name))
+                    .unwrap_or_default();
+                info!(
+                    "BlockIO {} pid={} dev={}{} sector={} nr_sector={} bytes={} op={} flags={} comm={}{}{}",
+                    e.event_type.as_str().to_uppercase(),
+                    e.metadata.pid,
+                    e.device(),
+                    name,
+                    e.sector,
+                    e.nr_sector,
+                    e.bytes,
+                    e.op.as_str(),
+                    e.flags,
+                    e.comm,
+                    latency,
+                    error
+                )
+            }
+            HoneyBeeEvent::Connection(e) => match e.destination {
+                Some(destination) => info!(
+                    "PID {} connecting to {} {} after {}us (cgroup_id={}, ts={}){}",
+                    e.metadata.pid,
+                    destination,
+                    e.result(),
+                    e.latency_ns / 1000,
+                    e.metadata.cgroup_id,
+                    e.metadata.timestamp,
+                    zone(&e.zone)
+                ),
+                None => info!(
+                    "PID {} connecting with address family {} {} (cgroup_id={}, ts={})",
+                    e.metadata.pid,
+                    e.address_family,
+                    e.result(),
+                    e.metadata.cgroup_id,
+                    e.metadata.timestamp
+                ),
+            },
+            HoneyBeeEvent::Tcp(e) => info!(
+                "TCP {} pid={} cgroup_id={} {} -> {} state={} count={}{}",
+                e.event_type.as_str().to_uppercase(),
+                e.metadata.pid,
+                e.metadata.cgroup_id,
+                e.source,
+                e.destination,
+                e.state.unwrap_or("unknown"),
+                e.count,
+                zone(&e.zone)
+            ),
+            HoneyBeeEvent::TcpTraffic(e) => info!(
+                "TCP_TRAFFIC cgroup_id={} peer={} sent={} received={} closed={}{}",
+                e.metadata.cgroup_id,
+                e.destination,
+                e.sent_bytes,
+                e.received_bytes,
+                e.connections_closed,
+                zone(&e.zone)
+            ),
+            HoneyBeeEvent::GpuOpen(e) => info!(
+                "GPU_OPEN pid={} comm={} gpu_index={} type={} file={} cgroup_id={}",
+                e.metadata.pid, e.comm, e.gpu_index, e.gpu_type, e.filename, e.metadata.cgroup_id,
+            ),
+            HoneyBeeEvent::Custom(e) => {
+                let args: Vec<String> = e
+                    .args
+                    .iter()
+                    .map(|arg| format!("{}={}", arg.name, arg.value))
+                    .collect();
+                info!(
+                    "CUSTOM probe={} pid={} cgroup_id={} {}",
+                    e.probe,
+                    e.metadata.pid,
+                    e.metadata.cgroup_id,
+                    args.join(" ")
+                )
+            }
+        }
+    }
+}
@@ -0,0 +1,31 @@
+use std::sync::Arc;
+
+use crate::{events::HoneyBeeEvent, pipeline::Sink, telemetry::metrics::ProbeMetrics};
+
+/// Aggregates events into the registry behind `/metrics` and the OTLP metric export.
+pub struct MetricsSink {
+    metrics: Arc<ProbeMetrics>,
+}
+
+impl MetricsSink {
+    pub fn new(metrics: Arc<ProbeMetrics>) -> Self {
+        Self { metrics }
+    }
+}
+
+impl Sink for MetricsSink {
+    fn name(&self) -> &str {
+        "metrics"
+    }
+
+    fn handle(&mut self, event: &HoneyBeeEvent) {
+        match event {
+            HoneyBeeEvent::BlockIo(e) => self.metrics.record_block_io(e),
+            HoneyBeeEvent::Connection(e) => self.metrics.record_connection(e),
+            HoneyBeeEvent::Tcp(e) => self.metrics.record_tcp(e),
+            HoneyBeeEvent::TcpTraffic(e) => self.metrics.record_tcp_traffic(e),
+            HoneyBeeEvent::GpuOpen(e) => self.metrics.record_gpu_open(e),
+            HoneyBeeEvent::Custom(e) => self.metrics.record_custom(e),
+        }
+    }
+}
@@ -0,0 +1,4 @@
+pub mod file;
+pub mod log;
+pub mod metrics;
+pub mod otlp;
@@ -0,0 +1,170 @@
+use crate::{
+    events::{HoneyBeeEvent, errno_name},
+    pipeline::{CloseFuture, Sink},
+    telemetry::otlp::{OtlpExporter, bool_attr, event_record, int_attr, string_attr},
+};
+
+/// Ships every event as an OTLP log record.
+pub struct OtlpSink {
+    exporter: OtlpExporter,
+}
+
+impl OtlpSink {
+    pub fn new(exporter: OtlpExporter) -> Self {
+        Self { exporter }
+    }
+}
+
+impl Sink for OtlpSink {
+    fn name(&self) -> &str {
+        "otlp"
+    }
+
+    fn handle(&mut self, event: &HoneyBeeEvent) {
+        let metadata = event.metadata();
+        let mut attributes = vec![
+            int_attr("process.pid", metadata.pid as i64),
+            int_attr("cgroup.id", metadata.cgroup_id as i64),
+        ];
+
+        let (name, body) =
+            match event {
+                HoneyBeeEvent::BlockIo(e) => {
+                    attributes.extend([
+                        string_attr("process.command", e.comm.as_str()),
+                        string_attr("block.device", e.device()),
+                        string_attr("block.event_type", e.event_type.as_str()),
+                        string_attr("block.rwbs", e.rwbs.as_str()),
+                        string_attr("block.op", e.op.as_str()),
+                        string_attr("block.flags", e.flags.to_string()),
+                        int_attr("block.sector", e.sector as i64),
+                        int_attr("block.bytes", e.bytes as i64),
+                    ]);
+                    if let Some(latency_ns) = e.latency_ns {
+                        attributes.push(int_attr("block.latency_ns", latency_ns as i64));
+                    }
+                    if let Some(error) = e.error {
+                        attributes.push(string_attr("block.error", errno_name(error)));
+                        attributes.push(int_attr("block.error_code", error as i64));
+                    }
+                    if let Some(device) = &e.block_device {
+                        attributes.push(string_attr("block.device.name", device.name.as_str()));
+                        if let Some(parent) = &device.parent {
+                            attributes.push(string_attr("block.device.parent", parent.as_str()));
+                        }
+                        if let Some(dm_name) = &device.dm_name {
+                            attributes.push(string_attr("block.device.dm_name", dm_name.as_str()));
+                            attributes.push(bool_attr("block.device.lvm", device.lvm));
+                        }
+                        if let Some(model) = &device.model {
+                            attributes.push(string_attr("block.device.model", model.as_str()));
+                        }
+                    }
+                    (
+                        "honeybeepf.block_io",
+                        format!(
+                            "block io {} on {} ({} bytes)",
+                            e.event_type.as_str(),
+                            e.device(),
+                            e.bytes
+                        ),
+                    )
+                }
+                HoneyBeeEvent::Connection(e) => {
+                    attributes.extend([
+                        int_attr("network.address_family", e.address_family as i64),
+                        int_attr("network.connect.latency_ns", e.latency_ns as i64),
+                    ]);
+                    if let Some(error) = e.error {
+                        attributes.push(string_attr("error.type", errno_name(error)));
+                    }
+                    if let Some(destination) = e.destination {
+                        let network_type = if destination.is_ipv4() {
+                            "ipv4"
+                        } else {
+                            "ipv6"
+                        };
+                        attributes.extend([
+                            string_attr("network.type", network_type),
+                            string_attr("destination.address", destination.ip().to_string()),
+                            int_attr("destination.port", destination.port() as i64),
+                        ]);
+                    }
+                    if let Some(zone) = &e.zone {
+                        attributes.push(string_attr("destination.zone", zone.as_ref()));
+                    }
+                    let body = match e.destination {
+                        Some(destination) => format!("connect to {}", destination),
+                        None => format!("connect with address family {}", e.address_family),
+                    };
+                    ("honeybeepf.network.connect", body)
+                }
+                HoneyBeeEvent::Tcp(e) => {
+                    attributes.extend([
+                        string_attr("network.transport", "tcp"),
+                        string_attr("tcp.event", e.event_type.as_str()),
+                        int_attr("tcp.event.count", e.count as i64),
+                        string_attr("source.address", e.source.ip().to_string()),
+                        int_attr("source.port", e.source.port() as i64),
+                        string_attr("destination.address", e.destination.ip().to_string()),
+                        int_attr("destination.port", e.destination.port() as i64),
+                    ]);
+                    if let Some(state) = e.state {
+                        attributes.push(string_attr("tcp.state", state));
+                    }
+                    if let Some(zone) = &e.zone {
+                        attributes.push(string_attr("destination.zone", zone.as_ref()));
+                    }
+                    (
+                        "honeybeepf.network.tcp",
+                        format!("tcp {} to {}", e.event_type.as_str(), e.destination),
+                    )
+                }
+                HoneyBeeEvent::TcpTraffic(e) => {
+                    attributes.extend([
+                        string_attr("network.transport", "tcp"),
+                        string_attr("destination.address", e.destination.to_string()),
+                        int_attr("network.io.sent_bytes", e.sent_bytes as i64),
+                        int_attr("network.io.received_bytes", e.received_bytes as i64),
+                        int_attr("tcp.connections_closed", e.connections_closed as i64),
+                    ]);
+                    if let Some(zone) = &e.zone {
+                        attributes.push(string_attr("destination.zone", zone.as_ref()));
+                    }
+                    (
+                        "honeybeepf.network.tcp_traffic",
+                        format!(
+                            "{} bytes sent to and {} received from {}",
+                            e.sent_bytes, e.received_bytes, e.destination
+                        ),
+                    )
+                }
+                HoneyBeeEvent::GpuOpen(e) => {
+                    attributes.extend([
+                        string_attr("process.command", e.comm.as_str()),
+                        int_attr("gpu.index", e.gpu_index as i64),
+                        string_attr("gpu.type", e.gpu_type),
+                        string_attr("gpu.device_file", e.filename.as_str()),
+                    ]);
+                    (
+                        "honeybeepf.gpu.open",
+                        format!("{} opened {}", e.comm, e.filename),
+                    )
+                }
+                HoneyBeeEvent::Custom(e) => {
+                    attributes.push(string_attr("probe.name", e.probe.as_str()));
+                    attributes.extend(e.args.iter().map(|arg| {
+                        int_attr(&format!("probe.args.{}", arg.name), arg.value as i64)
+                    }));
+                    ("honeybeepf.custom", format!("custom probe {} hit", e.probe))
+                }
+            };
+
+        self.exporter
+            .emit(event_record(name, metadata.timestamp, body, attributes));
+    }
+
+    fn close(self: Box<Self>) -> CloseFuture {
+        Box::pin(self.exporter.shutdown())
+    }
+}
@@ -0,0 +1,337 @@
+//! Named address ranges network peers are classified into, such as the pod and
+//! service CIDRs of the cluster or an on-prem storage network. Events are
+//! labelled with the zone of the most specific range holding their peer.
+
+use std::{
+    fmt,
+    net::{IpAddr, Ipv4Addr},
+    str::FromStr,
+    sync::Arc,
+};
+
+use anyhow::{Context, Result, bail, ensure};
+
+use crate::{events::HoneyBeeEvent, settings::NetworkZone};
+
+/// Address range in CIDR notation; a bare address is a single host.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cidr {
+    addr: IpAddr,
+    prefix_len: u8,
+}
+
+impl FromStr for Cidr {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let s = s.trim();
+        let (addr, prefix_len) = match s.split_once('/') {
+            Some((addr, prefix_len)) => (addr, Some(prefix_len)),
+            None => (s, None),
+        };
+        let addr: IpAddr = addr
+            .parse()
+            .with_context(|| format!("invalid address in '{}'", s))?;
+        let width = address_width(addr);
+        let prefix_len = match prefix_len {
+            Some(prefix_len) => prefix_len
+                .parse::<u8>()
+                .ok()
+                .filter(|&prefix_len| prefix_len <= width)
+                .with_context(|| {
+                    format!("invalid prefix length in '{}', expected 0 to {}", s, width)
+                })?,
+            None => width,
+        };
+
+        let bits = address_bits(addr);
+        let network = bits & prefix_mask(prefix_len);
+        ensure!(
+            bits == network,
+            "'{}' has host bits set; the network is {}/{}",
+            s,
+            bits_address(network, addr.is_ipv4()),
+            prefix_len
+        );
+        Ok(Self { addr, prefix_len })
+    }
+}
+
+impl fmt::Display for Cidr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix_len)
+    }
+}
+
+fn address_width(addr: IpAddr) -> u8 {
+    if addr.is_ipv4() { 32 } else { 128 }
+}
+
+/// The address as bits, most significant first, so that IPv4 and IPv6 prefixes
+/// are both read from the top.
+fn address_bits(addr: IpAddr) -> u128 {
+    match addr {
+        IpAddr::V4(addr) => u128::from(u32::from(addr)) << 96,
+        IpAddr::V6(addr) => u128::from(addr),
+    }
+}
+
+fn bits_address(bits: u128, ipv4: bool) -> IpAddr {
+    if ipv4 {
+        IpAddr::V4(Ipv4Addr::from((bits >> 96) as u32))
+    } else {
+        IpAddr::V6(bits.into())
+    }
+}
+
+fn prefix_mask(prefix_len: u8) -> u128 {
+    u128::MAX
+        .checked_shl(128 - u32::from(prefix_len))
+        .unwrap_or(0)
+}
+
+/// Binary trie over address bits. A lookup follows the address one bit at a
+/// time and remembers the deepest zone it passed, so the longest prefix wins
+/// in at most one step per bit, however many ranges are configured.
+#[derive(Debug)]
+struct PrefixTrie {
+    /// The root, covering every address, comes first.
+    nodes: Vec<TrieNode>,
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+struct TrieNode {
+    /// Index of the node for a 0 and a 1 as the next bit; 0 when there is none,
+    /// as the root is nobody's child.
+    children: [u32; 2],
+    zone: Option<u32>,
+}
+
+impl Default for PrefixTrie {
+    fn default() -> Self {
+        Self {
+            nodes: vec![TrieNode::default()],
+        }
+    }
+}
+
+fn bit(bits: u128, index: u8) -> usize {
+    ((bits >> (127 - index)) & 1) as usize
+}
+
+impl PrefixTrie {
+    /// Assigns the prefix to `zone`, returning the zone it had before.
+    fn insert(&mut self, bits: u128, prefix_len: u8, zone: u32) -> Option<u32> {
+        let mut node = 0;
+        for index in 0..prefix_len {
+            let bit = bit(bits, index);
+            node = match self.nodes[node].children[bit] {
+                0 => {
+                    self.nodes.push(TrieNode::default());
+                    let child = self.nodes.len() - 1;
+                    self.nodes[node].children[bit] = child as u32;
+                    child
+                }
+                child => child as usize,
+            };
+        }
+        self.nodes[node].zone.replace(zone)
+    }
+
+    fn lookup(&self, bits: u128, width: u8) -> Option<u32> {
+        let mut node = 0;
+        let mut zone = self.nodes[node].zone;
+        for index in 0..width {
+            node = match self.nodes[node].children[bit(bits, index)] {
+                0 => break,
+                child => child as usize,
+            };
+            zone = self.nodes[node].zone.or(zone);
+        }
+        zone
+    }
+}
+
+/// Zone of every configured range, by address family.
+#[derive(Debug, Default)]
+pub struct ZoneTable {
+    names: Vec<Arc<str>>,
+    ipv4: PrefixTrie,
+    ipv6: PrefixTrie,
+}
+
+impl ZoneTable {
+    /// Fails on ranges that are not valid CIDRs, and on a range listed under
+    /// two zones, as either could win.
+    pub fn new(zones: &[NetworkZone]) -> Result<Self> {
+        let mut table = Self::default();
+        for zone in zones {
+            ensure!(!zone.name.trim().is_empty(), "zone names must not be empty");
+            let index = table.names.len() as u32;
+            table.names.push(zone.name.as_str().into());
+            for cidr in &zone.cidrs {
+                let cidr: Cidr = cidr
+                    .parse()
+                    .with_context(|| format!("in zone {}", zone.name))?;
+                let trie = if cidr.addr.is_ipv4() {
+                    &mut table.ipv4
+                } else {
+                    &mut table.ipv6
+                };
+                if let Some(other) = trie.insert(address_bits(cidr.addr), cidr.prefix_len, index)
+                    && other != index
+                {
+                    bail!(
+                        "{} is in both zone {} and zone {}",
+                        cidr,
+                        table.names[other as usize],
+                        zone.name
+                    );
+                }
+            }
+        }
+        Ok(table)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty()
+    }
+
+    /// Zone of the most specific range holding `addr`. IPv4-mapped IPv6
+    /// addresses are matched against the IPv4 ranges.
+    pub fn classify(&self, addr: IpAddr) -> Option<&Arc<str>> {
+        let addr = match addr {
+            IpAddr::V6(v6) => v6.to_ipv4_mapped().map_or(addr, IpAddr::V4),
+            IpAddr::V4(_) => addr,
+        };
+        let trie = if addr.is_ipv4() {
+            &self.ipv4
+        } else {
+            &self.ipv6
+        };
+        trie.lookup(address_bits(addr), address_width(addr))
+            .map(|zone| &self.names[zone as usize])
+    }
+
+    /// Labels network events with the zone of their peer.
+    pub fn annotate(&self, event: &mut HoneyBeeEvent) {
+        match event {
+            HoneyBeeEvent::Connection(e) => {
+                e.zone = e
+                    .destination
+                    .and_then(|destination| self.classify(destination.ip()))
+                    .cloned();
+            }
+            HoneyBeeEvent::Tcp(e) => e.zone = self.classify(e.destination.ip()).cloned(),
+            HoneyBeeEvent::TcpTraffic(e) => e.zone = self.classify(e.destination).cloned(),
+            HoneyBeeEvent::BlockIo(_) | HoneyBeeEvent::GpuOpen(_) | HoneyBeeEvent::Custom(_) => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::{Connection, Metadata};
+
+    fn zone(name: &str, cidrs: &[&str]) -> NetworkZone {
+        NetworkZone {
+            name: name.to_string(),
+            cidrs: cidrs.iter().map(ToString::to_string).collect(),
+        }
+    }
+
+    fn cluster() -> ZoneTable {
+        ZoneTable::new(&[
+            zone("internet", &["0.0.0.0/0", "::/0"]),
+            zone("on_prem", &["10.0.0.0/8", "fd00::/8"]),
+            zone("pod", &["10.244.0.0/16", "fd00:10:244::/56"]),
+            zone("service", &["10.96.0.0/12"]),
+            zone("storage", &["10.244.7.20"]),
+        ])
+        .unwrap()
+    }
+
+    fn classify<'a>(table: &'a ZoneTable, addr: &str) -> Option<&'a str> {
+        table.classify(addr.parse().unwrap()).map(|zone| &**zone)
+    }
+
+    #[test]
+    fn test_longest_prefix_wins() {
+        let table = cluster();
+        assert_eq!(classify(&table, "10.244.3.9"), Some("pod"));
+        assert_eq!(classify(&table, "10.244.7.20"), Some("storage"));
+        assert_eq!(classify(&table, "10.244.7.21"), Some("pod"));
+        assert_eq!(classify(&table, "10.96.0.1"), Some("service"));
+        assert_eq!(classify(&table, "10.111.255.255"), Some("service"));
+        assert_eq!(classify(&table, "10.112.0.0"), Some("on_prem"));
+        assert_eq!(classify(&table, "192.0.2.1"), Some("internet"));
+
+        assert_eq!(classify(&table, "fd00:10:244:0:ff::1"), Some("pod"));
+        assert_eq!(classify(&table, "fd00:10:244:100::1"), Some("on_prem"));
+        assert_eq!(classify(&table, "2001:db8::1"), Some("internet"));
+        // Dual-stack sockets reach IPv4 peers through mapped addresses
+        assert_eq!(classify(&table, "::ffff:10.96.0.10"), Some("service"));
+
+        let no_default = ZoneTable::new(&[zone("pod", &["10.244.0.0/16"])]).unwrap();
+        assert_eq!(classify(&no_default, "10.245.0.1"), None);
+        assert_eq!(classify(&no_default, "fd00::1"), None);
+        assert!(ZoneTable::default().is_empty());
+    }
+
+    #[test]
+    fn test_parse_cidr() {
+        let cidr: Cidr = " 10.244.0.0/16 ".parse().unwrap();
+        assert_eq!(cidr.to_string(), "10.244.0.0/16");
+        let host: Cidr = "2001:db8::1".parse().unwrap();
+        assert_eq!(host.to_string(), "2001:db8::1/128");
+
+        let error = "10.244.1.0/16".parse::<Cidr>().unwrap_err();
+        assert!(
+            error.to_string().contains("the network is 10.244.0.0/16"),
+            "{}",
+            error
+        );
+        assert!("10.0.0.0/33".parse::<Cidr>().is_err());
+        assert!("10.0.0.0/".parse::<Cidr>().is_err());
+        assert!("pods/16".parse::<Cidr>().is_err());
+    }
+
+    #[test]
+    fn test_rejects_ambiguous_zones() {
+        let error = ZoneTable::new(&[
+            zone("pod", &["10.244.0.0/16"]),
+            zone("nodes", &["10.0.0.0/8", "10.244.0.0/16"]),
+        ])
+        .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "10.244.0.0/16 is in both zone pod and zone nodes"
+        );
+        // Repeating a range within one zone is harmless
+        assert!(ZoneTable::new(&[zone("pod", &["10.244.0.0/16", "10.244.0.0/16"])]).is_ok());
+        assert!(ZoneTable::new(&[zone("pod", &["10.244.0.0/16/1"])]).is_err());
+    }
+
+    #[test]
+    fn test_annotate_network_events() {
+        let table = cluster();
+        let mut event = HoneyBeeEvent::Connection(Connection {
+            metadata: Metadata {
+                pid: 1,
+                cgroup_id: 1,
+                timestamp: 0,
+            },
+            address_family: libc::AF_INET as u16,
+            destination: Some("10.96.0.1:443".parse().unwrap()),
+            zone: None,
+            latency_ns: 0,
+            error: None,
+        });
+        table.annotate(&mut event);
+        let HoneyBeeEvent::Connection(connection) = &event else {
+            unreachable!();
+        };
+        assert_eq!(connection.zone.as_deref(), Some("service"));
+    }
+}
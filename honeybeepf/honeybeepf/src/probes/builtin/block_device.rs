@@ -0,0 +1,369 @@
+//! Names, partition parents, device-mapper names and models of block devices,
+//! read from sysfs and cached per device number.
+
+use std::{
+    collections::HashMap,
+    fs, io,
+    os::fd::{AsRawFd, FromRawFd, OwnedFd},
+    path::{Path, PathBuf},
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, AtomicU64, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+use log::{debug, info, warn};
+use serde::Serialize;
+use tokio::io::unix::AsyncFd;
+
+pub const SYSFS_ROOT: &str = "/sys";
+
+/// How long a device number that sysfs does not know stays unresolved before
+/// it is looked up again, in case its hotplug event was missed.
+const MISSING_DEVICE_TTL: Duration = Duration::from_secs(30);
+/// Resolved devices are re-read this often even without hotplug events, which
+/// the agent only receives when it runs in the host network namespace.
+const RESOLVED_DEVICE_TTL: Duration = Duration::from_secs(600);
+
+const UEVENT_BUFFER_SIZE: usize = 8192;
+/// Multicast group of uevents sent by the kernel itself, rather than by udev.
+const UEVENT_KERNEL_GROUP: u32 = 1;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct BlockDevice {
+    /// Kernel name, e.g. `nvme0n1p1`, `sda` or `dm-3`.
+    pub name: String,
+    /// Disk holding this partition.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub parent: Option<String>,
+    /// Device-mapper name, e.g. `vg0-data` for an LVM volume.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dm_name: Option<String>,
+    /// Whether the device-mapper target belongs to LVM.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub lvm: bool,
+    /// Model of the disk, or of the disk holding the partition.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+}
+
+struct CacheEntry {
+    device: Option<Arc<BlockDevice>>,
+    resolved_at: Instant,
+}
+
+impl CacheEntry {
+    fn is_fresh(&self) -> bool {
+        let ttl = match self.device {
+            Some(_) => RESOLVED_DEVICE_TTL,
+            None => MISSING_DEVICE_TTL,
+        };
+        self.resolved_at.elapsed() < ttl
+    }
+}
+
+/// Resolves `major:minor` device numbers through sysfs.
+pub struct BlockDeviceResolver {
+    sysfs: PathBuf,
+    cache: Mutex<HashMap<(u32, u32), CacheEntry>>,
+    /// Bumped by every invalidation, so that a lookup racing one does not cache
+    /// what it read before the device changed.
+    invalidations: AtomicU64,
+    watching: AtomicBool,
+}
+
+impl BlockDeviceResolver {
+    pub fn new(sysfs: impl Into<PathBuf>) -> Self {
+        Self {
+            sysfs: sysfs.into(),
+            cache: Mutex::new(HashMap::new()),
+            invalidations: AtomicU64::new(0),
+            watching: AtomicBool::new(false),
+        }
+    }
+
+    /// Reads sysfs without holding the cache, so lookups of other devices are
+    /// not held up by it.
+    pub fn resolve(&self, major: u32, minor: u32) -> Option<Arc<BlockDevice>> {
+        if let Some(entry) = self.cache.lock().unwrap().get(&(major, minor))
+            && entry.is_fresh()
+        {
+            return entry.device.clone();
+        }
+
+        let invalidations = self.invalidations.load(Ordering::SeqCst);
+        let device = read_device(&self.sysfs, major, minor).map(Arc::new);
+        let mut cache = self.cache.lock().unwrap();
+        if self.invalidations.load(Ordering::SeqCst) != invalidations {
+            return device;
+        }
+        cache.insert(
+            (major, minor),
+            CacheEntry {
+                device: device.clone(),
+                resolved_at: Instant::now(),
+            },
+        );
+        device
+    }
+
+    /// Forgets a device so that its next event reads sysfs again.
+    pub fn invalidate(&self, major: u32, minor: u32) {
+        let mut cache = self.cache.lock().unwrap();
+        self.invalidations.fetch_add(1, Ordering::SeqCst);
+        cache.remove(&(major, minor));
+    }
+
+    /// Starts following block device hotplug events, once per resolver.
+    pub fn watch_hotplug(self: &Arc<Self>) {
+        if self.watching.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        let socket = match uevent_socket().and_then(AsyncFd::new) {
+            Ok(socket) => socket,
+            Err(e) => {
+                warn!(
+                    "Block device hotplug events unavailable ({}); names are re-read every {:?}",
+                    e, RESOLVED_DEVICE_TTL
+                );
+                return;
+            }
+        };
+
+        let resolver = Arc::downgrade(self);
+        tokio::spawn(async move {
+            let mut buffer = vec![0u8; UEVENT_BUFFER_SIZE];
+            loop {
+                let mut guard = match socket.readable().await {
+                    Ok(guard) => guard,
+                    Err(e) => {
+                        warn!("Stopped following block device hotplug events: {}", e);
+                        return;
+                    }
+                };
+                let Some(resolver) = resolver.upgrade() else {
+                    return;
+                };
+                loop {
+                    let len = unsafe {
+                        libc::recv(
+                            socket.as_raw_fd(),
+                            buffer.as_mut_ptr().cast(),
+                            buffer.len(),
+                            0,
+                        )
+                    };
+                    if len < 0 {
+                        break;
+                    }
+                    if let Some(uevent) = BlockUevent::parse(&buffer[..len as usize]) {
+                        debug!(
+                            "Block device {}:{} {}",
+                            uevent.major, uevent.minor, uevent.action
+                        );
+                        resolver.invalidate(uevent.major, uevent.minor);
+                    }
+                }
+                guard.clear_ready();
+            }
+        });
+        info!("Following block device hotplug events");
+    }
+}
+
+/// The parts of a kernel uevent for a block device that the cache cares about.
+#[derive(Debug, PartialEq, Eq)]
+struct BlockUevent<'a> {
+    action: &'a str,
+    major: u32,
+    minor: u32,
+}
+
+impl<'a> BlockUevent<'a> {
+    /// Parses `ACTION@DEVPATH\0KEY=VALUE\0...`, returning `None` for other subsystems.
+    fn parse(message: &'a [u8]) -> Option<Self> {
+        let mut fields = message
+            .split(|&b| b == 0)
+            .filter_map(|field| std::str::from_utf8(field).ok());
+        let (action, _devpath) = fields.next()?.split_once('@')?;
+
+        let (mut block, mut major, mut minor) = (false, None, None);
+        for field in fields {
+            match field.split_once('=') {
+                Some(("SUBSYSTEM", subsystem)) => block = subsystem == "block",
+                Some(("MAJOR", value)) => major = value.parse().ok(),
+                Some(("MINOR", value)) => minor = value.parse().ok(),
+                _ => {}
+            }
+        }
+        if !block {
+            return None;
+        }
+        Some(Self {
+            action,
+            major: major?,
+            minor: minor?,
+        })
+    }
+}
+
+fn uevent_socket() -> io::Result<OwnedFd> {
+    let fd = unsafe {
+        libc::socket(
+            libc::AF_NETLINK,
+            libc::SOCK_DGRAM | libc::SOCK_NONBLOCK | libc::SOCK_CLOEXEC,
+            libc::NETLINK_KOBJECT_UEVENT,
+        )
+    };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let socket = unsafe { OwnedFd::from_raw_fd(fd) };
+
+    let mut addr: libc::sockaddr_nl = unsafe { std::mem::zeroed() };
+    addr.nl_family = libc::AF_NETLINK as libc::sa_family_t;
+    addr.nl_groups = UEVENT_KERNEL_GROUP;
+    let ret = unsafe {
+        libc::bind(
+            socket.as_raw_fd(),
+            (&addr as *const libc::sockaddr_nl).cast(),
+            std::mem::size_of::<libc::sockaddr_nl>() as libc::socklen_t,
+        )
+    };
+    if ret != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(socket)
+}
+
+/// Reads what sysfs knows about a device, or `None` if it has no such device.
+fn read_device(sysfs: &Path, major: u32, minor: u32) -> Option<BlockDevice> {
+    // A symlink into /sys/devices: .../block/<disk> or .../block/<disk>/<partition>
+    let dir = fs::canonicalize(sysfs.join(format!("dev/block/{}:{}", major, minor))).ok()?;
+    let name = dir.file_name()?.to_str()?.to_string();
+
+    let partition = dir.join("partition").exists();
+    let disk = if partition { dir.parent()? } else { &dir };
+    let parent = partition
+        .then(|| disk.file_name()?.to_str().map(str::to_string))
+        .flatten();
+
+    Some(BlockDevice {
+        name,
+        parent,
+        dm_name: read_attribute(&dir.join("dm/name")),
+        lvm: read_attribute(&dir.join("dm/uuid")).is_some_and(|uuid| uuid.starts_with("LVM-")),
+        model: read_attribute(&disk.join("device/model")),
+    })
+}
+
+/// Contents of a sysfs attribute without the padding some drivers add.
+fn read_attribute(path: &Path) -> Option<String> {
+    let value = fs::read_to_string(path).ok()?;
+    let value = value.trim();
+    (!value.is_empty()).then(|| value.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::fs::symlink;
+
+    use super::*;
+
+    /// Lays out the parts of /sys that the resolver reads.
+    fn fake_sysfs() -> tempfile::TempDir {
+        let root = tempfile::tempdir().unwrap();
+        let path = root.path();
+        let write = |relative: &str, contents: &str| {
+            let file = path.join(relative);
+            fs::create_dir_all(file.parent().unwrap()).unwrap();
+            fs::write(file, contents).unwrap();
+        };
+
+        let nvme = "devices/pci0000:00/0000:00:04.0/nvme/nvme0/nvme0n1";
+        write(&format!("{}/nvme0n1p1/partition", nvme), "1\n");
+        write(
+            &format!("{}/device/model", nvme),
+            "Amazon Elastic Block Store              \n",
+        );
+        write("devices/virtual/block/dm-3/dm/name", "vg0-data\n");
+        write(
+            "devices/virtual/block/dm-3/dm/uuid",
+            "LVM-abcdefghijklmnopqrstuvwxyz\n",
+        );
+
+        fs::create_dir_all(path.join("dev/block")).unwrap();
+        symlink(format!("../../{}", nvme), path.join("dev/block/259:0")).unwrap();
+        symlink(
+            format!("../../{}/nvme0n1p1", nvme),
+            path.join("dev/block/259:1"),
+        )
+        .unwrap();
+        symlink(
+            "../../devices/virtual/block/dm-3",
+            path.join("dev/block/253:3"),
+        )
+        .unwrap();
+        root
+    }
+
+    #[test]
+    fn test_resolve_disks_partitions_and_dm() {
+        let sysfs = fake_sysfs();
+        let resolver = BlockDeviceResolver::new(sysfs.path());
+
+        let disk = resolver.resolve(259, 0).unwrap();
+        assert_eq!(disk.name, "nvme0n1");
+        assert_eq!(disk.parent, None);
+        assert_eq!(disk.model.as_deref(), Some("Amazon Elastic Block Store"));
+
+        let partition = resolver.resolve(259, 1).unwrap();
+        assert_eq!(partition.name, "nvme0n1p1");
+        assert_eq!(partition.parent.as_deref(), Some("nvme0n1"));
+        assert_eq!(partition.model, disk.model);
+
+        let dm = resolver.resolve(253, 3).unwrap();
+        assert_eq!(dm.name, "dm-3");
+        assert_eq!(dm.dm_name.as_deref(), Some("vg0-data"));
+        assert!(dm.lvm);
+        assert_eq!(dm.model, None);
+    }
+
+    #[test]
+    fn test_hotplugged_device_is_picked_up_after_invalidation() {
+        let sysfs = fake_sysfs();
+        let resolver = BlockDeviceResolver::new(sysfs.path());
+        assert_eq!(resolver.resolve(8, 0), None);
+
+        write_disk(sysfs.path(), "sda");
+        // Still cached as missing until the hotplug event arrives
+        assert_eq!(resolver.resolve(8, 0), None);
+
+        let uevent = b"add@/devices/virtual/block/sda\0ACTION=add\0SUBSYSTEM=block\0MAJOR=8\0MINOR=0\0DEVNAME=sda\0";
+        let uevent = BlockUevent::parse(uevent).unwrap();
+        assert_eq!(
+            uevent,
+            BlockUevent {
+                action: "add",
+                major: 8,
+                minor: 0
+            }
+        );
+        resolver.invalidate(uevent.major, uevent.minor);
+        assert_eq!(resolver.resolve(8, 0).unwrap().name, "sda");
+    }
+
+    #[test]
+    fn test_uevents_of_other_subsystems_are_ignored() {
+        let uevent = b"add@/devices/virtual/net/veth0\0ACTION=add\0SUBSYSTEM=net\0";
+        assert_eq!(BlockUevent::parse(uevent), None);
+        assert_eq!(BlockUevent::parse(b"libudev\0garbage"), None);
+    }
+
+    fn write_disk(sysfs: &Path, name: &str) {
+        let dir = sysfs.join("devices/virtual/block").join(name);
+        fs::create_dir_all(&dir).unwrap();
+        symlink(&dir, sysfs.join("dev/block/8:0")).unwrap();
+    }
+}
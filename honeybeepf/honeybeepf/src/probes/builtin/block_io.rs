@@ -1,60 +1,577 @@
-use anyhow::Result;
-use aya::Ebpf;
-use honeybeepf_common::{BlockIoEvent, BlockIoEventType};
-use log::info;
+use std::{
+    collections::{HashMap, HashSet},
+    os::fd::AsFd,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
 
-use crate::probes::{attach_tracepoint, spawn_ringbuf_handler, Probe, TracepointConfig};
+use anyhow::{Context, Result, bail, ensure};
+use aya::{
+    Ebpf,
+    maps::{Array, HashMap as BpfHashMap, Map, MapData, MapError, PerCpuHashMap},
+};
+use honeybeepf_common::{
+    BLOCK_IO_HISTOGRAMS_MAP, BLOCK_IO_INFLIGHT_MAP, BLOCK_IO_LAYOUTS_MAP, BLOCK_IO_QUEUES_MAP,
+    BlockIoEvent, BlockIoHistogramKey, BlockIoProgram, BlockQueueCounters, BlockRequestKey,
+    BlockTraceLayout, EventMetadata, Log2Bucket, dev_major, dev_minor,
+};
+use log::{info, warn};
 
-pub struct BlockIoProbe;
+use crate::events::{BlockIo, HoneyBeeEvent};
+use crate::pipeline::EventSender;
+use crate::probes::builtin::block_device::{BlockDeviceResolver, SYSFS_ROOT};
+use crate::probes::builtin::block_trace::{BlockTracepoints, CATEGORY};
+use crate::probes::{
+    attach_tracepoint, detach_program, program_status, spawn_ringbuf_handler, KernelFeature,
+    Probe, ProbeStatus, TracepointConfig,
+};
+use crate::settings::Settings;
+use crate::telemetry::metrics::{BlockQueueStats, ProbeMetrics};
+
+const START_PROGRAM: &str = "honeybeepf_block_io_start";
+const DONE_PROGRAM: &str = "honeybeepf_block_io_done";
+const ERROR_PROGRAM: &str = "honeybeepf_block_io_error";
+/// How often the queue counters are read between two reports; queue depth and
+/// busy time are resolved to this period.
+const QUEUE_SAMPLE_PERIOD: Duration = Duration::from_millis(100);
+const RING_BUFFER: &str = "BLOCK_IO_EVENTS";
+
+/// The tracepoints are picked at attach time, which fails when none of
+/// `block_trace::ATTACH_PATHS` is usable.
+const REQUIRED_FEATURES: [KernelFeature; 1] = [KernelFeature::RingBuf];
+
+pub struct BlockIoProbe {
+    devices: Arc<BlockDeviceResolver>,
+    /// Tracepoints in use while attached.
+    tracepoints: Mutex<Option<BlockTracepoints>>,
+}
+
+impl BlockIoProbe {
+    pub fn new() -> Self {
+        Self {
+            devices: Arc::new(BlockDeviceResolver::new(SYSFS_ROOT)),
+            tracepoints: Mutex::new(None),
+        }
+    }
+}
+
+impl Default for BlockIoProbe {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
 impl Probe for BlockIoProbe {
-    fn attach(&self, bpf: &mut Ebpf) -> Result<()> {
+    fn name(&self) -> &'static str {
+        "block_io"
+    }
+
+    fn description(&self) -> &'static str {
+        "Block device request start, completion and errors"
+    }
+
+    fn config_section(&self) -> &'static str {
+        "builtin_probes.block_io"
+    }
+
+    fn required_features(&self) -> &[KernelFeature] {
+        &REQUIRED_FEATURES
+    }
+
+    fn enabled(&self, settings: &Settings) -> bool {
+        settings.builtin_probes.block_io.unwrap_or(false)
+    }
+
+    fn ring_buffer(&self) -> &'static str {
+        RING_BUFFER
+    }
+
+    fn attach(&self, bpf: &mut Ebpf, events: &EventSender) -> Result<()> {
         info!("Attaching block IO probes...");
+        reset_request_tracking(bpf)?;
 
-        attach_tracepoint(
-            bpf,
-            TracepointConfig {
-                program_name: "honeybeepf_block_io_start",
-                category: "block",
-                name: "block_io_start",
-            },
-        )?;
-        attach_tracepoint(
-            bpf,
-            TracepointConfig {
-                program_name: "honeybeepf_block_io_done",
-                category: "block",
-                name: "block_io_done",
-            },
-        )?;
-
-        spawn_ringbuf_handler(bpf, "BLOCK_IO_EVENTS", |event: BlockIoEvent| {
-            let rwbs = std::str::from_utf8(&event.rwbs)
-                .unwrap_or("<invalid>")
-                .trim_matches(char::from(0));
-            let comm = std::str::from_utf8(&event.comm)
-                .unwrap_or("<invalid>")
-                .trim_matches(char::from(0));
-
-            let type_str = match BlockIoEventType::from(event.event_type) {
-                BlockIoEventType::Start => "START",
-                BlockIoEventType::Done => "DONE",
-                BlockIoEventType::Unknown => "UNKNOWN",
-            };
+        let tracepoints = BlockTracepoints::select()?;
+        info!("Following block IO through {}", tracepoints);
+        write_layout(bpf, BlockIoProgram::Start, tracepoints.start)?;
+        write_layout(bpf, BlockIoProgram::Done, tracepoints.done)?;
+        for (program, name) in [
+            (START_PROGRAM, tracepoints.path.start),
+            (DONE_PROGRAM, tracepoints.path.done),
+        ] {
+            let attached = attach_tracepoint(
+                bpf,
+                TracepointConfig {
+                    program_name: program,
+                    category: CATEGORY,
+                    name,
+                },
+            )?;
+            ensure!(attached, "Tracepoint {}:{} disappeared", CATEGORY, name);
+        }
+        // Errors are an addition to start and done, so their absence does not fail the probe
+        match tracepoints.error {
+            Some((name, layout)) => {
+                write_layout(bpf, BlockIoProgram::Error, layout)?;
+                attach_tracepoint(
+                    bpf,
+                    TracepointConfig {
+                        program_name: ERROR_PROGRAM,
+                        category: CATEGORY,
+                        name,
+                    },
+                )?;
+            }
+            None => warn!("No block request error tracepoint available; not reporting IO errors"),
+        }
+        *self.tracepoints.lock().unwrap() = Some(tracepoints);
 
-            info!(
-                "BlockIO {} pid={} dev={}:{} sector={} nr_sector={} bytes={} rwbs={} comm={}",
-                type_str,
-                event.metadata.pid,
-                event.dev >> 20,
-                event.dev & 0xFFFFF,
-                event.sector,
-                event.nr_sector,
-                event.bytes,
-                rwbs,
-                comm
-            );
+        self.devices.watch_hotplug();
+        let devices = self.devices.clone();
+        let sender = events.clone();
+        spawn_ringbuf_handler(bpf, events, RING_BUFFER, move |event: BlockIoEvent| {
+            let mut block_io = BlockIo::from(&event);
+            block_io.block_device = devices.resolve(block_io.major, block_io.minor);
+            sender.send(HoneyBeeEvent::BlockIo(block_io));
         })?;
         Ok(())
     }
+
+    fn detach(&self, bpf: &mut Ebpf) -> Result<()> {
+        for program in [START_PROGRAM, DONE_PROGRAM, ERROR_PROGRAM] {
+            detach_program(bpf, program)?;
+        }
+        *self.tracepoints.lock().unwrap() = None;
+        Ok(())
+    }
+
+    fn status(&self, bpf: &Ebpf) -> ProbeStatus {
+        let with_errors = self
+            .tracepoints
+            .lock()
+            .unwrap()
+            .is_none_or(|tracepoints| tracepoints.error.is_some());
+        if with_errors {
+            program_status(bpf, &[START_PROGRAM, DONE_PROGRAM, ERROR_PROGRAM])
+        } else {
+            program_status(bpf, &[START_PROGRAM, DONE_PROGRAM])
+        }
+    }
+
+    fn attach_path(&self) -> Option<String> {
+        let tracepoints = *self.tracepoints.lock().unwrap();
+        tracepoints
+            .or_else(|| BlockTracepoints::select().ok())
+            .map(|tracepoints| tracepoints.to_string())
+    }
+}
+
+/// Tells `program` where its tracepoint keeps the request fields.
+fn write_layout(bpf: &mut Ebpf, program: BlockIoProgram, layout: BlockTraceLayout) -> Result<()> {
+    let mut layouts: Array<_, BlockTraceLayout> = Array::try_from(
+        bpf.map_mut(BLOCK_IO_LAYOUTS_MAP)
+            .with_context(|| format!("Failed to find {} map", BLOCK_IO_LAYOUTS_MAP))?,
+    )?;
+    layouts.set(program as u32, layout, 0)?;
+    Ok(())
+}
+
+/// Starts from empty request tracking, so that requests whose completion went
+/// unseen while the programs were detached do not stay in flight forever.
+fn reset_request_tracking(bpf: &mut Ebpf) -> Result<()> {
+    let mut inflight: BpfHashMap<_, BlockRequestKey, EventMetadata> = BpfHashMap::try_from(
+        bpf.map_mut(BLOCK_IO_INFLIGHT_MAP)
+            .with_context(|| format!("Failed to find {} map", BLOCK_IO_INFLIGHT_MAP))?,
+    )?;
+    let keys = inflight.keys().collect::<Result<Vec<_>, _>>()?;
+    for key in keys {
+        inflight.remove(&key)?;
+    }
+
+    let mut queues: PerCpuHashMap<_, u32, BlockQueueCounters> = PerCpuHashMap::try_from(
+        bpf.map_mut(BLOCK_IO_QUEUES_MAP)
+            .with_context(|| format!("Failed to find {} map", BLOCK_IO_QUEUES_MAP))?,
+    )?;
+    let keys = queues.keys().collect::<Result<Vec<_>, _>>()?;
+    for key in keys {
+        queues.remove(&key)?;
+    }
+    Ok(())
+}
+
+/// Moves the kernel's block IO histograms into `ProbeMetrics`. The map is only
+/// filled in `BlockIoMode::Histograms`; otherwise reading it is a no-op.
+pub struct BlockIoHistograms {
+    map: PerCpuHashMap<MapData, BlockIoHistogramKey, Log2Bucket>,
+    metrics: Arc<ProbeMetrics>,
+}
+
+impl BlockIoHistograms {
+    pub fn new(bpf: &mut Ebpf, metrics: Arc<ProbeMetrics>) -> Result<Self> {
+        let map = bpf
+            .take_map(BLOCK_IO_HISTOGRAMS_MAP)
+            .with_context(|| format!("Failed to find {} map", BLOCK_IO_HISTOGRAMS_MAP))?;
+        Ok(Self {
+            map: PerCpuHashMap::try_from(map)?,
+            metrics,
+        })
+    }
+
+    /// Adds every slot counted since the previous call to the metrics and clears it.
+    pub fn update(&mut self) -> Result<()> {
+        let keys = self.map.keys().collect::<Result<Vec<_>, _>>()?;
+        for key in keys {
+            let values = match self.map.get(&key, 0) {
+                Ok(values) => values,
+                Err(MapError::KeyNotFound) => continue,
+                Err(e) => return Err(e.into()),
+            };
+            // Removing the entry resets it; samples landing between the read and
+            // the removal are lost, which is a handful per interval at most
+            self.map.remove(&key)?;
+
+            let bucket = values.iter().fold(Log2Bucket::default(), |total, cpu| Log2Bucket {
+                count: total.count + cpu.count,
+                sum: total.sum + cpu.sum,
+            });
+            self.metrics.record_block_io_bucket(&key, &bucket)?;
+        }
+        Ok(())
+    }
+
+    /// Updates the metrics every `interval`, and once more at shutdown.
+    pub fn spawn(mut self, events: &EventSender, interval: Duration) {
+        events.spawn_producer(move |stop| async move {
+            let mut tick = tokio::time::interval(interval);
+            loop {
+                let stopping = tokio::select! {
+                    _ = tick.tick() => false,
+                    _ = stop.cancelled() => true,
+                };
+                if let Err(e) = self.update() {
+                    warn!("Failed to read {} map: {:#}", BLOCK_IO_HISTOGRAMS_MAP, e);
+                }
+                if stopping {
+                    return;
+                }
+            }
+        });
+    }
+}
+
+/// Samples the kernel's per-device request counters into queue depth and
+/// utilization gauges.
+pub struct BlockIoQueues {
+    map: PerCpuHashMap<MapData, u32, BlockQueueCounters>,
+    /// Requests the kernel tracks, counted to write off those it evicted.
+    inflight: BpfHashMap<MapData, BlockRequestKey, EventMetadata>,
+    sampler: QueueSampler,
+    metrics: Arc<ProbeMetrics>,
+}
+
+impl BlockIoQueues {
+    pub fn new(bpf: &Ebpf, metrics: Arc<ProbeMetrics>) -> Result<Self> {
+        let map = Map::PerCpuHashMap(reopen_map(bpf, BLOCK_IO_QUEUES_MAP)?);
+        let inflight = Map::LruHashMap(reopen_map(bpf, BLOCK_IO_INFLIGHT_MAP)?);
+        Ok(Self {
+            map: PerCpuHashMap::try_from(map)?,
+            inflight: BpfHashMap::try_from(inflight)?,
+            sampler: QueueSampler::default(),
+            metrics,
+        })
+    }
+
+    fn sample(&mut self) -> Result<()> {
+        let devices = self.map.keys().collect::<Result<Vec<_>, _>>()?;
+        for dev in devices {
+            let values = match self.map.get(&dev, 0) {
+                Ok(values) => values,
+                Err(MapError::KeyNotFound) => continue,
+                Err(e) => return Err(e.into()),
+            };
+            let counters = values.iter().fold(BlockQueueCounters::default(), |total, cpu| {
+                BlockQueueCounters {
+                    started: total.started + cpu.started,
+                    completed: total.completed + cpu.completed,
+                    queued_ns: total.queued_ns + cpu.queued_ns,
+                }
+            });
+            self.sampler.sample(dev, counters, QUEUE_SAMPLE_PERIOD);
+        }
+        Ok(())
+    }
+
+    /// Number of requests the kernel still tracks, by device.
+    fn tracked_requests(&self) -> Result<HashMap<u32, u64>> {
+        // Keys are read while the kernel changes the map, and a removed key makes
+        // the walk start over, so the same request can come up twice
+        let keys = self.inflight.keys().collect::<Result<Vec<_>, _>>()?;
+        let requests: HashSet<(u32, u64)> = keys.iter().map(|key| (key.dev, key.sector)).collect();
+        let mut tracked = HashMap::new();
+        for (dev, _) in requests {
+            *tracked.entry(dev).or_default() += 1;
+        }
+        Ok(tracked)
+    }
+
+    fn report(&mut self, elapsed: Duration) {
+        match self.tracked_requests() {
+            Ok(tracked) => self.sampler.resync(&tracked),
+            Err(e) => warn!("Failed to read {} map: {:#}", BLOCK_IO_INFLIGHT_MAP, e),
+        }
+        let (stats, gone) = self.sampler.finish(elapsed);
+        for (dev, stats) in stats {
+            if let Err(e) = self.metrics.record_block_io_queue(&device_label(dev), &stats) {
+                warn!("Failed to record block IO queue of {}: {:#}", device_label(dev), e);
+            }
+        }
+        for dev in gone {
+            self.metrics.remove_block_io_queue(&device_label(dev));
+        }
+    }
+
+    /// Reports every `interval`, and what was sampled of the last one at shutdown.
+    pub fn spawn(mut self, events: &EventSender, interval: Duration) {
+        events.spawn_producer(move |stop| async move {
+            let mut tick = tokio::time::interval(QUEUE_SAMPLE_PERIOD);
+            let mut last_report = Instant::now();
+            loop {
+                tokio::select! {
+                    _ = tick.tick() => {}
+                    _ = stop.cancelled() => {
+                        self.report(last_report.elapsed());
+                        return;
+                    }
+                }
+                if let Err(e) = self.sample() {
+                    warn!("Failed to read {} map: {:#}", BLOCK_IO_QUEUES_MAP, e);
+                    continue;
+                }
+                let elapsed = last_report.elapsed();
+                if elapsed >= interval {
+                    self.report(elapsed);
+                    last_report = Instant::now();
+                }
+            }
+        });
+    }
+}
+
+/// Opens a map through a descriptor of its own, as `BlockIoProbe` clears the
+/// request tracking maps on attach.
+fn reopen_map(bpf: &Ebpf, name: &str) -> Result<MapData> {
+    let data = match bpf.map(name) {
+        Some(Map::PerCpuHashMap(data) | Map::LruHashMap(data)) => data,
+        _ => bail!("Failed to find {} map", name),
+    };
+    let fd = data.fd().as_fd().try_clone_to_owned()?;
+    Ok(MapData::from_fd(fd)?)
+}
+
+fn device_label(dev: u32) -> String {
+    format!("{}:{}", dev_major(dev), dev_minor(dev))
+}
+
+fn in_flight(counters: &BlockQueueCounters) -> u64 {
+    counters.started.saturating_sub(counters.completed)
+}
+
+/// Turns successive readings of the per-device counters into queue statistics.
+#[derive(Default)]
+struct QueueSampler {
+    devices: HashMap<u32, DeviceQueue>,
+}
+
+#[derive(Default)]
+struct DeviceQueue {
+    /// Counters at the latest sample.
+    last: BlockQueueCounters,
+    /// Counters at the latest report.
+    reported: BlockQueueCounters,
+    max_depth: u64,
+    /// Depth at every sample since the latest report.
+    depth_samples: Vec<u64>,
+    busy: Duration,
+    /// Requests counted as started that the kernel no longer tracks, e.g. as
+    /// they were evicted from its full map. Their completion is never counted.
+    lost: u64,
+    /// Whether the device was seen since the latest report.
+    sampled: bool,
+}
+
+impl DeviceQueue {
+    fn depth(&self, counters: &BlockQueueCounters) -> u64 {
+        in_flight(counters).saturating_sub(self.lost)
+    }
+}
+
+impl QueueSampler {
+    fn sample(&mut self, dev: u32, counters: BlockQueueCounters, period: Duration) {
+        let queue = self.devices.entry(dev).or_default();
+        // A new device, or counters cleared by a re-attach: start from this reading
+        if (!queue.sampled && queue.last == BlockQueueCounters::default())
+            || counters.started < queue.last.started
+            || counters.completed < queue.last.completed
+        {
+            *queue = DeviceQueue {
+                last: counters,
+                reported: counters,
+                max_depth: in_flight(&counters),
+                depth_samples: vec![in_flight(&counters)],
+                busy: Duration::ZERO,
+                lost: 0,
+                sampled: true,
+            };
+            return;
+        }
+
+        let before = queue.depth(&queue.last);
+        let depth = queue.depth(&counters);
+        // Busy throughout when requests were in flight at both readings; otherwise
+        // for at most as long as the requests completed in between took
+        queue.busy += if before > 0 && depth > 0 {
+            period
+        } else {
+            let queued_ns = counters.queued_ns.saturating_sub(queue.last.queued_ns);
+            Duration::from_nanos(queued_ns).min(period)
+        };
+        queue.max_depth = queue.max_depth.max(depth);
+        queue.depth_samples.push(depth);
+        queue.last = counters;
+        queue.sampled = true;
+    }
+
+    /// Writes off the requests the kernel stopped tracking, given how many it
+    /// still tracks per device. Recomputed from scratch every time, so that a
+    /// request completing in between the readings is not written off for good.
+    fn resync(&mut self, tracked: &HashMap<u32, u64>) {
+        for (dev, queue) in &mut self.devices {
+            let tracked = tracked.get(dev).copied().unwrap_or(0);
+            queue.lost = in_flight(&queue.last).saturating_sub(tracked);
+        }
+    }
+
+    /// Statistics of every device sampled over the last `interval`, and the
+    /// devices that disappeared since the previous call.
+    fn finish(&mut self, interval: Duration) -> (Vec<(u32, BlockQueueStats)>, Vec<u32>) {
+        let gone: Vec<u32> = self
+            .devices
+            .iter()
+            .filter(|(_, queue)| !queue.sampled)
+            .map(|(&dev, _)| dev)
+            .collect();
+        for dev in &gone {
+            self.devices.remove(dev);
+        }
+
+        let interval_ns = interval.as_nanos().max(1) as f64;
+        let stats = self
+            .devices
+            .iter_mut()
+            .map(|(&dev, queue)| {
+                let depth = queue.depth(&queue.last);
+                let queued_ns = queue.last.queued_ns.saturating_sub(queue.reported.queued_ns);
+                let stats = BlockQueueStats {
+                    depth,
+                    max_depth: queue.max_depth,
+                    avg_depth: queued_ns as f64 / interval_ns,
+                    utilization: (queue.busy.as_nanos() as f64 / interval_ns).min(1.0),
+                    depth_samples: std::mem::take(&mut queue.depth_samples),
+                };
+                queue.reported = queue.last;
+                queue.max_depth = depth;
+                queue.busy = Duration::ZERO;
+                queue.sampled = false;
+                (dev, stats)
+            })
+            .collect();
+        (stats, gone)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PERIOD: Duration = Duration::from_millis(100);
+
+    fn counters(started: u64, completed: u64, queued_ms: u64) -> BlockQueueCounters {
+        BlockQueueCounters {
+            started,
+            completed,
+            queued_ns: queued_ms * 1_000_000,
+        }
+    }
+
+    #[test]
+    fn test_queue_sampler() {
+        let mut sampler = QueueSampler::default();
+        // History from before the first reading does not count
+        sampler.sample(1, counters(100, 100, 5_000), PERIOD);
+        // Four requests queue up and stay in flight for two periods
+        sampler.sample(1, counters(104, 100, 5_000), PERIOD);
+        sampler.sample(1, counters(104, 100, 5_000), PERIOD);
+        sampler.sample(1, counters(104, 104, 5_600), PERIOD);
+        // One short request in an otherwise idle period
+        sampler.sample(1, counters(105, 105, 5_610), PERIOD);
+
+        let (stats, gone) = sampler.finish(PERIOD * 5);
+        assert!(gone.is_empty());
+        let [(1, stats)] = &stats[..] else {
+            panic!("unexpected devices: {:?}", stats);
+        };
+        assert_eq!(stats.depth, 0);
+        assert_eq!(stats.max_depth, 4);
+        assert!((stats.avg_depth - 1.22).abs() < 1e-9, "{}", stats.avg_depth);
+        // Two busy periods and the 10ms request: 210ms out of 500ms
+        assert!((stats.utilization - 0.42).abs() < 1e-9, "{}", stats.utilization);
+        assert_eq!(stats.depth_samples, [0, 4, 4, 0, 0]);
+
+        // Without readings the device is forgotten
+        let (stats, gone) = sampler.finish(PERIOD);
+        assert!(stats.is_empty());
+        assert_eq!(gone, [1]);
+    }
+
+    #[test]
+    fn test_queue_sampler_restarts_after_reset() {
+        let mut sampler = QueueSampler::default();
+        sampler.sample(7, counters(50, 48, 100), PERIOD);
+        sampler.sample(7, counters(52, 50, 110), PERIOD);
+        // Cleared by a re-attach, then two requests issued
+        sampler.sample(7, counters(2, 0, 0), PERIOD);
+        sampler.sample(7, counters(3, 1, 1), PERIOD);
+
+        let (stats, _) = sampler.finish(PERIOD * 2);
+        let (_, stats) = &stats[0];
+        assert_eq!(stats.depth, 2);
+        assert_eq!(stats.max_depth, 2);
+        assert!((stats.utilization - 0.5).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_queue_sampler_writes_off_evicted_requests() {
+        let mut sampler = QueueSampler::default();
+        sampler.sample(3, counters(0, 0, 0), PERIOD);
+        sampler.sample(3, counters(10, 0, 0), PERIOD);
+        // Four of the ten were evicted from the kernel's map since
+        sampler.resync(&HashMap::from([(3, 6)]));
+        let (stats, _) = sampler.finish(PERIOD * 2);
+        assert_eq!(stats[0].1.depth, 6);
+        assert_eq!(stats[0].1.max_depth, 10);
+
+        // The tracked ones complete and another starts; the evicted ones never complete
+        sampler.sample(3, counters(10, 6, 60), PERIOD);
+        sampler.sample(3, counters(11, 6, 60), PERIOD);
+        sampler.resync(&HashMap::from([(3, 1)]));
+        let (stats, _) = sampler.finish(PERIOD * 2);
+        assert_eq!(stats[0].1.depth, 1);
+        assert_eq!(stats[0].1.max_depth, 6);
+
+        // Nothing left in flight, however long it runs
+        sampler.sample(3, counters(11, 7, 65), PERIOD);
+        sampler.resync(&HashMap::new());
+        let (stats, _) = sampler.finish(PERIOD);
+        assert_eq!(stats[0].1.depth, 0);
+        assert_eq!(stats[0].1.max_depth, 1);
+    }
 }
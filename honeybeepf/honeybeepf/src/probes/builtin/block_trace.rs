@@ -0,0 +1,254 @@
+//! Tracepoints the block IO programs attach to, and where each finds the request
+//! fields in its record. `block_io_start`/`block_io_done` only exist since Linux
+//! 6.5; older kernels report the same requests through `block_rq_issue` and
+//! `block_rq_complete`, whose records are laid out differently.
+//!
+//! There is no kprobe path: a kprobe on `blk_mq_start_request` or
+//! `__blk_account_io_start` sees only a `struct request`, whose layout changes
+//! between kernels and cannot be resolved without BTF relocations. A kernel with
+//! neither pair of tracepoints is not supported.
+
+use std::{collections::HashMap, fmt};
+
+use anyhow::{Context, Result, bail, ensure};
+use honeybeepf_common::{BLOCK_FIELD_MISSING, BlockTraceLayout};
+use log::debug;
+
+use crate::probes::{
+    custom::config::{FieldLayout, read_tracepoint_format},
+    tracepoint_exists,
+};
+
+pub const CATEGORY: &str = "block";
+
+/// Start and done tracepoints of one way of following requests.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AttachPath {
+    pub start: &'static str,
+    pub done: &'static str,
+}
+
+/// Every attach path, preferred first.
+pub const ATTACH_PATHS: [AttachPath; 2] = [
+    // Accounted requests, from their allocation until they are done
+    AttachPath {
+        start: "block_io_start",
+        done: "block_io_done",
+    },
+    // Requests handed to the driver, until it completes them
+    AttachPath {
+        start: "block_rq_issue",
+        done: "block_rq_complete",
+    },
+];
+
+/// Tracepoints reporting failed requests, preferred first. `block_rq_error` only
+/// fires on failures; older kernels report them through `block_rq_complete`.
+pub const ERROR_TRACEPOINTS: [&str; 2] = ["block_rq_error", "block_rq_complete"];
+
+impl fmt::Display for AttachPath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}/{}", CATEGORY, self.start, self.done)
+    }
+}
+
+/// The tracepoints one attach selected, with the layouts of their records.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BlockTracepoints {
+    pub path: AttachPath,
+    pub start: BlockTraceLayout,
+    pub done: BlockTraceLayout,
+    /// Absent when the kernel has no tracepoint reporting failed requests.
+    pub error: Option<(&'static str, BlockTraceLayout)>,
+}
+
+impl fmt::Display for BlockTracepoints {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.path)?;
+        if let Some((name, _)) = self.error {
+            write!(f, ", errors from {}:{}", CATEGORY, name)?;
+        }
+        Ok(())
+    }
+}
+
+impl BlockTracepoints {
+    /// Picks the first attach path whose tracepoints exist and carry the fields
+    /// the programs read. Fails with the reason each path was passed over.
+    pub fn select() -> Result<Self> {
+        let mut unusable = Vec::with_capacity(ATTACH_PATHS.len());
+        for path in ATTACH_PATHS {
+            match Self::read(path) {
+                Ok(tracepoints) => return Ok(tracepoints),
+                Err(e) => {
+                    debug!("Not attaching block IO through {}: {:#}", path, e);
+                    unusable.push(format!("{} ({:#})", path, e));
+                }
+            }
+        }
+        bail!(
+            "No usable block IO tracepoints, and kprobes are not supported: {}",
+            unusable.join(", ")
+        )
+    }
+
+    fn read(path: AttachPath) -> Result<Self> {
+        for name in [path.start, path.done] {
+            ensure!(
+                tracepoint_exists(CATEGORY, name),
+                "no tracepoint {}:{}",
+                CATEGORY,
+                name
+            );
+        }
+        let start = read_layout(path.start, false)?;
+        let done = read_layout(path.done, false)?;
+        let error = ERROR_TRACEPOINTS
+            .iter()
+            .filter(|name| tracepoint_exists(CATEGORY, name))
+            .find_map(|&name| read_layout(name, true).ok().map(|layout| (name, layout)));
+        Ok(Self {
+            path,
+            start,
+            done,
+            error,
+        })
+    }
+}
+
+fn read_layout(name: &str, with_error: bool) -> Result<BlockTraceLayout> {
+    let fields = read_tracepoint_format(CATEGORY, name)?;
+    layout_from_fields(&fields, with_error)
+        .with_context(|| format!("Unexpected format of {}:{}", CATEGORY, name))
+}
+
+/// Checks that the record has every field the programs read, at the size they
+/// read it with.
+pub fn layout_from_fields(
+    fields: &HashMap<String, FieldLayout>,
+    with_error: bool,
+) -> Result<BlockTraceLayout> {
+    let field = |name: &str, size: u32| -> Result<u16> {
+        let layout = fields
+            .get(name)
+            .with_context(|| format!("no {} field", name))?;
+        ensure!(
+            layout.size >= size,
+            "{} field is {} bytes, expected {}",
+            name,
+            layout.size,
+            size
+        );
+        u16::try_from(layout.offset)
+            .ok()
+            .filter(|&offset| offset != BLOCK_FIELD_MISSING)
+            .with_context(|| format!("{} field at offset {}", name, layout.offset))
+    };
+    let optional = |name: &str, size: u32| -> Result<u16> {
+        if fields.contains_key(name) {
+            field(name, size)
+        } else {
+            Ok(BLOCK_FIELD_MISSING)
+        }
+    };
+
+    Ok(BlockTraceLayout {
+        dev: field("dev", 4)?,
+        sector: field("sector", 8)?,
+        nr_sector: field("nr_sector", 4)?,
+        bytes: optional("bytes", 4)?,
+        error: if with_error {
+            field("error", 4)?
+        } else {
+            BLOCK_FIELD_MISSING
+        },
+        rwbs: field("rwbs", 8)?,
+        comm: optional("comm", 16)?,
+        _pad: 0,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::probes::custom::config::parse_tracepoint_format;
+
+    const HEADER: &str = "format:
+\tfield:unsigned short common_type;\toffset:0;\tsize:2;\tsigned:0;
+\tfield:unsigned char common_flags;\toffset:2;\tsize:1;\tsigned:0;
+\tfield:unsigned char common_preempt_count;\toffset:3;\tsize:1;\tsigned:0;
+\tfield:int common_pid;\toffset:4;\tsize:4;\tsigned:1;
+";
+
+    /// `block_rq_issue` as of Linux 6.1.
+    const RQ_ISSUE: &str = "
+\tfield:dev_t dev;\toffset:8;\tsize:4;\tsigned:0;
+\tfield:sector_t sector;\toffset:16;\tsize:8;\tsigned:0;
+\tfield:unsigned int nr_sector;\toffset:24;\tsize:4;\tsigned:0;
+\tfield:unsigned int bytes;\toffset:28;\tsize:4;\tsigned:0;
+\tfield:char rwbs[8];\toffset:32;\tsize:8;\tsigned:0;
+\tfield:char comm[16];\toffset:40;\tsize:16;\tsigned:0;
+\tfield:__data_loc char[] cmd;\toffset:56;\tsize:4;\tsigned:0;
+";
+
+    /// `block_rq_complete` as of Linux 6.1.
+    const RQ_COMPLETE: &str = "
+\tfield:dev_t dev;\toffset:8;\tsize:4;\tsigned:0;
+\tfield:sector_t sector;\toffset:16;\tsize:8;\tsigned:0;
+\tfield:unsigned int nr_sector;\toffset:24;\tsize:4;\tsigned:0;
+\tfield:int error;\toffset:28;\tsize:4;\tsigned:1;
+\tfield:char rwbs[8];\toffset:32;\tsize:8;\tsigned:0;
+\tfield:__data_loc char[] cmd;\toffset:40;\tsize:4;\tsigned:0;
+";
+
+    /// `block_io_start` on kernels that also record the I/O priority.
+    const IO_START_IOPRIO: &str = "
+\tfield:dev_t dev;\toffset:8;\tsize:4;\tsigned:0;
+\tfield:sector_t sector;\toffset:16;\tsize:8;\tsigned:0;
+\tfield:unsigned int nr_sector;\toffset:24;\tsize:4;\tsigned:0;
+\tfield:unsigned int bytes;\toffset:28;\tsize:4;\tsigned:0;
+\tfield:unsigned short ioprio;\toffset:32;\tsize:2;\tsigned:0;
+\tfield:char rwbs[8];\toffset:34;\tsize:8;\tsigned:0;
+\tfield:char comm[16];\toffset:42;\tsize:16;\tsigned:0;
+\tfield:__data_loc char[] cmd;\toffset:60;\tsize:4;\tsigned:0;
+";
+
+    fn layout(fields: &str, with_error: bool) -> Result<BlockTraceLayout> {
+        let format = format!("name: test\n{}{}", HEADER, fields);
+        layout_from_fields(&parse_tracepoint_format(&format), with_error)
+    }
+
+    #[test]
+    fn test_layouts_follow_format() {
+        let issue = layout(RQ_ISSUE, false).unwrap();
+        assert_eq!((issue.dev, issue.sector, issue.nr_sector), (8, 16, 24));
+        assert_eq!((issue.bytes, issue.rwbs, issue.comm), (28, 32, 40));
+        assert_eq!(issue.error, BLOCK_FIELD_MISSING);
+
+        let complete = layout(RQ_COMPLETE, true).unwrap();
+        assert_eq!((complete.error, complete.rwbs), (28, 32));
+        assert_eq!(complete.bytes, BLOCK_FIELD_MISSING);
+        assert_eq!(complete.comm, BLOCK_FIELD_MISSING);
+
+        let start = layout(IO_START_IOPRIO, false).unwrap();
+        assert_eq!((start.rwbs, start.comm), (34, 42));
+    }
+
+    #[test]
+    fn test_layout_rejects_missing_fields() {
+        // Errors are only read from the completion records
+        assert!(layout(RQ_ISSUE, true).is_err());
+        assert!(
+            layout(
+                &RQ_COMPLETE.replace("sector_t sector", "sector_t lba"),
+                true
+            )
+            .is_err()
+        );
+        let short_rwbs = RQ_ISSUE.replace(
+            "rwbs[8];\toffset:32;\tsize:8",
+            "rwbs[4];\toffset:32;\tsize:4",
+        );
+        assert!(layout(&short_rwbs, false).is_err());
+    }
+}
@@ -4,7 +4,13 @@ use honeybeepf_common::GpuOpenEvent;
 use log::info;
 use std::fs;
 
-use crate::probes::{attach_tracepoint, spawn_ringbuf_handler, Probe, TracepointConfig};
+use crate::events::{c_str, GpuOpen, HoneyBeeEvent};
+use crate::pipeline::EventSender;
+use crate::probes::{
+    attach_tracepoint, detach_program, program_status, spawn_ringbuf_handler, KernelFeature,
+    Probe, ProbeStatus, TracepointConfig,
+};
+use crate::settings::Settings;
 
 fn get_process_name(pid: u32) -> String {
     fs::read_to_string(format!("/proc/{}/comm", pid))
@@ -12,56 +18,95 @@ fn get_process_name(pid: u32) -> String {
         .unwrap_or_else(|_| "<unknown>".to_string())
 }
 
+fn decode(event: &GpuOpenEvent) -> GpuOpen {
+    let comm = match c_str(&event.comm) {
+        "" => get_process_name(event.metadata.pid),
+        event_comm => event_comm.to_string(),
+    };
+
+    let filename = c_str(&event.filename);
+
+    let gpu_type = if filename.starts_with("/dev/nvidia") {
+        "NVIDIA"
+    } else if filename.starts_with("/dev/dri/") {
+        "DRI"
+    } else {
+        "Unknown"
+    };
+
+    GpuOpen {
+        metadata: (&event.metadata).into(),
+        gpu_index: event.gpu_index,
+        gpu_type,
+        flags: event.flags,
+        comm,
+        filename: filename.to_string(),
+    }
+}
+
+const PROGRAM: &str = "honeybeepf_gpu_open_enter";
+const RING_BUFFER: &str = "GPU_OPEN_EVENTS";
+
+const REQUIRED_FEATURES: [KernelFeature; 2] = [
+    KernelFeature::RingBuf,
+    KernelFeature::Tracepoint {
+        category: "syscalls",
+        name: "sys_enter_openat",
+    },
+];
+
 pub struct GpuOpenProbe;
 
 impl Probe for GpuOpenProbe {
-    fn attach(&self, bpf: &mut Ebpf) -> Result<()> {
+    fn name(&self) -> &'static str {
+        "gpu_open"
+    }
+
+    fn description(&self) -> &'static str {
+        "Processes opening NVIDIA and DRI GPU device files"
+    }
+
+    fn config_section(&self) -> &'static str {
+        "builtin_probes.gpu_open"
+    }
+
+    fn required_features(&self) -> &[KernelFeature] {
+        &REQUIRED_FEATURES
+    }
+
+    fn enabled(&self, settings: &Settings) -> bool {
+        settings.builtin_probes.gpu_open.unwrap_or(false)
+    }
+
+    fn ring_buffer(&self) -> &'static str {
+        RING_BUFFER
+    }
+
+    fn attach(&self, bpf: &mut Ebpf, events: &EventSender) -> Result<()> {
         info!("Attaching GPU open probes...");
 
         attach_tracepoint(
             bpf,
             TracepointConfig {
-                program_name: "honeybeepf_gpu_open_enter",
+                program_name: PROGRAM,
                 category: "syscalls",
                 name: "sys_enter_openat",
             },
         )?;
 
-        spawn_ringbuf_handler(bpf, "GPU_OPEN_EVENTS", |event: GpuOpenEvent| {
-            let comm = {
-                let event_comm = std::str::from_utf8(&event.comm)
-                    .unwrap_or("")
-                    .trim_matches(char::from(0));
-                if event_comm.is_empty() {
-                    get_process_name(event.metadata.pid)
-                } else {
-                    event_comm.to_string()
-                }
-            };
-
-            let filename = std::str::from_utf8(&event.filename)
-                .unwrap_or("<invalid>")
-                .trim_matches(char::from(0));
-
-            let gpu_type = if filename.starts_with("/dev/nvidia") {
-                "NVIDIA"
-            } else if filename.starts_with("/dev/dri/") {
-                "DRI"
-            } else {
-                "Unknown"
-            };
-
-            info!(
-                "GPU_OPEN pid={} comm={} gpu_index={} type={} file={} cgroup_id={}",
-                event.metadata.pid,
-                comm,
-                event.gpu_index,
-                gpu_type,
-                filename,
-                event.metadata.cgroup_id,
-            );
+        let sender = events.clone();
+        spawn_ringbuf_handler(bpf, events, RING_BUFFER, move |event: GpuOpenEvent| {
+            sender.send(HoneyBeeEvent::GpuOpen(decode(&event)));
         })?;
 
         Ok(())
     }
+
+    fn detach(&self, bpf: &mut Ebpf) -> Result<()> {
+        detach_program(bpf, PROGRAM)
+    }
+
+    fn status(&self, bpf: &Ebpf) -> ProbeStatus {
+        program_status(bpf, &[PROGRAM])
+    }
 }
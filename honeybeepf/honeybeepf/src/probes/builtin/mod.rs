@@ -1,3 +1,18 @@
 pub mod network;
+pub mod block_device;
 pub mod block_io;
+pub mod block_trace;
 pub mod gpu_open;
+pub mod tcp;
+pub mod tcp_traffic;
+
+use crate::probes::ProbeRegistry;
+
+/// Adds every builtin probe to `registry`; a new probe only needs a line here.
+pub fn register(registry: &mut ProbeRegistry) {
+    registry.register(network::NetworkLatencyProbe);
+    registry.register(tcp::TcpRetransmitProbe::default());
+    registry.register(tcp_traffic::TcpTrafficProbe);
+    registry.register(block_io::BlockIoProbe::new());
+    registry.register(gpu_open::GpuOpenProbe);
+}
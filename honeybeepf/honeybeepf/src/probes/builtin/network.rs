@@ -1,36 +1,144 @@
-use std::net::Ipv4Addr;
-
-use anyhow::Result;
+use anyhow::{Result, ensure};
 use aya::Ebpf;
-use honeybeepf_common::ConnectionEvent;
-use log::info;
+use honeybeepf_common::{ConnectionEvent, TcpProgram};
+use log::{info, warn};
+
+use crate::events::{Connection, HoneyBeeEvent};
+use crate::pipeline::EventSender;
+use crate::probes::builtin::tcp::{read_layout, write_layout};
+use crate::probes::{
+    attach_tracepoint, detach_program, program_status, spawn_ringbuf_handler, tracepoint_exists,
+    KernelFeature, Probe, ProbeStatus, TracepointConfig,
+};
+use crate::settings::Settings;
+
+const PROGRAM: &str = "honeybeepf";
+const EXIT_PROGRAM: &str = "honeybeepf_connect_exit";
+const RING_BUFFER: &str = "NETWORK_EVENTS";
 
-use crate::probes::{attach_tracepoint, spawn_ringbuf_handler, Probe, TracepointConfig};
+/// Programs following TCP handshakes past a non-blocking connect(), with their
+/// tracepoints and the `TcpProgram` layout they read the record through, if
+/// any. Without them such connects are reported as EINPROGRESS.
+const HANDSHAKE_PROGRAMS: [(&str, TracepointConfig, Option<TcpProgram>); 2] = [
+    (
+        "handshake state changes",
+        TracepointConfig {
+            program_name: "honeybeepf_connect_state",
+            category: "sock",
+            name: "inet_sock_set_state",
+        },
+        None,
+    ),
+    (
+        "refused handshakes",
+        TracepointConfig {
+            program_name: "honeybeepf_connect_reset",
+            category: "tcp",
+            name: "tcp_receive_reset",
+        },
+        Some(TcpProgram::ReceiveReset),
+    ),
+];
+
+const REQUIRED_FEATURES: [KernelFeature; 3] = [
+    KernelFeature::RingBuf,
+    KernelFeature::Tracepoint {
+        category: "syscalls",
+        name: "sys_enter_connect",
+    },
+    KernelFeature::Tracepoint {
+        category: "syscalls",
+        name: "sys_exit_connect",
+    },
+];
 
 pub struct NetworkLatencyProbe;
 
 impl Probe for NetworkLatencyProbe {
-    fn attach(&self, bpf: &mut Ebpf) -> Result<()> {
+    fn name(&self) -> &'static str {
+        "network_latency"
+    }
+
+    fn description(&self) -> &'static str {
+        "Outgoing connect() calls, their destinations, latency and outcome"
+    }
+
+    fn config_section(&self) -> &'static str {
+        "builtin_probes.network_latency"
+    }
+
+    fn required_features(&self) -> &[KernelFeature] {
+        &REQUIRED_FEATURES
+    }
+
+    fn enabled(&self, settings: &Settings) -> bool {
+        settings.builtin_probes.network_latency.unwrap_or(false)
+    }
+
+    fn ring_buffer(&self) -> &'static str {
+        RING_BUFFER
+    }
+
+    fn attach(&self, bpf: &mut Ebpf, events: &EventSender) -> Result<()> {
         info!("Attaching network latency probes...");
-        attach_tracepoint(
-            bpf,
-            TracepointConfig {
-                program_name: "honeybeepf",
-                category: "syscalls",
-                name: "sys_enter_connect",
-            },
-        )?;
-        
-        spawn_ringbuf_handler(bpf, "NETWORK_EVENTS", |event: ConnectionEvent| {
-            let dest_ip = Ipv4Addr::from(u32::from_be(event.dest_addr));
-            let dest_port = u16::from_be(event.dest_port);
-
-            info!(
-                "PID {} connecting to {}:{} (cgroup_id={}, ts={})",
-                event.metadata.pid, dest_ip, dest_port, event.metadata.cgroup_id, event.metadata.timestamp
-            );
+        let syscall_programs = [
+            (PROGRAM, "sys_enter_connect"),
+            (EXIT_PROGRAM, "sys_exit_connect"),
+        ];
+        for (program, name) in syscall_programs {
+            let attached = attach_tracepoint(
+                bpf,
+                TracepointConfig {
+                    program_name: program,
+                    category: "syscalls",
+                    name,
+                },
+            )?;
+            ensure!(attached, "Tracepoint syscalls:{} disappeared", name);
+        }
+        for (what, config, layout) in HANDSHAKE_PROGRAMS {
+            if let Some(program) = layout
+                && tracepoint_exists(config.category, config.name)
+            {
+                match read_layout(config.category, config.name) {
+                    Ok(layout) => write_layout(bpf, program, layout)?,
+                    Err(e) => {
+                        warn!("Not following {}: {:#}", what, e);
+                        continue;
+                    }
+                }
+            }
+            if !attach_tracepoint(bpf, config)? {
+                warn!("Not following {}; non-blocking connects may report EINPROGRESS", what);
+            }
+        }
+
+        let sender = events.clone();
+        spawn_ringbuf_handler(bpf, events, RING_BUFFER, move |event: ConnectionEvent| {
+            sender.send(HoneyBeeEvent::Connection(Connection::from(&event)));
         })?;
-        
+
         Ok(())
     }
+
+    fn detach(&self, bpf: &mut Ebpf) -> Result<()> {
+        detach_program(bpf, PROGRAM)?;
+        detach_program(bpf, EXIT_PROGRAM)?;
+        for (_, config, _) in HANDSHAKE_PROGRAMS {
+            detach_program(bpf, config.program_name)?;
+        }
+        Ok(())
+    }
+
+    fn status(&self, bpf: &Ebpf) -> ProbeStatus {
+        // Handshake programs only count where the kernel lets them attach
+        let mut programs = vec![PROGRAM, EXIT_PROGRAM];
+        programs.extend(
+            HANDSHAKE_PROGRAMS
+                .iter()
+                .filter(|(_, config, _)| tracepoint_exists(config.category, config.name))
+                .map(|(_, config, _)| config.program_name),
+        );
+        program_status(bpf, &programs)
+    }
 }
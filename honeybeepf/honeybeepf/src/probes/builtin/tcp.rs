@@ -0,0 +1,342 @@
+//! Retransmits and resets on TCP sockets. The tracepoints recording them have
+//! moved their fields between kernel versions, so the programs read the socket
+//! fields at offsets taken from the tracefs format.
+//!
+//! Events carry the task that connected or accepted the socket. To check an
+//! accepted socket, drop packets on loopback (`tc qdisc add dev lo root netem
+//! loss 30%`), run a server that accepts and writes to a client, and check
+//! that its retransmits report the server's pid.
+
+use std::{collections::HashMap, sync::Mutex};
+
+use anyhow::{Context, Result, ensure};
+use aya::{Ebpf, maps::Array};
+use honeybeepf_common::{
+    TCP_FIELD_MISSING, TCP_TRACE_LAYOUTS_MAP, TcpEvent, TcpProgram, TcpTraceLayout,
+};
+use log::{info, warn};
+
+use crate::{
+    events::{HoneyBeeEvent, Tcp},
+    pipeline::EventSender,
+    probes::{
+        KernelFeature, Probe, ProbeStatus, TracepointConfig, attach_kprobe, attach_tracepoint,
+        custom::config::{FieldLayout, read_tracepoint_format},
+        detach_program, program_status, spawn_ringbuf_handler, tracepoint_exists,
+    },
+    settings::Settings,
+};
+
+const CATEGORY: &str = "tcp";
+const RING_BUFFER: &str = "TCP_EVENTS";
+const OWNER_PROGRAM: &str = "honeybeepf_tcp_owner";
+/// Learns the owner of accepted sockets, whose handshake ran in no task.
+const ACCEPT_PROGRAM: &str = "honeybeepf_tcp_accept";
+const ACCEPT_FUNCTION: &str = "inet_csk_accept";
+
+/// Programs reading socket records, with their tracepoints. Only retransmits are
+/// required; resets are reported where the kernel has their tracepoints.
+const SOCKET_PROGRAMS: [(TcpProgram, &str, &str); 3] = [
+    (
+        TcpProgram::Retransmit,
+        "honeybeepf_tcp_retransmit",
+        "tcp_retransmit_skb",
+    ),
+    (
+        TcpProgram::SendReset,
+        "honeybeepf_tcp_send_reset",
+        "tcp_send_reset",
+    ),
+    (
+        TcpProgram::ReceiveReset,
+        "honeybeepf_tcp_receive_reset",
+        "tcp_receive_reset",
+    ),
+];
+
+/// Where the owner of each socket is learned.
+const OWNER_TRACEPOINT: TracepointConfig<'static> = TracepointConfig {
+    program_name: OWNER_PROGRAM,
+    category: "sock",
+    name: "inet_sock_set_state",
+};
+
+const REQUIRED_FEATURES: [KernelFeature; 2] = [
+    KernelFeature::RingBuf,
+    KernelFeature::Tracepoint {
+        category: CATEGORY,
+        name: "tcp_retransmit_skb",
+    },
+];
+
+#[derive(Default)]
+pub struct TcpRetransmitProbe {
+    /// Programs attached, while attached.
+    programs: Mutex<Option<Vec<&'static str>>>,
+}
+
+impl Probe for TcpRetransmitProbe {
+    fn name(&self) -> &'static str {
+        "tcp_retransmit"
+    }
+
+    fn description(&self) -> &'static str {
+        "TCP retransmits and resets per connection"
+    }
+
+    fn config_section(&self) -> &'static str {
+        "builtin_probes.tcp_retransmit"
+    }
+
+    fn required_features(&self) -> &[KernelFeature] {
+        &REQUIRED_FEATURES
+    }
+
+    fn enabled(&self, settings: &Settings) -> bool {
+        settings.builtin_probes.tcp_retransmit.unwrap_or(false)
+    }
+
+    fn ring_buffer(&self) -> &'static str {
+        RING_BUFFER
+    }
+
+    fn attach(&self, bpf: &mut Ebpf, events: &EventSender) -> Result<()> {
+        info!("Attaching TCP retransmit probes...");
+        let mut attached = Vec::new();
+        for (program, program_name, name) in SOCKET_PROGRAMS {
+            let required = program == TcpProgram::Retransmit;
+            if !required && !tracepoint_exists(CATEGORY, name) {
+                warn!(
+                    "Tracepoint {}:{} not available; not reporting it",
+                    CATEGORY, name
+                );
+                continue;
+            }
+            let layout = match read_layout(CATEGORY, name) {
+                Ok(layout) => layout,
+                Err(e) if !required => {
+                    warn!("Not reporting {}:{}: {:#}", CATEGORY, name, e);
+                    continue;
+                }
+                Err(e) => return Err(e),
+            };
+            write_layout(bpf, program, layout)?;
+            let config = TracepointConfig {
+                program_name,
+                category: CATEGORY,
+                name,
+            };
+            ensure!(
+                attach_tracepoint(bpf, config)?,
+                "Tracepoint {}:{} disappeared",
+                CATEGORY,
+                name
+            );
+            attached.push(program_name);
+        }
+        // Closing sockets report the retransmits still throttled on them
+        if tracepoint_exists(OWNER_TRACEPOINT.category, OWNER_TRACEPOINT.name) {
+            let layout = read_layout(OWNER_TRACEPOINT.category, OWNER_TRACEPOINT.name)?;
+            write_layout(bpf, TcpProgram::SetState, layout)?;
+        }
+        if attach_tracepoint(bpf, OWNER_TRACEPOINT)? {
+            attached.push(OWNER_PROGRAM);
+        } else {
+            warn!("Socket owners unknown; TCP events carry no pid or cgroup");
+        }
+        match attach_kprobe(bpf, ACCEPT_PROGRAM, ACCEPT_FUNCTION, 0) {
+            Ok(()) => attached.push(ACCEPT_PROGRAM),
+            Err(e) => warn!(
+                "Owners of accepted sockets unknown; their TCP events carry no pid or cgroup: {:#}",
+                e
+            ),
+        }
+        *self.programs.lock().unwrap() = Some(attached);
+
+        let sender = events.clone();
+        spawn_ringbuf_handler(bpf, events, RING_BUFFER, move |event: TcpEvent| {
+            sender.send(HoneyBeeEvent::Tcp(Tcp::from(&event)));
+        })?;
+
+        Ok(())
+    }
+
+    fn detach(&self, bpf: &mut Ebpf) -> Result<()> {
+        for (_, program_name, _) in SOCKET_PROGRAMS {
+            detach_program(bpf, program_name)?;
+        }
+        detach_program(bpf, OWNER_PROGRAM)?;
+        detach_program(bpf, ACCEPT_PROGRAM)?;
+        *self.programs.lock().unwrap() = None;
+        Ok(())
+    }
+
+    fn status(&self, bpf: &Ebpf) -> ProbeStatus {
+        match self.programs.lock().unwrap().as_deref() {
+            Some(attached) => program_status(bpf, attached),
+            None => {
+                let mut programs: Vec<&str> = SOCKET_PROGRAMS
+                    .iter()
+                    .map(|&(_, program_name, _)| program_name)
+                    .collect();
+                programs.extend([OWNER_PROGRAM, ACCEPT_PROGRAM]);
+                program_status(bpf, &programs)
+            }
+        }
+    }
+}
+
+/// Tells `program` where its tracepoint keeps the socket fields.
+pub fn write_layout(bpf: &mut Ebpf, program: TcpProgram, layout: TcpTraceLayout) -> Result<()> {
+    let mut layouts: Array<_, TcpTraceLayout> = Array::try_from(
+        bpf.map_mut(TCP_TRACE_LAYOUTS_MAP)
+            .with_context(|| format!("Failed to find {} map", TCP_TRACE_LAYOUTS_MAP))?,
+    )?;
+    layouts.set(program as u32, layout, 0)?;
+    Ok(())
+}
+
+pub fn read_layout(category: &str, name: &str) -> Result<TcpTraceLayout> {
+    let fields = read_tracepoint_format(category, name)?;
+    layout_from_fields(&fields)
+        .with_context(|| format!("Unexpected format of {}:{}", category, name))
+}
+
+/// Size of the `sockaddr_in6` newer tracepoints record addresses in.
+const SOCKADDR_IN6_SIZE: u32 = 28;
+
+/// Checks that the record has every field the programs read, at the size they
+/// read it with.
+pub fn layout_from_fields(fields: &HashMap<String, FieldLayout>) -> Result<TcpTraceLayout> {
+    let field = |name: &str, size: u32| -> Result<u16> {
+        let layout = fields
+            .get(name)
+            .with_context(|| format!("no {} field", name))?;
+        ensure!(
+            layout.size >= size,
+            "{} field is {} bytes, expected {}",
+            name,
+            layout.size,
+            size
+        );
+        u16::try_from(layout.offset)
+            .ok()
+            .filter(|&offset| offset != TCP_FIELD_MISSING)
+            .with_context(|| format!("{} field at offset {}", name, layout.offset))
+    };
+
+    let skaddr = field("skaddr", 8)?;
+    let state = if fields.contains_key("state") {
+        field("state", 4)?
+    } else {
+        TCP_FIELD_MISSING
+    };
+    if fields.contains_key("saddr_v6") {
+        return Ok(TcpTraceLayout {
+            skaddr,
+            state,
+            sport: field("sport", 2)?,
+            dport: field("dport", 2)?,
+            family: field("family", 2)?,
+            saddr: field("saddr_v6", 16)?,
+            daddr: field("daddr_v6", 16)?,
+            sockaddr: 0,
+        });
+    }
+    Ok(TcpTraceLayout {
+        skaddr,
+        state,
+        sport: TCP_FIELD_MISSING,
+        dport: TCP_FIELD_MISSING,
+        family: TCP_FIELD_MISSING,
+        saddr: field("saddr", SOCKADDR_IN6_SIZE)?,
+        daddr: field("daddr", SOCKADDR_IN6_SIZE)?,
+        sockaddr: 1,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::probes::custom::config::parse_tracepoint_format;
+
+    const HEADER: &str = "format:
+\tfield:unsigned short common_type;\toffset:0;\tsize:2;\tsigned:0;
+\tfield:unsigned char common_flags;\toffset:2;\tsize:1;\tsigned:0;
+\tfield:unsigned char common_preempt_count;\toffset:3;\tsize:1;\tsigned:0;
+\tfield:int common_pid;\toffset:4;\tsize:4;\tsigned:1;
+";
+
+    /// `tcp_retransmit_skb` as of Linux 6.1.
+    const RETRANSMIT_SKB: &str = "
+\tfield:const void * skbaddr;\toffset:8;\tsize:8;\tsigned:0;
+\tfield:const void * skaddr;\toffset:16;\tsize:8;\tsigned:0;
+\tfield:int state;\toffset:24;\tsize:4;\tsigned:1;
+\tfield:__u16 sport;\toffset:28;\tsize:2;\tsigned:0;
+\tfield:__u16 dport;\toffset:30;\tsize:2;\tsigned:0;
+\tfield:__u16 family;\toffset:32;\tsize:2;\tsigned:0;
+\tfield:__u8 saddr[4];\toffset:34;\tsize:4;\tsigned:0;
+\tfield:__u8 daddr[4];\toffset:38;\tsize:4;\tsigned:0;
+\tfield:__u8 saddr_v6[16];\toffset:42;\tsize:16;\tsigned:0;
+\tfield:__u8 daddr_v6[16];\toffset:58;\tsize:16;\tsigned:0;
+";
+
+    /// `tcp_receive_reset` as of Linux 6.1, which has no state.
+    const RECEIVE_RESET: &str = "
+\tfield:const void * skaddr;\toffset:8;\tsize:8;\tsigned:0;
+\tfield:__u16 sport;\toffset:16;\tsize:2;\tsigned:0;
+\tfield:__u16 dport;\toffset:18;\tsize:2;\tsigned:0;
+\tfield:__u16 family;\toffset:20;\tsize:2;\tsigned:0;
+\tfield:__u8 saddr[4];\toffset:22;\tsize:4;\tsigned:0;
+\tfield:__u8 daddr[4];\toffset:26;\tsize:4;\tsigned:0;
+\tfield:__u8 saddr_v6[16];\toffset:30;\tsize:16;\tsigned:0;
+\tfield:__u8 daddr_v6[16];\toffset:46;\tsize:16;\tsigned:0;
+\tfield:__u64 sock_cookie;\toffset:64;\tsize:8;\tsigned:0;
+";
+
+    /// `tcp_send_reset` since Linux 6.10, with sockaddr addresses.
+    const SEND_RESET_SOCKADDR: &str = "
+\tfield:const void * skbaddr;\toffset:8;\tsize:8;\tsigned:0;
+\tfield:const void * skaddr;\toffset:16;\tsize:8;\tsigned:0;
+\tfield:int state;\toffset:24;\tsize:4;\tsigned:1;
+\tfield:enum sk_rst_reason reason;\toffset:28;\tsize:4;\tsigned:0;
+\tfield:__u8 saddr[sizeof(struct sockaddr_in6)];\toffset:32;\tsize:28;\tsigned:0;
+\tfield:__u8 daddr[sizeof(struct sockaddr_in6)];\toffset:60;\tsize:28;\tsigned:0;
+";
+
+    fn layout(fields: &str) -> Result<TcpTraceLayout> {
+        let format = format!("name: test\n{}{}", HEADER, fields);
+        layout_from_fields(&parse_tracepoint_format(&format))
+    }
+
+    #[test]
+    fn test_layouts_follow_format() {
+        let retransmit = layout(RETRANSMIT_SKB).unwrap();
+        assert_eq!((retransmit.skaddr, retransmit.state), (16, 24));
+        assert_eq!(
+            (retransmit.sport, retransmit.dport, retransmit.family),
+            (28, 30, 32)
+        );
+        assert_eq!(
+            (retransmit.saddr, retransmit.daddr, retransmit.sockaddr),
+            (42, 58, 0)
+        );
+
+        let reset = layout(RECEIVE_RESET).unwrap();
+        assert_eq!((reset.skaddr, reset.state), (8, TCP_FIELD_MISSING));
+        assert_eq!((reset.saddr, reset.daddr), (30, 46));
+
+        let reset = layout(SEND_RESET_SOCKADDR).unwrap();
+        assert_eq!((reset.skaddr, reset.state), (16, 24));
+        assert_eq!((reset.saddr, reset.daddr, reset.sockaddr), (32, 60, 1));
+        assert_eq!(reset.sport, TCP_FIELD_MISSING);
+    }
+
+    #[test]
+    fn test_layout_rejects_missing_fields() {
+        assert!(layout(&RETRANSMIT_SKB.replace("skaddr", "sk")).is_err());
+        // IPv4-only addresses without the v6 copies are not enough
+        assert!(layout(&RECEIVE_RESET.replace("saddr_v6", "saddr6")).is_err());
+        assert!(layout(&RETRANSMIT_SKB.replace("__u16 family", "__u16 af")).is_err());
+    }
+}
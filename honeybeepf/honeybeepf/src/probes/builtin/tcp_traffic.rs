@@ -0,0 +1,427 @@
+//! Bytes sent and received on TCP connections, summarized per cgroup and peer.
+//! Connections are picked up when they are established, so those already open
+//! when the probe attaches are not counted.
+
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use anyhow::{Context, Result, ensure};
+use aya::{
+    Ebpf,
+    maps::{HashMap as BpfHashMap, MapData, MapError},
+};
+use honeybeepf_common::{TCP_CONNECTIONS_MAP, TcpConnection, TcpProgram};
+use log::{info, warn};
+
+use crate::{
+    events::{HoneyBeeEvent, Metadata, TcpTraffic, ipv6_socket_addr},
+    pipeline::EventSender,
+    probes::{
+        KernelFeature, Probe, ProbeStatus, TracepointConfig, attach_kprobe, attach_tracepoint,
+        builtin::tcp, detach_program, program_status, spawn_ringbuf_handler,
+    },
+    settings::Settings,
+    telemetry::unix_now_nanos,
+};
+
+const RING_BUFFER: &str = "TCP_TRAFFIC_EVENTS";
+
+/// Where connections are picked up when established and reported once closed.
+const STATE_TRACEPOINT: TracepointConfig<'static> = TracepointConfig {
+    program_name: "honeybeepf_tcp_traffic_state",
+    category: "sock",
+    name: "inet_sock_set_state",
+};
+
+/// Programs counting bytes, with the kernel functions they attach to.
+const KPROBES: [(&str, &str); 3] = [
+    ("honeybeepf_tcp_sendmsg", "tcp_sendmsg"),
+    ("honeybeepf_tcp_sendmsg_return", "tcp_sendmsg"),
+    ("honeybeepf_tcp_cleanup_rbuf", "tcp_cleanup_rbuf"),
+];
+
+const PROGRAMS: [&str; 4] = [
+    STATE_TRACEPOINT.program_name,
+    KPROBES[0].0,
+    KPROBES[1].0,
+    KPROBES[2].0,
+];
+
+const REQUIRED_FEATURES: [KernelFeature; 3] = [
+    KernelFeature::RingBuf,
+    KernelFeature::Kprobes,
+    KernelFeature::Tracepoint {
+        category: STATE_TRACEPOINT.category,
+        name: STATE_TRACEPOINT.name,
+    },
+];
+
+pub struct TcpTrafficProbe;
+
+impl Probe for TcpTrafficProbe {
+    fn name(&self) -> &'static str {
+        "tcp_traffic"
+    }
+
+    fn description(&self) -> &'static str {
+        "Bytes sent and received over TCP per cgroup and peer"
+    }
+
+    fn config_section(&self) -> &'static str {
+        "builtin_probes.tcp_traffic"
+    }
+
+    fn required_features(&self) -> &[KernelFeature] {
+        &REQUIRED_FEATURES
+    }
+
+    fn enabled(&self, settings: &Settings) -> bool {
+        settings.builtin_probes.tcp_traffic.unwrap_or(false)
+    }
+
+    /// Read by `TcpTrafficReports` rather than by the probe, so that closed
+    /// connections and open ones are summarized together.
+    fn ring_buffer(&self) -> &'static str {
+        RING_BUFFER
+    }
+
+    fn attach(&self, bpf: &mut Ebpf, _events: &EventSender) -> Result<()> {
+        info!("Attaching TCP traffic probes...");
+        let layout = tcp::read_layout(STATE_TRACEPOINT.category, STATE_TRACEPOINT.name)?;
+        tcp::write_layout(bpf, TcpProgram::SetState, layout)?;
+        ensure!(
+            attach_tracepoint(bpf, STATE_TRACEPOINT)?,
+            "Tracepoint {}:{} disappeared",
+            STATE_TRACEPOINT.category,
+            STATE_TRACEPOINT.name
+        );
+        for (program_name, function) in KPROBES {
+            attach_kprobe(bpf, program_name, function, 0)?;
+        }
+        Ok(())
+    }
+
+    fn detach(&self, bpf: &mut Ebpf) -> Result<()> {
+        for program_name in PROGRAMS {
+            detach_program(bpf, program_name)?;
+        }
+        Ok(())
+    }
+
+    fn status(&self, bpf: &Ebpf) -> ProbeStatus {
+        program_status(bpf, &PROGRAMS)
+    }
+}
+
+/// Publishes what every cgroup exchanged with each peer, from the connections
+/// still open in the kernel's map and those reported closed through the ringbuf.
+pub struct TcpTrafficReports {
+    connections: BpfHashMap<MapData, u64, TcpConnection>,
+    ledger: Arc<Mutex<TrafficLedger>>,
+}
+
+impl TcpTrafficReports {
+    pub fn new(bpf: &mut Ebpf) -> Result<Self> {
+        let map = bpf
+            .take_map(TCP_CONNECTIONS_MAP)
+            .with_context(|| format!("Failed to find {} map", TCP_CONNECTIONS_MAP))?;
+        Ok(Self {
+            connections: BpfHashMap::try_from(map)?,
+            ledger: Arc::default(),
+        })
+    }
+
+    /// Reports every `interval`, and once more at shutdown.
+    pub fn spawn(mut self, bpf: &mut Ebpf, events: &EventSender, interval: Duration) -> Result<()> {
+        let ledger = self.ledger.clone();
+        spawn_ringbuf_handler(
+            bpf,
+            events,
+            RING_BUFFER,
+            move |connection: TcpConnection| {
+                ledger.lock().unwrap().account(&connection, true);
+            },
+        )?;
+
+        let sender = events.clone();
+        events.spawn_producer(move |stop| async move {
+            let mut tick = tokio::time::interval(interval);
+            // The first tick completes immediately
+            tick.tick().await;
+            loop {
+                let stopping = tokio::select! {
+                    _ = tick.tick() => false,
+                    _ = stop.cancelled() => true,
+                };
+                if let Err(e) = self.scan() {
+                    warn!("Failed to read {} map: {:#}", TCP_CONNECTIONS_MAP, e);
+                }
+                let traffic = self.ledger.lock().unwrap().report(unix_now_nanos());
+                for traffic in traffic {
+                    sender.send(HoneyBeeEvent::TcpTraffic(traffic));
+                }
+                if stopping {
+                    return;
+                }
+            }
+        });
+        Ok(())
+    }
+
+    fn scan(&mut self) -> Result<()> {
+        let sockets = self.connections.keys().collect::<Result<Vec<_>, _>>()?;
+        let mut open = Vec::with_capacity(sockets.len());
+        for sk in sockets {
+            match self.connections.get(&sk, 0) {
+                Ok(connection) => open.push(connection),
+                // Closed since the keys were read
+                Err(MapError::KeyNotFound) => continue,
+                Err(e) => return Err(e.into()),
+            }
+        }
+        self.ledger.lock().unwrap().scan(open);
+        Ok(())
+    }
+}
+
+/// Turns readings of open connections and reports of closed ones into bytes per
+/// cgroup and peer, counting each byte once.
+#[derive(Default)]
+struct TrafficLedger {
+    /// What was counted so far of each connection, by socket and establishment
+    /// time, as sockets are reused.
+    connections: HashMap<(u64, u64), Counted>,
+    totals: HashMap<(u64, IpAddr), Totals>,
+}
+
+#[derive(Default)]
+struct Counted {
+    sent: u64,
+    received: u64,
+    closed: bool,
+    /// Whether the connection was seen since the latest scan.
+    seen: bool,
+    /// Whether the latest scan missed the connection. Its close may still be on
+    /// the way through the ringbuf, so it is only forgotten as evicted once the
+    /// next scan misses it too.
+    missed: bool,
+}
+
+#[derive(Default)]
+struct Totals {
+    sent: u64,
+    received: u64,
+    closed: u64,
+}
+
+impl TrafficLedger {
+    /// Counts what `connection` moved since it was last seen.
+    fn account(&mut self, connection: &TcpConnection, closed: bool) {
+        let key = (connection.sk, connection.metadata.timestamp);
+        let counted = self.connections.entry(key).or_default();
+        // A reading of the map taken before the close was reported
+        if counted.closed {
+            return;
+        }
+        let sent = connection.sent_bytes.saturating_sub(counted.sent);
+        let received = connection.received_bytes.saturating_sub(counted.received);
+        counted.sent += sent;
+        counted.received += received;
+        counted.closed = closed;
+        counted.seen = true;
+        if sent == 0 && received == 0 && !closed {
+            return;
+        }
+
+        let peer = ipv6_socket_addr(connection.daddr, 0, 0).ip();
+        let totals = self
+            .totals
+            .entry((connection.metadata.cgroup_id, peer))
+            .or_default();
+        totals.sent += sent;
+        totals.received += received;
+        totals.closed += u64::from(closed);
+    }
+
+    /// Counts the connections read from the map, and forgets those that closed
+    /// and those the two latest scans missed. Connections without an owner yet
+    /// are left for a later scan; their first bytes may be read before the
+    /// owner is.
+    fn scan(&mut self, open: impl IntoIterator<Item = TcpConnection>) {
+        for connection in open {
+            if connection.metadata.pid == 0 && connection.metadata.cgroup_id == 0 {
+                continue;
+            }
+            self.account(&connection, false);
+        }
+        self.connections.retain(|_, counted| {
+            let keep = !counted.closed && (counted.seen || !counted.missed);
+            counted.missed = !counted.seen;
+            counted.seen = false;
+            keep
+        });
+    }
+
+    /// Traffic counted since the previous report, stamped with `timestamp`.
+    fn report(&mut self, timestamp: u64) -> Vec<TcpTraffic> {
+        self.totals
+            .drain()
+            .map(|((cgroup_id, destination), totals)| TcpTraffic {
+                metadata: Metadata {
+                    pid: 0,
+                    cgroup_id,
+                    timestamp,
+                },
+                destination,
+                zone: None,
+                sent_bytes: totals.sent,
+                received_bytes: totals.received,
+                connections_closed: totals.closed,
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv6Addr;
+
+    use super::*;
+
+    fn connection(sk: u64, cgroup_id: u64, peer: &str, sent: u64, received: u64) -> TcpConnection {
+        let mut connection = TcpConnection {
+            sk,
+            daddr: peer.parse::<Ipv6Addr>().unwrap().octets(),
+            sent_bytes: sent,
+            received_bytes: received,
+            ..Default::default()
+        };
+        connection.metadata.cgroup_id = cgroup_id;
+        connection.metadata.timestamp = 1000 + sk;
+        connection
+    }
+
+    fn report(ledger: &mut TrafficLedger) -> Vec<(u64, String, u64, u64, u64)> {
+        let mut traffic: Vec<_> = ledger
+            .report(0)
+            .into_iter()
+            .map(|t| {
+                let peer = t.destination.to_string();
+                (
+                    t.metadata.cgroup_id,
+                    peer,
+                    t.sent_bytes,
+                    t.received_bytes,
+                    t.connections_closed,
+                )
+            })
+            .collect();
+        traffic.sort();
+        traffic
+    }
+
+    #[test]
+    fn test_ledger_counts_each_byte_once() {
+        let mut ledger = TrafficLedger::default();
+        ledger.scan([connection(1, 42, "::ffff:10.0.0.9", 100, 0)]);
+        assert_eq!(
+            report(&mut ledger),
+            [(42, "10.0.0.9".to_string(), 100, 0, 0)]
+        );
+
+        ledger.scan([connection(1, 42, "::ffff:10.0.0.9", 150, 20)]);
+        assert_eq!(
+            report(&mut ledger),
+            [(42, "10.0.0.9".to_string(), 50, 20, 0)]
+        );
+
+        // Closed between the map read and the scan that would have counted it
+        ledger.account(&connection(1, 42, "::ffff:10.0.0.9", 170, 30), true);
+        ledger.scan([connection(1, 42, "::ffff:10.0.0.9", 160, 25)]);
+        assert_eq!(
+            report(&mut ledger),
+            [(42, "10.0.0.9".to_string(), 20, 10, 1)]
+        );
+
+        ledger.scan([]);
+        assert!(report(&mut ledger).is_empty());
+        assert!(ledger.connections.is_empty());
+    }
+
+    #[test]
+    fn test_ledger_waits_for_late_close() {
+        let mut ledger = TrafficLedger::default();
+        ledger.scan([connection(1, 42, "::ffff:10.0.0.9", 100, 0)]);
+        assert_eq!(
+            report(&mut ledger),
+            [(42, "10.0.0.9".to_string(), 100, 0, 0)]
+        );
+
+        // Gone from the map before its close was read from the ringbuf
+        ledger.scan([]);
+        ledger.account(&connection(1, 42, "::ffff:10.0.0.9", 120, 0), true);
+        assert_eq!(
+            report(&mut ledger),
+            [(42, "10.0.0.9".to_string(), 20, 0, 1)]
+        );
+        ledger.scan([]);
+        assert!(ledger.connections.is_empty());
+
+        // Evicted, so no close follows
+        ledger.scan([connection(2, 42, "::ffff:10.0.0.9", 10, 0)]);
+        ledger.scan([]);
+        assert_eq!(ledger.connections.len(), 1);
+        ledger.scan([]);
+        assert!(ledger.connections.is_empty());
+    }
+
+    #[test]
+    fn test_ledger_skips_connections_without_owner() {
+        let mut ledger = TrafficLedger::default();
+        // Read between the first byte count and the owner
+        ledger.scan([connection(1, 0, "::ffff:10.0.0.9", 100, 0)]);
+        assert!(report(&mut ledger).is_empty());
+        assert!(ledger.connections.is_empty());
+
+        ledger.scan([connection(1, 42, "::ffff:10.0.0.9", 150, 0)]);
+        assert_eq!(
+            report(&mut ledger),
+            [(42, "10.0.0.9".to_string(), 150, 0, 0)]
+        );
+    }
+
+    #[test]
+    fn test_ledger_sums_per_cgroup_and_peer() {
+        let mut ledger = TrafficLedger::default();
+        ledger.scan([
+            connection(1, 42, "2001:db8::1", 10, 1),
+            connection(2, 42, "2001:db8::1", 20, 2),
+            connection(3, 7, "2001:db8::1", 30, 3),
+            // Established, but nothing moved yet
+            connection(4, 0, "2001:db8::2", 0, 0),
+        ]);
+        // Opened and closed within the interval
+        ledger.account(&connection(5, 42, "2001:db8::3", 5, 0), true);
+        assert_eq!(
+            report(&mut ledger),
+            [
+                (7, "2001:db8::1".to_string(), 30, 3, 0),
+                (42, "2001:db8::1".to_string(), 30, 3, 0),
+                (42, "2001:db8::3".to_string(), 5, 0, 1),
+            ]
+        );
+
+        // A reused socket is a new connection
+        let mut reused = connection(1, 42, "2001:db8::1", 4, 0);
+        reused.metadata.timestamp += 1;
+        ledger.scan([connection(1, 42, "2001:db8::1", 10, 1), reused]);
+        assert_eq!(
+            report(&mut ledger),
+            [(42, "2001:db8::1".to_string(), 4, 0, 0)]
+        );
+    }
+}
@@ -0,0 +1,376 @@
+use std::collections::{HashMap, HashSet};
+
+use anyhow::{Context, Result, bail, ensure};
+use honeybeepf_common::{MAX_CUSTOM_ARGS, MAX_CUSTOM_PROBES_PER_KIND};
+use serde::Deserialize;
+
+use crate::probes::tracefs_event_path;
+
+/// User-defined probes, as passed in `CUSTOM_PROBE_CONFIG` (JSON).
+///
+/// ```json
+/// {
+///   "tracepoints": [{"name": "exec", "category": "sched", "event": "sched_process_exec",
+///                    "args": [{"name": "pid", "field": "pid"}]}],
+///   "kprobes": [{"name": "tcp_connect", "function": "tcp_connect"}],
+///   "uprobes": [{"name": "ssl_write", "binary": "/usr/lib/libssl.so.3", "symbol": "SSL_write",
+///                "args": [{"name": "len", "index": 2}]}]
+/// }
+/// ```
+#[derive(Debug, Clone, Default, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct CustomProbeConfig {
+    #[serde(default)]
+    pub tracepoints: Vec<TracepointDefinition>,
+    #[serde(default)]
+    pub kprobes: Vec<KprobeDefinition>,
+    #[serde(default)]
+    pub uprobes: Vec<UprobeDefinition>,
+}
+
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct TracepointDefinition {
+    /// Name reported on every event emitted by this probe.
+    pub name: String,
+    pub category: String,
+    pub event: String,
+    #[serde(default)]
+    pub args: Vec<FieldArg>,
+}
+
+/// A tracepoint record field, either by name (resolved through tracefs) or by raw layout.
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct FieldArg {
+    pub name: String,
+    pub field: Option<String>,
+    pub offset: Option<u32>,
+    pub size: Option<u32>,
+}
+
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct KprobeDefinition {
+    pub name: String,
+    pub function: String,
+    #[serde(default)]
+    pub offset: u64,
+    /// Attach as a kretprobe and report the return value as `retval`.
+    #[serde(default, rename = "return")]
+    pub is_return: bool,
+    #[serde(default)]
+    pub args: Vec<RegisterArg>,
+}
+
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct UprobeDefinition {
+    pub name: String,
+    /// Path of the binary or shared library, as seen from the agent.
+    pub binary: String,
+    pub symbol: Option<String>,
+    pub offset: Option<u64>,
+    /// Restrict the probe to a single process.
+    pub pid: Option<i32>,
+    /// Attach as a uretprobe and report the return value as `retval`.
+    #[serde(default, rename = "return")]
+    pub is_return: bool,
+    #[serde(default)]
+    pub args: Vec<RegisterArg>,
+}
+
+/// A function argument, by position in the calling convention.
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct RegisterArg {
+    pub name: String,
+    pub index: u32,
+}
+
+impl CustomProbeConfig {
+    pub fn parse(raw: &str) -> Result<Self> {
+        let config: Self =
+            serde_json::from_str(raw).context("Failed to parse custom probe config")?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tracepoints.is_empty() && self.kprobes.is_empty() && self.uprobes.is_empty()
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        let kretprobes = self.kprobes.iter().filter(|p| p.is_return).count();
+        let uretprobes = self.uprobes.iter().filter(|p| p.is_return).count();
+        for (kind, count) in [
+            ("tracepoints", self.tracepoints.len()),
+            ("kprobes", self.kprobes.len() - kretprobes),
+            ("kretprobes", kretprobes),
+            ("uprobes", self.uprobes.len() - uretprobes),
+            ("uretprobes", uretprobes),
+        ] {
+            ensure!(
+                count <= MAX_CUSTOM_PROBES_PER_KIND as usize,
+                "At most {} custom {} are supported, got {}",
+                MAX_CUSTOM_PROBES_PER_KIND,
+                kind,
+                count
+            );
+        }
+
+        let mut names = HashSet::new();
+        let mut check_name = |name: &str| -> Result<()> {
+            ensure!(
+                !name.trim().is_empty(),
+                "Custom probe name must not be empty"
+            );
+            ensure!(
+                names.insert(name.to_string()),
+                "Duplicate custom probe name {}",
+                name
+            );
+            Ok(())
+        };
+
+        for probe in &self.tracepoints {
+            check_name(&probe.name)?;
+            check_arg_count(&probe.name, probe.args.len())?;
+            for arg in &probe.args {
+                match (&arg.field, arg.offset, arg.size) {
+                    (Some(_), None, None) => {}
+                    (None, Some(_), Some(size)) => check_size(&probe.name, &arg.name, size)?,
+                    _ => bail!(
+                        "Custom probe {}: argument {} needs either `field` or `offset` and `size`",
+                        probe.name,
+                        arg.name
+                    ),
+                }
+            }
+        }
+
+        for probe in &self.kprobes {
+            check_name(&probe.name)?;
+            check_register_args(&probe.name, probe.is_return, &probe.args)?;
+        }
+
+        for probe in &self.uprobes {
+            check_name(&probe.name)?;
+            ensure!(
+                probe.symbol.is_some() || probe.offset.is_some(),
+                "Custom probe {}: uprobes need a `symbol` or an `offset`",
+                probe.name
+            );
+            check_register_args(&probe.name, probe.is_return, &probe.args)?;
+        }
+
+        Ok(())
+    }
+}
+
+fn check_arg_count(probe: &str, count: usize) -> Result<()> {
+    ensure!(
+        count <= MAX_CUSTOM_ARGS,
+        "Custom probe {}: at most {} args are supported, got {}",
+        probe,
+        MAX_CUSTOM_ARGS,
+        count
+    );
+    Ok(())
+}
+
+fn check_size(probe: &str, arg: &str, size: u32) -> Result<()> {
+    ensure!(
+        matches!(size, 1 | 2 | 4 | 8),
+        "Custom probe {}: argument {} has unsupported size {}",
+        probe,
+        arg,
+        size
+    );
+    Ok(())
+}
+
+fn check_register_args(probe: &str, is_return: bool, args: &[RegisterArg]) -> Result<()> {
+    ensure!(
+        !is_return || args.is_empty(),
+        "Custom probe {}: return probes only report `retval` and take no args",
+        probe
+    );
+    check_arg_count(probe, args.len())?;
+    for arg in args {
+        ensure!(
+            (arg.index as usize) < MAX_CUSTOM_ARGS,
+            "Custom probe {}: argument {} has index {}, the maximum is {}",
+            probe,
+            arg.name,
+            arg.index,
+            MAX_CUSTOM_ARGS - 1
+        );
+    }
+    Ok(())
+}
+
+/// Offset and size of a field in a tracepoint record.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FieldLayout {
+    pub offset: u32,
+    pub size: u32,
+}
+
+/// Parses the `format` file tracefs exposes for every event.
+pub fn parse_tracepoint_format(format: &str) -> HashMap<String, FieldLayout> {
+    let mut fields = HashMap::new();
+    for line in format.lines() {
+        let line = line.trim();
+        let Some(rest) = line.strip_prefix("field:") else {
+            continue;
+        };
+
+        let mut parts = rest.split(';').map(str::trim);
+        let Some(declaration) = parts.next() else {
+            continue;
+        };
+        let mut offset = None;
+        let mut size = None;
+        for part in parts {
+            if let Some(value) = part.strip_prefix("offset:") {
+                offset = value.parse().ok();
+            } else if let Some(value) = part.strip_prefix("size:") {
+                size = value.parse().ok();
+            }
+        }
+
+        // "unsigned long args[6]" -> "args", and the length may be an expression
+        // with spaces: "__u8 saddr[sizeof(struct sockaddr_in6)]" -> "saddr"
+        let declaration = declaration
+            .strip_suffix(']')
+            .and_then(|array| array.rsplit_once('['))
+            .map_or(declaration, |(declaration, _)| declaration);
+        let name = declaration
+            .rsplit(' ')
+            .next()
+            .unwrap_or(declaration)
+            .trim_start_matches('*');
+
+        if let (Some(offset), Some(size)) = (offset, size) {
+            fields.insert(name.to_string(), FieldLayout { offset, size });
+        }
+    }
+    fields
+}
+
+pub fn read_tracepoint_format(category: &str, event: &str) -> Result<HashMap<String, FieldLayout>> {
+    let path = tracefs_event_path(category, event)
+        .with_context(|| format!("Tracepoint {}:{} not available", category, event))?
+        .join("format");
+    let format = std::fs::read_to_string(&path)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    Ok(parse_tracepoint_format(&format))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SCHED_PROCESS_EXEC_FORMAT: &str = "name: sched_process_exec
+ID: 316
+format:
+\tfield:unsigned short common_type;\toffset:0;\tsize:2;\tsigned:0;
+\tfield:unsigned char common_flags;\toffset:2;\tsize:1;\tsigned:0;
+\tfield:unsigned char common_preempt_count;\toffset:3;\tsize:1;\tsigned:0;
+\tfield:int common_pid;\toffset:4;\tsize:4;\tsigned:1;
+
+\tfield:__data_loc char[] filename;\toffset:8;\tsize:4;\tsigned:0;
+\tfield:pid_t pid;\toffset:12;\tsize:4;\tsigned:1;
+\tfield:pid_t old_pid;\toffset:16;\tsize:4;\tsigned:1;
+\tfield:unsigned long args[6];\toffset:24;\tsize:48;\tsigned:0;
+
+print fmt: \"filename=%s pid=%d old_pid=%d\", __get_str(filename), REC->pid, REC->old_pid
+";
+
+    #[test]
+    fn test_parse_config() {
+        let config = CustomProbeConfig::parse(
+            r#"{
+                "tracepoints": [{"name": "exec", "category": "sched", "event": "sched_process_exec",
+                                 "args": [{"name": "pid", "field": "pid"},
+                                          {"name": "old_pid", "offset": 16, "size": 4}]}],
+                "kprobes": [{"name": "tcp_connect", "function": "tcp_connect"},
+                            {"name": "tcp_connect_ret", "function": "tcp_connect", "return": true}],
+                "uprobes": [{"name": "ssl_write", "binary": "/usr/lib/libssl.so.3",
+                             "symbol": "SSL_write", "args": [{"name": "len", "index": 2}]}]
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(config.tracepoints[0].args[1].offset, Some(16));
+        assert!(config.kprobes[1].is_return);
+        assert_eq!(config.uprobes[0].args[0].index, 2);
+    }
+
+    #[test]
+    fn test_chart_defaults_are_empty() {
+        let config =
+            CustomProbeConfig::parse(r#"{"kprobes":[],"uprobes":[],"tracepoints":[]}"#).unwrap();
+        assert!(config.is_empty());
+    }
+
+    #[test]
+    fn test_rejects_invalid_definitions() {
+        for raw in [
+            r#"{"kprobes": [{"name": "a", "function": "f", "unknown": 1}]}"#,
+            r#"{"kprobes": [{"name": "a", "function": "f"}, {"name": "a", "function": "g"}]}"#,
+            r#"{"kprobes": [{"name": "a", "function": "f", "return": true,
+                             "args": [{"name": "x", "index": 0}]}]}"#,
+            r#"{"kprobes": [{"name": "a", "function": "f", "args": [{"name": "x", "index": 6}]}]}"#,
+            r#"{"tracepoints": [{"name": "a", "category": "c", "event": "e",
+                                 "args": [{"name": "x", "offset": 8}]}]}"#,
+            r#"{"tracepoints": [{"name": "a", "category": "c", "event": "e",
+                                 "args": [{"name": "x", "offset": 8, "size": 3}]}]}"#,
+            r#"{"uprobes": [{"name": "a", "binary": "/bin/true"}]}"#,
+        ] {
+            assert!(CustomProbeConfig::parse(raw).is_err(), "accepted {}", raw);
+        }
+    }
+
+    #[test]
+    fn test_rejects_too_many_probes_of_a_kind() {
+        let kprobes: Vec<String> = (0..=MAX_CUSTOM_PROBES_PER_KIND)
+            .map(|i| format!(r#"{{"name": "p{}", "function": "f"}}"#, i))
+            .collect();
+        let raw = format!(r#"{{"kprobes": [{}]}}"#, kprobes.join(","));
+        assert!(CustomProbeConfig::parse(&raw).is_err());
+    }
+
+    #[test]
+    fn test_parse_tracepoint_format() {
+        let fields = parse_tracepoint_format(SCHED_PROCESS_EXEC_FORMAT);
+        assert_eq!(
+            fields["pid"],
+            FieldLayout {
+                offset: 12,
+                size: 4
+            }
+        );
+        assert_eq!(fields["common_pid"], FieldLayout { offset: 4, size: 4 });
+        assert_eq!(
+            fields["args"],
+            FieldLayout {
+                offset: 24,
+                size: 48
+            }
+        );
+        assert_eq!(fields["filename"], FieldLayout { offset: 8, size: 4 });
+
+        let fields = parse_tracepoint_format(
+            "\tfield:__u8 saddr[sizeof(struct sockaddr_in6)];\toffset:32;\tsize:28;\tsigned:0;",
+        );
+        assert_eq!(
+            fields["saddr"],
+            FieldLayout {
+                offset: 32,
+                size: 28
+            }
+        );
+    }
+}
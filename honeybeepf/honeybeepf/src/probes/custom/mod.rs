@@ -1 +1,446 @@
-// Future custom probes will go here
+use std::{
+    collections::HashMap,
+    sync::{Arc, RwLock},
+};
+
+use anyhow::{Context, Result, bail};
+use aya::{Ebpf, maps::Array, programs::UProbe};
+use honeybeepf_common::{
+    CustomArgSource, CustomArgSpec, CustomProbeEvent, CustomProbeKind, CustomProbeSpec,
+    custom_probe_id,
+};
+use log::{info, warn};
+
+use crate::{
+    events::{Custom, HoneyBeeEvent},
+    pipeline::EventSender,
+    probes::{
+        KernelFeature, Probe, ProbeStatus, TracepointConfig, attach_kprobe, attach_tracepoint,
+        detach_program, program_status, spawn_ringbuf_handler,
+    },
+    settings::Settings,
+};
+
+pub mod config;
+
+pub use config::CustomProbeConfig;
+use config::{FieldArg, KprobeDefinition, RegisterArg, TracepointDefinition, UprobeDefinition};
+
+const RETVAL_ARG: &str = "retval";
+const RING_BUFFER: &str = "CUSTOM_EVENTS";
+
+/// Names attached to the raw values a custom probe program emits.
+#[derive(Debug)]
+pub struct CustomProbeInfo {
+    pub name: String,
+    pub arg_names: Vec<String>,
+}
+
+enum Target<'a> {
+    Tracepoint(&'a TracepointDefinition),
+    Kprobe(&'a KprobeDefinition),
+    Uprobe(&'a UprobeDefinition),
+}
+
+struct Assignment<'a> {
+    kind: CustomProbeKind,
+    slot: u32,
+    target: Target<'a>,
+}
+
+impl Assignment<'_> {
+    fn id(&self) -> u32 {
+        custom_probe_id(self.kind, self.slot)
+    }
+
+    fn program_name(&self) -> String {
+        let kind = match self.kind {
+            CustomProbeKind::Tracepoint => "tracepoint",
+            CustomProbeKind::Kprobe => "kprobe",
+            CustomProbeKind::Kretprobe => "kretprobe",
+            CustomProbeKind::Uprobe => "uprobe",
+            CustomProbeKind::Uretprobe => "uretprobe",
+        };
+        format!("honeybeepf_custom_{}_{}", kind, self.slot)
+    }
+
+    fn name(&self) -> &str {
+        match self.target {
+            Target::Tracepoint(p) => &p.name,
+            Target::Kprobe(p) => &p.name,
+            Target::Uprobe(p) => &p.name,
+        }
+    }
+}
+
+type Decoders = Arc<RwLock<HashMap<u32, CustomProbeInfo>>>;
+
+/// Attaches the generic custom probe programs according to `CUSTOM_PROBE_CONFIG`.
+pub struct CustomProbes {
+    config: CustomProbeConfig,
+    /// Shared with the ringbuf reader, which outlives any single configuration.
+    decoders: Decoders,
+}
+
+impl CustomProbes {
+    pub fn new(config: CustomProbeConfig) -> Self {
+        Self {
+            config,
+            decoders: Decoders::default(),
+        }
+    }
+
+    pub fn config(&self) -> &CustomProbeConfig {
+        &self.config
+    }
+
+    /// Replacement definitions that reuse the running ringbuf reader, for reloads.
+    pub fn with_config(&self, config: CustomProbeConfig) -> Self {
+        Self {
+            config,
+            decoders: self.decoders.clone(),
+        }
+    }
+
+    /// Gives every definition its own program slot for its kind.
+    fn assignments(&self) -> Vec<Assignment<'_>> {
+        let mut next_slot: HashMap<u32, u32> = HashMap::new();
+        let mut assign = |kind: CustomProbeKind, target| {
+            let slot = next_slot.entry(kind as u32).or_default();
+            let assignment = Assignment {
+                kind,
+                slot: *slot,
+                target,
+            };
+            *slot += 1;
+            assignment
+        };
+
+        let mut assignments = Vec::new();
+        for probe in &self.config.tracepoints {
+            assignments.push(assign(
+                CustomProbeKind::Tracepoint,
+                Target::Tracepoint(probe),
+            ));
+        }
+        for probe in &self.config.kprobes {
+            let kind = if probe.is_return {
+                CustomProbeKind::Kretprobe
+            } else {
+                CustomProbeKind::Kprobe
+            };
+            assignments.push(assign(kind, Target::Kprobe(probe)));
+        }
+        for probe in &self.config.uprobes {
+            let kind = if probe.is_return {
+                CustomProbeKind::Uretprobe
+            } else {
+                CustomProbeKind::Uprobe
+            };
+            assignments.push(assign(kind, Target::Uprobe(probe)));
+        }
+        assignments
+    }
+
+    fn attach_one(bpf: &mut Ebpf, assignment: &Assignment) -> Result<()> {
+        let program_name = assignment.program_name();
+        match assignment.target {
+            Target::Tracepoint(probe) => {
+                attach_tracepoint(
+                    bpf,
+                    TracepointConfig {
+                        program_name: &program_name,
+                        category: &probe.category,
+                        name: &probe.event,
+                    },
+                )?;
+            }
+            Target::Kprobe(probe) => {
+                attach_kprobe(bpf, &program_name, &probe.function, probe.offset)?;
+            }
+            Target::Uprobe(probe) => {
+                info!("Loading program {}", program_name);
+                let program: &mut UProbe = bpf
+                    .program_mut(&program_name)
+                    .with_context(|| format!("Failed to find {} program", program_name))?
+                    .try_into()?;
+                program.load()?;
+                program
+                    .attach(
+                        probe.symbol.as_deref(),
+                        probe.offset.unwrap_or(0),
+                        &probe.binary,
+                        probe.pid,
+                    )
+                    .with_context(|| format!("Failed to attach uprobe to {}", probe.binary))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Probe for CustomProbes {
+    fn name(&self) -> &'static str {
+        "custom"
+    }
+
+    fn description(&self) -> &'static str {
+        "User-defined tracepoints, kprobes and uprobes"
+    }
+
+    fn config_section(&self) -> &'static str {
+        "custom_probes"
+    }
+
+    /// Per-target requirements are checked as each definition is attached.
+    fn required_features(&self) -> &[KernelFeature] {
+        &[KernelFeature::RingBuf]
+    }
+
+    fn enabled(&self, settings: &Settings) -> bool {
+        settings
+            .custom_probes()
+            .is_ok_and(|config| config.is_some_and(|config| !config.is_empty()))
+    }
+
+    fn ring_buffer(&self) -> &'static str {
+        RING_BUFFER
+    }
+
+    fn attach(&self, bpf: &mut Ebpf, events: &EventSender) -> Result<()> {
+        if self.config.is_empty() {
+            return Ok(());
+        }
+        info!("Attaching custom probes...");
+
+        let mut resolved = Vec::new();
+        for assignment in self.assignments() {
+            match build_spec(&assignment.target) {
+                Ok((spec, info)) => resolved.push((assignment, spec, info)),
+                Err(e) => warn!("Skipping custom probe {}: {:#}", assignment.name(), e),
+            }
+        }
+
+        let mut specs: Array<_, CustomProbeSpec> = Array::try_from(
+            bpf.map_mut("CUSTOM_PROBE_SPECS")
+                .context("Failed to get map")?,
+        )?;
+        for (assignment, spec, _) in &resolved {
+            specs.set(assignment.id(), spec, 0)?;
+        }
+
+        let mut probes = HashMap::new();
+        for (assignment, _, info) in resolved {
+            // A bad user-supplied target should not take down the builtin probes
+            match Self::attach_one(bpf, &assignment) {
+                Ok(()) => {
+                    probes.insert(assignment.id(), info);
+                }
+                Err(e) => warn!(
+                    "Failed to attach custom probe {}: {:#}",
+                    assignment.name(),
+                    e
+                ),
+            }
+        }
+
+        if probes.is_empty() {
+            warn!("No custom probes could be attached");
+            return Ok(());
+        }
+        *self.decoders.write().unwrap() = probes;
+
+        let sender = events.clone();
+        let decoders = self.decoders.clone();
+        spawn_ringbuf_handler(
+            bpf,
+            events,
+            RING_BUFFER,
+            move |event: CustomProbeEvent| {
+                if let Some(info) = decoders.read().unwrap().get(&event.probe_id) {
+                    sender.send(HoneyBeeEvent::Custom(Custom::decode(info, &event)));
+                }
+            },
+        )?;
+
+        Ok(())
+    }
+
+    fn detach(&self, bpf: &mut Ebpf) -> Result<()> {
+        for assignment in self.assignments() {
+            detach_program(bpf, &assignment.program_name())?;
+        }
+        self.decoders.write().unwrap().clear();
+        Ok(())
+    }
+
+    fn status(&self, bpf: &Ebpf) -> ProbeStatus {
+        let programs: Vec<String> = self
+            .assignments()
+            .iter()
+            .map(Assignment::program_name)
+            .collect();
+        program_status(bpf, &programs)
+    }
+}
+
+fn build_spec(target: &Target) -> Result<(CustomProbeSpec, CustomProbeInfo)> {
+    let (name, args) = match target {
+        Target::Tracepoint(probe) => (&probe.name, tracepoint_args(probe)?),
+        Target::Kprobe(probe) if probe.is_return => (&probe.name, return_args()),
+        Target::Kprobe(probe) => (&probe.name, register_args(&probe.args)),
+        Target::Uprobe(probe) if probe.is_return => (&probe.name, return_args()),
+        Target::Uprobe(probe) => (&probe.name, register_args(&probe.args)),
+    };
+
+    let mut spec = CustomProbeSpec {
+        nr_args: args.len() as u32,
+        ..Default::default()
+    };
+    let mut arg_names = Vec::with_capacity(args.len());
+    for (i, (arg_name, arg)) in args.into_iter().enumerate() {
+        spec.args[i] = arg;
+        arg_names.push(arg_name.to_string());
+    }
+
+    Ok((
+        spec,
+        CustomProbeInfo {
+            name: name.clone(),
+            arg_names,
+        },
+    ))
+}
+
+fn tracepoint_args(probe: &TracepointDefinition) -> Result<Vec<(&str, CustomArgSpec)>> {
+    let needs_format = probe.args.iter().any(|arg| arg.field.is_some());
+    let layouts = if needs_format {
+        config::read_tracepoint_format(&probe.category, &probe.event)?
+    } else {
+        HashMap::new()
+    };
+
+    probe
+        .args
+        .iter()
+        .map(|arg: &FieldArg| {
+            let (offset, size) = match &arg.field {
+                Some(field) => {
+                    let layout = layouts.get(field).with_context(|| {
+                        format!(
+                            "Tracepoint {}:{} has no field {}",
+                            probe.category, probe.event, field
+                        )
+                    })?;
+                    if !matches!(layout.size, 1 | 2 | 4 | 8) {
+                        bail!(
+                            "Field {} is {} bytes; only scalar fields are supported",
+                            field,
+                            layout.size
+                        );
+                    }
+                    (layout.offset, layout.size)
+                }
+                // Validated when parsing the config
+                None => (arg.offset.unwrap_or(0), arg.size.unwrap_or(8)),
+            };
+            Ok((
+                arg.name.as_str(),
+                CustomArgSpec {
+                    source: CustomArgSource::TracepointField as u32,
+                    offset,
+                    size,
+                    ..Default::default()
+                },
+            ))
+        })
+        .collect()
+}
+
+fn register_args(args: &[RegisterArg]) -> Vec<(&str, CustomArgSpec)> {
+    args.iter()
+        .map(|arg| {
+            (
+                arg.name.as_str(),
+                CustomArgSpec {
+                    source: CustomArgSource::FunctionArg as u32,
+                    offset: arg.index,
+                    size: 8,
+                    ..Default::default()
+                },
+            )
+        })
+        .collect()
+}
+
+fn return_args() -> Vec<(&'static str, CustomArgSpec)> {
+    vec![(
+        RETVAL_ARG,
+        CustomArgSpec {
+            source: CustomArgSource::ReturnValue as u32,
+            size: 8,
+            ..Default::default()
+        },
+    )]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_slots_are_assigned_per_kind() {
+        let config = CustomProbeConfig::parse(
+            r#"{
+                "kprobes": [{"name": "a", "function": "f"},
+                            {"name": "b", "function": "f", "return": true},
+                            {"name": "c", "function": "g"}],
+                "uprobes": [{"name": "d", "binary": "/bin/true", "offset": 16}]
+            }"#,
+        )
+        .unwrap();
+        let probes = CustomProbes::new(config);
+
+        let programs: Vec<(String, u32)> = probes
+            .assignments()
+            .iter()
+            .map(|a| (a.program_name(), a.id()))
+            .collect();
+        assert_eq!(
+            programs,
+            vec![
+                (
+                    "honeybeepf_custom_kprobe_0".to_string(),
+                    custom_probe_id(CustomProbeKind::Kprobe, 0)
+                ),
+                (
+                    "honeybeepf_custom_kretprobe_0".to_string(),
+                    custom_probe_id(CustomProbeKind::Kretprobe, 0)
+                ),
+                (
+                    "honeybeepf_custom_kprobe_1".to_string(),
+                    custom_probe_id(CustomProbeKind::Kprobe, 1)
+                ),
+                (
+                    "honeybeepf_custom_uprobe_0".to_string(),
+                    custom_probe_id(CustomProbeKind::Uprobe, 0)
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_return_probes_report_retval() {
+        let config = CustomProbeConfig::parse(
+            r#"{"kprobes": [{"name": "ret", "function": "f", "return": true}]}"#,
+        )
+        .unwrap();
+        let probes = CustomProbes::new(config);
+        let assignments = probes.assignments();
+
+        let (spec, info) = build_spec(&assignments[0].target).unwrap();
+        assert_eq!(spec.nr_args, 1);
+        assert_eq!(spec.args[0].source, CustomArgSource::ReturnValue as u32);
+        assert_eq!(info.arg_names[0], RETVAL_ARG);
+    }
+}
@@ -1,16 +1,240 @@
-use anyhow::{Context, Result};
-use aya::maps::RingBuf;
-use aya::programs::TracePoint;
+use anyhow::{Context, Result, bail};
+use aya::maps::{Array, Map, MapData, RingBuf};
+use aya::programs::{KProbe, Program, ProgramError, TracePoint};
+use aya::util::KernelVersion;
 use aya::Ebpf;
-use log::{info, warn};
-use std::path::Path;
-use std::time::Duration;
+use honeybeepf_common::{COMMON_CONFIG_MAP, CommonConfig};
+use log::{debug, info, warn};
+use std::collections::HashSet;
+use std::fmt;
+use std::os::fd::AsRawFd;
+use std::path::{Path, PathBuf};
+use tokio::io::{unix::AsyncFd, Interest};
+use tokio_util::sync::CancellationToken;
+
+use crate::pipeline::EventSender;
+use crate::settings::Settings;
 
 pub mod builtin;
 pub mod custom;
+pub mod stats;
 
+/// A unit of instrumentation the engine can switch on and off at runtime.
 pub trait Probe {
-    fn attach(&self, bpf: &mut Ebpf) -> Result<()>;
+    /// Unique identifier used in logs and status reports.
+    fn name(&self) -> &'static str;
+
+    fn description(&self) -> &'static str;
+
+    /// Settings key that configures the probe.
+    fn config_section(&self) -> &'static str;
+
+    /// Checked before attaching; the probe is skipped if any of them is missing.
+    fn required_features(&self) -> &[KernelFeature];
+
+    fn enabled(&self, settings: &Settings) -> bool;
+
+    /// Ringbuf map the probe's programs write to, sized by `ring_buffers.<name>`.
+    fn ring_buffer(&self) -> &'static str;
+
+    fn attach(&self, bpf: &mut Ebpf, events: &EventSender) -> Result<()>;
+
+    /// Detaches and unloads the probe's programs. Its ringbuf reader keeps running
+    /// so a later `attach` can resume publishing.
+    fn detach(&self, bpf: &mut Ebpf) -> Result<()>;
+
+    fn status(&self, bpf: &Ebpf) -> ProbeStatus;
+
+    /// For probes that choose what to attach to at runtime, the choice in use
+    /// while attached, or the one attaching would make otherwise.
+    fn attach_path(&self) -> Option<String> {
+        None
+    }
+}
+
+/// Kernel facilities a probe depends on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KernelFeature {
+    /// BPF ring buffer maps, added in Linux 5.8.
+    RingBuf,
+    /// A static tracepoint exposed under tracefs.
+    Tracepoint {
+        category: &'static str,
+        name: &'static str,
+    },
+    /// Dynamic kprobes (`CONFIG_KPROBE_EVENTS`).
+    Kprobes,
+    /// Dynamic uprobes (`CONFIG_UPROBE_EVENTS`).
+    Uprobes,
+}
+
+impl KernelFeature {
+    pub fn is_available(&self) -> bool {
+        match self {
+            // Distribution kernels backport features, so only trust a version we could read
+            Self::RingBuf => KernelVersion::current()
+                .map_or(true, |version| version >= KernelVersion::new(5, 8, 0)),
+            Self::Tracepoint { category, name } => tracepoint_exists(category, name),
+            Self::Kprobes => tracefs_path("kprobe_events").is_some(),
+            Self::Uprobes => tracefs_path("uprobe_events").is_some(),
+        }
+    }
+}
+
+impl fmt::Display for KernelFeature {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::RingBuf => f.write_str("BPF ring buffer"),
+            Self::Tracepoint { category, name } => write!(f, "tracepoint {}:{}", category, name),
+            Self::Kprobes => f.write_str("kprobes"),
+            Self::Uprobes => f.write_str("uprobes"),
+        }
+    }
+}
+
+/// Features `probe` needs that the running kernel does not provide.
+pub fn missing_features(probe: &dyn Probe) -> Vec<KernelFeature> {
+    probe
+        .required_features()
+        .iter()
+        .copied()
+        .filter(|feature| !feature.is_available())
+        .collect()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProbeStatus {
+    Attached,
+    /// Some of the probe's programs are loaded, e.g. because a tracepoint was missing.
+    Partial,
+    Detached,
+}
+
+impl fmt::Display for ProbeStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Attached => "attached",
+            Self::Partial => "partially attached",
+            Self::Detached => "detached",
+        })
+    }
+}
+
+/// Status derived from how many of `programs` are currently loaded.
+pub fn program_status<S: AsRef<str>>(bpf: &Ebpf, programs: &[S]) -> ProbeStatus {
+    let loaded = programs
+        .iter()
+        .filter(|name| {
+            bpf.program(name.as_ref())
+                .is_some_and(|program| program.fd().is_ok())
+        })
+        .count();
+    match loaded {
+        0 => ProbeStatus::Detached,
+        n if n == programs.len() => ProbeStatus::Attached,
+        _ => ProbeStatus::Partial,
+    }
+}
+
+/// The probes the engine manages, in attach order.
+#[derive(Default)]
+pub struct ProbeRegistry {
+    probes: Vec<Box<dyn Probe>>,
+}
+
+impl ProbeRegistry {
+    /// Registry holding every builtin probe.
+    pub fn builtin() -> Self {
+        let mut registry = Self::default();
+        builtin::register(&mut registry);
+        registry
+    }
+
+    pub fn register(&mut self, probe: impl Probe + 'static) {
+        debug_assert!(
+            self.get(probe.name()).is_none(),
+            "probe {} registered twice",
+            probe.name()
+        );
+        self.probes.push(Box::new(probe));
+    }
+
+    pub fn get(&self, name: &str) -> Option<&dyn Probe> {
+        self.iter().find(|probe| probe.name() == name)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &dyn Probe> {
+        self.probes.iter().map(|probe| probe.as_ref())
+    }
+
+    /// Publishes the settings to the eBPF programs, then attaches newly enabled
+    /// probes and detaches disabled ones, leaving the rest running. `attached`
+    /// holds the names of the probes currently attached.
+    pub fn apply(
+        &self,
+        host: &mut impl ProbeHost,
+        settings: &Settings,
+        attached: &mut HashSet<&'static str>,
+        events: &EventSender,
+    ) -> Result<()> {
+        // Must land before any program is attached, or probes start out disabled
+        host.write_config(settings.to_common_config())?;
+
+        for probe in self.iter() {
+            let name = probe.name();
+            let enabled = probe.enabled(settings);
+            let is_attached = attached.contains(name);
+            if enabled && !is_attached {
+                let missing = missing_features(probe);
+                if !missing.is_empty() {
+                    let missing: Vec<String> = missing.iter().map(ToString::to_string).collect();
+                    warn!(
+                        "Skipping {} probe; the kernel lacks {}",
+                        name,
+                        missing.join(", ")
+                    );
+                    continue;
+                }
+                if let Err(e) = host.attach(probe, events) {
+                    // Don't leave half-attached programs behind for the next attempt
+                    if let Err(detach_err) = host.detach(probe) {
+                        warn!("Failed to clean up {} probe: {:#}", name, detach_err);
+                    }
+                    return Err(e);
+                }
+                attached.insert(name);
+            } else if !enabled && is_attached {
+                info!("Detaching {} probe", name);
+                host.detach(probe)?;
+                attached.remove(name);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// What applying the settings does to the loaded eBPF object, kept apart so
+/// that the order of the steps can be checked without a kernel.
+pub trait ProbeHost {
+    fn write_config(&mut self, config: CommonConfig) -> Result<()>;
+
+    fn attach(&mut self, probe: &dyn Probe, events: &EventSender) -> Result<()>;
+
+    fn detach(&mut self, probe: &dyn Probe) -> Result<()>;
+}
+
+impl ProbeHost for Ebpf {
+    fn write_config(&mut self, config: CommonConfig) -> Result<()> {
+        write_common_config(self, config)
+    }
+
+    fn attach(&mut self, probe: &dyn Probe, events: &EventSender) -> Result<()> {
+        probe.attach(self, events)
+    }
+
+    fn detach(&mut self, probe: &dyn Probe) -> Result<()> {
+        probe.detach(self)
+    }
 }
 
 pub struct TracepointConfig<'a> {
@@ -19,18 +243,26 @@ pub struct TracepointConfig<'a> {
     pub name: &'a str,
 }
 
-pub const POLL_INTERVAL_MS: u64 = 10;
+/// Ring entries handled per wakeup before a reader yields to the others.
+const RINGBUF_BATCH_SIZE: usize = 256;
 
-fn tracepoint_exists(category: &str, name: &str) -> bool {
+/// Path of `relative` under whichever tracefs mount is present.
+fn tracefs_path(relative: impl AsRef<Path>) -> Option<PathBuf> {
     const TRACEFS_MOUNT_POINTS: [&str; 2] = ["/sys/kernel/tracing", "/sys/kernel/debug/tracing"];
 
-    TRACEFS_MOUNT_POINTS.iter().any(|base| {
-        Path::new(base)
-            .join("events")
-            .join(category)
-            .join(name)
-            .exists()
-    })
+    TRACEFS_MOUNT_POINTS
+        .iter()
+        .map(|base| Path::new(base).join(relative.as_ref()))
+        .find(|path| path.exists())
+}
+
+/// Directory describing a tracepoint under whichever tracefs mount is present.
+pub fn tracefs_event_path(category: &str, name: &str) -> Option<PathBuf> {
+    tracefs_path(Path::new("events").join(category).join(name))
+}
+
+pub fn tracepoint_exists(category: &str, name: &str) -> bool {
+    tracefs_event_path(category, name).is_some()
 }
 
 pub fn attach_tracepoint(bpf: &mut Ebpf, config: TracepointConfig) -> Result<bool> {
@@ -54,25 +286,408 @@ pub fn attach_tracepoint(bpf: &mut Ebpf, config: TracepointConfig) -> Result<boo
     Ok(true)
 }
 
-pub fn spawn_ringbuf_handler<T, F>(bpf: &mut Ebpf, map_name: &str, handler: F) -> Result<()>
+/// Size in bytes of a loaded ringbuf map, as the kernel reports it.
+pub fn ring_buffer_size(bpf: &Ebpf, map_name: &str) -> Option<u32> {
+    match bpf.map(map_name)? {
+        Map::RingBuf(data) => data.info().ok().map(|info| info.max_entries()),
+        _ => None,
+    }
+}
+
+/// Publishes the settings the eBPF programs gate and tune themselves on.
+pub fn write_common_config(bpf: &mut Ebpf, config: CommonConfig) -> Result<()> {
+    let mut map: Array<_, CommonConfig> = Array::try_from(
+        bpf.map_mut(COMMON_CONFIG_MAP)
+            .with_context(|| format!("Failed to find {} map", COMMON_CONFIG_MAP))?,
+    )?;
+    map.set(0, config, 0)?;
+    Ok(())
+}
+
+pub fn attach_kprobe(bpf: &mut Ebpf, program_name: &str, function: &str, offset: u64) -> Result<()> {
+    info!("Loading program {}", program_name);
+    let program: &mut KProbe = bpf
+        .program_mut(program_name)
+        .with_context(|| format!("Failed to find {} program", program_name))?
+        .try_into()?;
+    program.load()?;
+    program
+        .attach(function, offset)
+        .with_context(|| format!("Failed to attach {} to {}", program_name, function))?;
+    Ok(())
+}
+
+/// Unloads a program, which also removes all of its links. Programs that were
+/// never loaded (e.g. a missing tracepoint) are left alone.
+pub fn detach_program(bpf: &mut Ebpf, program_name: &str) -> Result<()> {
+    let program = bpf
+        .program_mut(program_name)
+        .with_context(|| format!("Failed to find {} program", program_name))?;
+    let result = match program {
+        Program::TracePoint(p) => p.unload(),
+        Program::KProbe(p) => p.unload(),
+        Program::UProbe(p) => p.unload(),
+        _ => bail!("Don't know how to detach program {}", program_name),
+    };
+    match result {
+        Ok(()) => {
+            info!("Detached program {}", program_name);
+            Ok(())
+        }
+        Err(ProgramError::NotLoaded) => Ok(()),
+        Err(e) => Err(e).with_context(|| format!("Failed to detach {}", program_name)),
+    }
+}
+
+/// Reads `map_name` on the runtime, woken through epoll whenever the kernel commits
+/// new entries. At shutdown whatever is left in the ring is handed to `handler`
+/// before the reader returns.
+pub fn spawn_ringbuf_handler<T, F>(
+    bpf: &mut Ebpf,
+    events: &EventSender,
+    map_name: &str,
+    handler: F,
+) -> Result<()>
 where
     T: Copy + Send + 'static,
     F: Fn(T) + Send + 'static,
 {
-    let mut ring_buf =
-        RingBuf::try_from(bpf.take_map(map_name).context("Failed to get map")?)?;
-    tokio::task::spawn_blocking(move || {
-        loop {
-            let mut has_work = false;
-            while let Some(item) = ring_buf.next() {
-                has_work = true;
-                let event = unsafe { (item.as_ptr() as *const T).read_unaligned() };
-                handler(event);
+    let Some(map) = bpf.take_map(map_name) else {
+        // Taken by an earlier attach of the same probe, whose reader is still running
+        debug!("Ringbuf {} already has a reader", map_name);
+        return Ok(());
+    };
+    let ring_buf = RingBuf::try_from(map)?;
+    let ring_buf = AsyncFd::with_interest(ring_buf, Interest::READABLE)
+        .with_context(|| format!("Failed to register ringbuf {} for readiness", map_name))?;
+    let map_name = map_name.to_string();
+
+    events.spawn_producer(move |stop| read_ringbuf(ring_buf, stop, map_name, handler));
+    Ok(())
+}
+
+/// A ringbuf as its reader sees it, so that reading can be tested without a kernel.
+trait RingEntries: AsRawFd {
+    /// Hands the next entry to `f`, or returns false when the ring is empty.
+    fn next_entry(&mut self, f: impl FnOnce(&[u8])) -> bool;
+}
+
+impl RingEntries for RingBuf<MapData> {
+    fn next_entry(&mut self, f: impl FnOnce(&[u8])) -> bool {
+        match self.next() {
+            Some(item) => {
+                f(&item);
+                true
             }
-            if !has_work {
-                std::thread::sleep(Duration::from_millis(POLL_INTERVAL_MS));
+            None => false,
+        }
+    }
+}
+
+async fn read_ringbuf<R, T, F>(
+    mut ring_buf: AsyncFd<R>,
+    stop: CancellationToken,
+    map_name: String,
+    handler: F,
+) where
+    R: RingEntries,
+    T: Copy,
+    F: Fn(T),
+{
+    loop {
+        let ready = tokio::select! {
+            ready = ring_buf.readable_mut() => Some(ready),
+            _ = stop.cancelled() => None,
+        };
+        let Some(ready) = ready else {
+            read_batch(ring_buf.get_mut(), usize::MAX, &handler);
+            debug!("Ringbuf {} reader stopped", map_name);
+            return;
+        };
+        let mut guard = match ready {
+            Ok(guard) => guard,
+            Err(e) => {
+                warn!("Ringbuf {} reader failed: {}", map_name, e);
+                return;
             }
+        };
+
+        if read_batch(guard.get_inner_mut(), RINGBUF_BATCH_SIZE, &handler) < RINGBUF_BATCH_SIZE {
+            guard.clear_ready();
+        } else {
+            // Entries are still waiting; give the other readers a turn first
+            drop(guard);
+            tokio::task::yield_now().await;
         }
-    });
-    Ok(())
+    }
+}
+
+/// Hands up to `limit` entries to `handler`, returning how many were read.
+fn read_batch<R, T, F>(ring_buf: &mut R, limit: usize, handler: &F) -> usize
+where
+    R: RingEntries,
+    T: Copy,
+    F: Fn(T),
+{
+    let mut read = 0;
+    while read < limit
+        && ring_buf.next_entry(|entry| {
+            let event = unsafe { (entry.as_ptr() as *const T).read_unaligned() };
+            handler(event);
+        })
+    {
+        read += 1;
+    }
+    read
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::VecDeque;
+    use std::os::fd::RawFd;
+    use std::os::unix::net::UnixStream;
+    use std::sync::{Arc, Mutex};
+
+    use crate::pipeline::Pipeline;
+    use crate::settings::BuiltinProbes;
+    use crate::telemetry::metrics::ProbeMetrics;
+
+    fn settings(block_io: Option<bool>) -> Settings {
+        Settings {
+            otel_exporter_otlp_endpoint: None,
+            otel_exporter_otlp_protocol: None,
+            builtin_probes: BuiltinProbes {
+                block_io,
+                block_io_mode: None,
+                network_latency: None,
+                tcp_retransmit: None,
+                tcp_traffic: None,
+                gpu_open: None,
+                interval: None,
+            },
+            custom_probe_config: None,
+            custom_probes: None,
+            metrics: Default::default(),
+            sinks: Default::default(),
+            ring_buffers: Default::default(),
+            network_zones: None,
+            shutdown_timeout_seconds: None,
+        }
+    }
+
+    #[test]
+    fn test_builtin_registry_follows_settings() {
+        let registry = ProbeRegistry::builtin();
+        let names: Vec<&str> = registry.iter().map(|probe| probe.name()).collect();
+        let expected = ["network_latency", "tcp_retransmit", "tcp_traffic", "block_io", "gpu_open"];
+        assert_eq!(names, expected);
+
+        let settings = settings(Some(true));
+        let enabled: Vec<&str> = registry
+            .iter()
+            .filter(|probe| probe.enabled(&settings))
+            .map(|probe| probe.name())
+            .collect();
+        assert_eq!(enabled, ["block_io"]);
+
+        let block_io = registry.get("block_io").unwrap();
+        assert_eq!(block_io.config_section(), "builtin_probes.block_io");
+        assert!(block_io.required_features().contains(&KernelFeature::RingBuf));
+    }
+
+    /// Follows `builtin_probes.block_io`, without needing any kernel feature.
+    struct FakeProbe(&'static str);
+
+    impl Probe for FakeProbe {
+        fn name(&self) -> &'static str {
+            self.0
+        }
+
+        fn description(&self) -> &'static str {
+            "Test probe"
+        }
+
+        fn config_section(&self) -> &'static str {
+            "builtin_probes.block_io"
+        }
+
+        fn required_features(&self) -> &[KernelFeature] {
+            &[]
+        }
+
+        fn enabled(&self, settings: &Settings) -> bool {
+            settings.builtin_probes.block_io.unwrap_or(false)
+        }
+
+        fn ring_buffer(&self) -> &'static str {
+            "FAKE_EVENTS"
+        }
+
+        fn attach(&self, _bpf: &mut Ebpf, _events: &EventSender) -> Result<()> {
+            unreachable!("attached through FakeHost")
+        }
+
+        fn detach(&self, _bpf: &mut Ebpf) -> Result<()> {
+            unreachable!("detached through FakeHost")
+        }
+
+        fn status(&self, _bpf: &Ebpf) -> ProbeStatus {
+            ProbeStatus::Detached
+        }
+    }
+
+    /// Records what applying the settings did, in order.
+    #[derive(Default)]
+    struct FakeHost {
+        calls: Vec<String>,
+        failing: Option<&'static str>,
+    }
+
+    impl ProbeHost for FakeHost {
+        fn write_config(&mut self, config: CommonConfig) -> Result<()> {
+            self.calls.push(format!("config block_io={}", config.probe_block_io));
+            Ok(())
+        }
+
+        fn attach(&mut self, probe: &dyn Probe, _events: &EventSender) -> Result<()> {
+            self.calls.push(format!("attach {}", probe.name()));
+            if self.failing == Some(probe.name()) {
+                bail!("{} failed", probe.name());
+            }
+            Ok(())
+        }
+
+        fn detach(&mut self, probe: &dyn Probe) -> Result<()> {
+            self.calls.push(format!("detach {}", probe.name()));
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_apply_writes_config_before_attaching() {
+        let mut registry = ProbeRegistry::default();
+        registry.register(FakeProbe("first"));
+        registry.register(FakeProbe("second"));
+        let pipeline = Pipeline::new(Arc::new(ProbeMetrics::new().unwrap())).start(16);
+        let events = pipeline.sender();
+        let mut host = FakeHost::default();
+        let mut attached = HashSet::new();
+
+        registry
+            .apply(&mut host, &settings(Some(true)), &mut attached, events)
+            .unwrap();
+        assert_eq!(
+            host.calls,
+            ["config block_io=1", "attach first", "attach second"]
+        );
+        assert_eq!(attached.len(), 2);
+
+        host.calls.clear();
+        registry
+            .apply(&mut host, &settings(Some(false)), &mut attached, events)
+            .unwrap();
+        assert_eq!(
+            host.calls,
+            ["config block_io=0", "detach first", "detach second"]
+        );
+        assert!(attached.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_apply_cleans_up_failed_attach() {
+        let mut registry = ProbeRegistry::default();
+        registry.register(FakeProbe("broken"));
+        let pipeline = Pipeline::new(Arc::new(ProbeMetrics::new().unwrap())).start(16);
+        let mut host = FakeHost {
+            failing: Some("broken"),
+            ..Default::default()
+        };
+        let mut attached = HashSet::new();
+
+        let result = registry.apply(
+            &mut host,
+            &settings(Some(true)),
+            &mut attached,
+            pipeline.sender(),
+        );
+        assert!(result.is_err());
+        assert_eq!(
+            host.calls,
+            ["config block_io=1", "attach broken", "detach broken"]
+        );
+        assert!(attached.is_empty());
+    }
+
+    /// Entries queued up front, behind a socket that never becomes readable.
+    struct FakeRing {
+        entries: VecDeque<u32>,
+        socket: UnixStream,
+        _peer: UnixStream,
+    }
+
+    impl FakeRing {
+        fn new(entries: std::ops::Range<u32>) -> Self {
+            let (socket, peer) = UnixStream::pair().unwrap();
+            Self {
+                entries: entries.collect(),
+                socket,
+                _peer: peer,
+            }
+        }
+    }
+
+    impl AsRawFd for FakeRing {
+        fn as_raw_fd(&self) -> RawFd {
+            self.socket.as_raw_fd()
+        }
+    }
+
+    impl RingEntries for FakeRing {
+        fn next_entry(&mut self, f: impl FnOnce(&[u8])) -> bool {
+            match self.entries.pop_front() {
+                Some(entry) => {
+                    f(&entry.to_ne_bytes());
+                    true
+                }
+                None => false,
+            }
+        }
+    }
+
+    #[test]
+    fn test_read_batch_stops_at_limit() {
+        let mut ring = FakeRing::new(0..5);
+        let read = Mutex::new(Vec::new());
+        let handler = |entry: u32| read.lock().unwrap().push(entry);
+
+        assert_eq!(read_batch(&mut ring, 3, &handler), 3);
+        assert_eq!(*read.lock().unwrap(), [0, 1, 2]);
+        assert_eq!(read_batch(&mut ring, 3, &handler), 2);
+        assert_eq!(read_batch(&mut ring, 3, &handler), 0);
+        assert_eq!(*read.lock().unwrap(), [0, 1, 2, 3, 4]);
+    }
+
+    #[tokio::test]
+    async fn test_reader_drains_ring_on_stop() {
+        let pipeline = Pipeline::new(Arc::new(ProbeMetrics::new().unwrap())).start(16);
+        let entries = 2 * RINGBUF_BATCH_SIZE as u32 + 1;
+        let ring = AsyncFd::new(FakeRing::new(0..entries)).unwrap();
+        let read = Arc::new(Mutex::new(Vec::new()));
+
+        let handler_read = read.clone();
+        pipeline.sender().spawn_producer(move |stop| {
+            read_ringbuf(ring, stop, "FAKE_EVENTS".to_string(), move |entry: u32| {
+                handler_read.lock().unwrap().push(entry)
+            })
+        });
+        tokio::task::yield_now().await;
+        // Never woken, so nothing is read before shutdown
+        assert!(read.lock().unwrap().is_empty());
+
+        tokio::time::timeout(std::time::Duration::from_secs(5), pipeline.shutdown())
+            .await
+            .unwrap();
+        assert_eq!(*read.lock().unwrap(), (0..entries).collect::<Vec<_>>());
+    }
 }
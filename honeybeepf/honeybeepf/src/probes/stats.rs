@@ -0,0 +1,124 @@
+//! Ringbuf outcomes counted by the eBPF programs, exported as metrics.
+
+use std::{sync::Arc, time::Duration};
+
+use anyhow::{Context, Result};
+use aya::{
+    Ebpf,
+    maps::{MapData, PerCpuArray},
+};
+use honeybeepf_common::{
+    EMIT_STATS_ENTRIES, EMIT_STATS_MAP, EmitCounter, EmitProbe, emit_stat_index,
+};
+use log::warn;
+
+use crate::pipeline::EventSender;
+use crate::telemetry::metrics::ProbeMetrics;
+
+/// How often the per-CPU counters are summed into the metrics.
+pub const EMIT_STATS_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Copies the kernel's per-CPU counters into `ProbeMetrics`.
+pub struct EmitStats {
+    map: PerCpuArray<MapData, u64>,
+    /// Totals already added to the metrics, by map index.
+    reported: Vec<u64>,
+    metrics: Arc<ProbeMetrics>,
+}
+
+impl EmitStats {
+    pub fn new(bpf: &mut Ebpf, metrics: Arc<ProbeMetrics>) -> Result<Self> {
+        let map = bpf
+            .take_map(EMIT_STATS_MAP)
+            .with_context(|| format!("Failed to find {} map", EMIT_STATS_MAP))?;
+        Ok(Self {
+            map: PerCpuArray::try_from(map)?,
+            reported: vec![0; EMIT_STATS_ENTRIES as usize],
+            metrics,
+        })
+    }
+
+    /// Adds whatever the kernel counted since the previous call to the metrics.
+    pub fn update(&mut self) -> Result<()> {
+        for probe in EmitProbe::ALL {
+            for counter in EmitCounter::ALL {
+                let index = emit_stat_index(probe, counter);
+                let total: u64 = self.map.get(&index, 0)?.iter().sum();
+                let delta = advance(&mut self.reported[index as usize], total);
+                if delta == 0 {
+                    continue;
+                }
+
+                self.metrics
+                    .kernel_events(probe.as_str(), counter.as_str())
+                    .inc_by(delta);
+                if counter == EmitCounter::ReserveFailed {
+                    warn!(
+                        "Ringbuf for {} probe was full; {} events lost in the last {:?}",
+                        probe.as_str(),
+                        delta,
+                        EMIT_STATS_INTERVAL
+                    );
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Updates every `EMIT_STATS_INTERVAL`, and once more at shutdown so that
+    /// events lost while draining are still counted.
+    pub fn spawn(mut self, events: &EventSender) {
+        events.spawn_producer(move |stop| async move {
+            let mut tick = tokio::time::interval(EMIT_STATS_INTERVAL);
+            loop {
+                let stopping = tokio::select! {
+                    _ = tick.tick() => false,
+                    _ = stop.cancelled() => true,
+                };
+                if let Err(e) = self.update() {
+                    warn!("Failed to read {} map: {:#}", EMIT_STATS_MAP, e);
+                }
+                if stopping {
+                    return;
+                }
+            }
+        });
+    }
+}
+
+/// Records `total` as reported and returns how much it grew.
+fn advance(reported: &mut u64, total: u64) -> u64 {
+    let delta = total.saturating_sub(*reported);
+    *reported = total;
+    delta
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stat_indexes_are_unique_and_in_range() {
+        let mut seen = vec![false; EMIT_STATS_ENTRIES as usize];
+        for probe in EmitProbe::ALL {
+            for counter in EmitCounter::ALL {
+                let index = emit_stat_index(probe, counter) as usize;
+                assert!(
+                    !seen[index],
+                    "{:?}/{:?} reuses index {}",
+                    probe, counter, index
+                );
+                seen[index] = true;
+            }
+        }
+        assert!(seen.iter().all(|&used| used));
+    }
+
+    #[test]
+    fn test_advance_reports_growth_only() {
+        let mut reported = 0;
+        assert_eq!(advance(&mut reported, 5), 5);
+        assert_eq!(advance(&mut reported, 5), 0);
+        assert_eq!(advance(&mut reported, 12), 7);
+    }
+}
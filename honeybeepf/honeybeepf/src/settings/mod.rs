@@ -0,0 +1,450 @@
+use std::{
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use config::{Config, ConfigError, Environment, File};
+use honeybeepf_common::{BlockIoOp, RwbsFlag};
+use serde::Deserialize;
+
+use crate::{events::EventKind, pipeline::zones::ZoneTable, probes::custom::CustomProbeConfig};
+
+mod validation;
+mod watch;
+
+pub use validation::{
+    ConfigIssue, MAX_PROBE_INTERVAL_SECONDS, MAX_RING_BUFFER_BYTES, ValidationError,
+};
+pub use watch::ConfigWatcher;
+
+/// Environment variable naming the optional TOML/YAML config file.
+pub const CONFIG_FILE_ENV: &str = "HONEYBEEPF_CONFIG";
+
+const DEFAULT_PROBE_INTERVAL_SECONDS: u32 = 60;
+const DEFAULT_METRICS_PORT: u16 = 9464;
+const DEFAULT_METRICS_PATH: &str = "/metrics";
+const DEFAULT_SHUTDOWN_TIMEOUT_SECONDS: u64 = 10;
+
+const SINK_NAMES: [&str; 4] = ["log", "metrics", "otlp", "file"];
+/// Sink settings given as comma-separated lists in the environment.
+const SINK_LIST_KEYS: [&str; 3] = ["events", "block_io_ops", "block_io_flags"];
+
+#[derive(Debug, Deserialize, Clone)]
+#[allow(unused)]
+pub struct BuiltinProbes {
+    /// Block IO requests, from the `block` tracepoints. Kernels without
+    /// `block_io_start`/`block_io_done` or `block_rq_issue`/`block_rq_complete`
+    /// are not supported; there is no kprobe fallback.
+    pub block_io: Option<bool>,
+    /// How block IO is reported; `events` unless set.
+    pub block_io_mode: Option<BlockIoMode>,
+    pub network_latency: Option<bool>,
+    /// TCP retransmits and resets, from the `tcp` tracepoints. A socket's
+    /// retransmits are reported at most once per `interval`, with their count.
+    pub tcp_retransmit: Option<bool>,
+    /// Bytes sent and received per cgroup and peer, reported every `interval`.
+    pub tcp_traffic: Option<bool>,
+    pub gpu_open: Option<bool>,
+    pub interval: Option<u32>,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum BlockIoMode {
+    /// Every request start and completion is sent to user space.
+    #[default]
+    Events,
+    /// The kernel keeps log2 latency and size histograms per cgroup, device and
+    /// operation, which are exported every `builtin_probes.interval`.
+    Histograms,
+}
+
+#[derive(Debug, Deserialize, Clone, Default)]
+#[allow(unused)]
+pub struct MetricsSettings {
+    pub enabled: Option<bool>,
+    pub port: Option<u16>,
+    pub path: Option<String>,
+}
+
+impl MetricsSettings {
+    /// The Prometheus endpoint is on unless explicitly disabled, matching the Helm chart.
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.unwrap_or(true)
+    }
+
+    pub fn port(&self) -> u16 {
+        self.port.unwrap_or(DEFAULT_METRICS_PORT)
+    }
+
+    pub fn path(&self) -> String {
+        self.path
+            .clone()
+            .unwrap_or_else(|| DEFAULT_METRICS_PATH.to_string())
+    }
+}
+
+/// Ringbuf size in bytes per probe. Only read when the eBPF object is loaded, so
+/// changes need a restart; unset entries keep the size compiled into the object.
+#[derive(Debug, Deserialize, Clone, Default, PartialEq)]
+#[allow(unused)]
+pub struct RingBufferSettings {
+    pub block_io: Option<u32>,
+    pub network_latency: Option<u32>,
+    pub tcp_retransmit: Option<u32>,
+    pub tcp_traffic: Option<u32>,
+    pub gpu_open: Option<u32>,
+    pub custom: Option<u32>,
+}
+
+impl RingBufferSettings {
+    pub fn size(&self, probe: &str) -> Option<u32> {
+        match probe {
+            "block_io" => self.block_io,
+            "network_latency" => self.network_latency,
+            "tcp_retransmit" => self.tcp_retransmit,
+            "tcp_traffic" => self.tcp_traffic,
+            "gpu_open" => self.gpu_open,
+            "custom" => self.custom,
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Clone, Default)]
+#[allow(unused)]
+pub struct SinkSettings {
+    pub enabled: Option<bool>,
+    /// Event kinds delivered to this sink; every kind when unset.
+    pub events: Option<Vec<EventKind>>,
+    /// Block IO operations delivered to this sink; every operation when unset.
+    pub block_io_ops: Option<Vec<BlockIoOp>>,
+    /// Flags a block IO request must all carry to be delivered, e.g. `sync`.
+    pub block_io_flags: Option<Vec<RwbsFlag>>,
+    pub queue_size: Option<usize>,
+    /// Output path, only used by the file sink.
+    pub path: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Clone, Default)]
+#[allow(unused)]
+pub struct SinksSettings {
+    #[serde(default)]
+    pub log: SinkSettings,
+    #[serde(default)]
+    pub metrics: SinkSettings,
+    #[serde(default)]
+    pub otlp: SinkSettings,
+    #[serde(default)]
+    pub file: SinkSettings,
+}
+
+/// Named address ranges network peers are labelled with, e.g. the pod CIDR.
+/// A peer in several ranges gets the zone of the most specific one.
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+pub struct NetworkZone {
+    pub name: String,
+    /// Ranges in CIDR notation; a bare address is a single host.
+    pub cidrs: Vec<String>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+#[allow(unused)]
+pub struct Settings {
+    pub otel_exporter_otlp_endpoint: Option<String>,
+    pub otel_exporter_otlp_protocol: Option<String>,
+    pub builtin_probes: BuiltinProbes,
+    /// JSON document, as set by `CUSTOM_PROBE_CONFIG`; takes precedence over `custom_probes`.
+    pub custom_probe_config: Option<String>,
+    /// Structured form of the custom probe definitions, for config files.
+    pub custom_probes: Option<CustomProbeConfig>,
+    #[serde(default)]
+    pub metrics: MetricsSettings,
+    #[serde(default)]
+    pub sinks: SinksSettings,
+    #[serde(default)]
+    pub ring_buffers: RingBufferSettings,
+    /// Zones network events and byte counters are labelled with. Only read at
+    /// startup, like the sinks.
+    pub network_zones: Option<Vec<NetworkZone>>,
+    /// How long shutdown may spend draining ringbufs and flushing sinks.
+    pub shutdown_timeout_seconds: Option<u64>,
+}
+
+fn build_config(config_file: Option<&Path>) -> Result<Config, ConfigError> {
+    // Load .env file if it exists
+    dotenvy::dotenv().ok();
+
+    let mut builder = Config::builder();
+    if let Some(path) = config_file {
+        // Format is picked from the extension (.toml, .yaml, .yml, .json)
+        builder = builder.add_source(File::from(path).required(true));
+    }
+
+    // Map flat environment variables to nested structure keys
+    // Use __ as separator for nested keys (e.g. BUILTIN_PROBES__BLOCK_IO -> builtin_probes.block_io)
+    let mut environment = Environment::default()
+        .separator("__")
+        .try_parsing(true)
+        .list_separator(",");
+    for sink in SINK_NAMES {
+        for key in SINK_LIST_KEYS {
+            environment = environment.with_list_parse_key(&format!("sinks.{}.{}", sink, key));
+        }
+    }
+
+    builder.add_source(environment).build()
+}
+
+impl Settings {
+    /// Loads settings using the config file named by `HONEYBEEPF_CONFIG`, if any.
+    pub fn new() -> Result<Self, ConfigError> {
+        let config_file = std::env::var_os(CONFIG_FILE_ENV).map(PathBuf::from);
+        Self::load(config_file.as_deref())
+    }
+
+    /// Loads settings from an optional TOML/YAML file, with environment variables layered on top.
+    pub fn load(config_file: Option<&Path>) -> Result<Self, ConfigError> {
+        build_config(config_file)?.try_deserialize()
+    }
+
+    /// Like [`Settings::load`], but rejects unknown keys and out-of-range values,
+    /// reporting every problem together with its key and source.
+    pub fn load_validated(config_file: Option<&Path>) -> Result<Self, ValidationError> {
+        validation::load_validated(config_file)
+    }
+
+    /// Custom probe definitions from `CUSTOM_PROBE_CONFIG` or, failing that, the config file.
+    pub fn custom_probes(&self) -> anyhow::Result<Option<CustomProbeConfig>> {
+        if let Some(raw) = self.custom_probe_config.as_deref()
+            && !raw.trim().is_empty()
+        {
+            return CustomProbeConfig::parse(raw).map(Some);
+        }
+
+        match &self.custom_probes {
+            Some(config) => {
+                config.validate()?;
+                Ok(Some(config.clone()))
+            }
+            None => Ok(None),
+        }
+    }
+
+    pub fn zone_table(&self) -> anyhow::Result<ZoneTable> {
+        ZoneTable::new(self.network_zones.as_deref().unwrap_or_default())
+    }
+
+    pub fn shutdown_timeout(&self) -> Duration {
+        Duration::from_secs(
+            self.shutdown_timeout_seconds
+                .unwrap_or(DEFAULT_SHUTDOWN_TIMEOUT_SECONDS),
+        )
+    }
+
+    /// Period of the builtin probes' aggregated reports.
+    pub fn probe_interval(&self) -> Duration {
+        Duration::from_secs(self.probe_interval_seconds().into())
+    }
+
+    fn probe_interval_seconds(&self) -> u32 {
+        // Use a sensible non-zero default interval (in seconds) when not configured
+        self.builtin_probes
+            .interval
+            .unwrap_or(DEFAULT_PROBE_INTERVAL_SECONDS)
+    }
+
+    pub fn to_common_config(&self) -> honeybeepf_common::CommonConfig {
+        // Convert Option<bool> / Option<u32> to primitive POD types
+        let probe_block_io = self.builtin_probes.block_io.unwrap_or(false);
+        let block_io_histograms =
+            self.builtin_probes.block_io_mode.unwrap_or_default() == BlockIoMode::Histograms;
+        let probe_network_latency = self.builtin_probes.network_latency.unwrap_or(false);
+        let probe_tcp_retransmit = self.builtin_probes.tcp_retransmit.unwrap_or(false);
+        let probe_tcp_traffic = self.builtin_probes.tcp_traffic.unwrap_or(false);
+        let probe_gpu_open = self.builtin_probes.gpu_open.unwrap_or(false);
+
+        honeybeepf_common::CommonConfig {
+            probe_block_io: probe_block_io as u8,
+            probe_network_latency: probe_network_latency as u8,
+            probe_gpu_open: probe_gpu_open as u8,
+            probe_tcp_retransmit: probe_tcp_retransmit as u8,
+            probe_tcp_traffic: probe_tcp_traffic as u8,
+            block_io_histograms: block_io_histograms as u8,
+            probe_interval: self.probe_interval_seconds(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serial_test::serial;
+
+    use super::*;
+
+    #[test]
+    #[serial]
+    fn test_load_settings() {
+        // Ensure .env is loaded
+        dotenvy::dotenv().ok();
+
+        // Manual override for testing deterministic values
+        unsafe {
+            std::env::set_var("BUILTIN_PROBES__BLOCK_IO", "true");
+            std::env::set_var("BUILTIN_PROBES__INTERVAL", "42");
+        }
+
+        let settings = Settings::new().expect("Failed to load settings");
+
+        assert_eq!(settings.builtin_probes.block_io, Some(true));
+        assert_eq!(settings.builtin_probes.interval, Some(42));
+    }
+
+    #[test]
+    #[serial]
+    fn test_load_file_under_env_overrides() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("honeybeepf.toml");
+        std::fs::write(
+            &path,
+            r#"
+otel_exporter_otlp_endpoint = "http://collector:4317"
+
+[builtin_probes]
+block_io = false
+block_io_mode = "histograms"
+gpu_open = true
+interval = 5
+
+[sinks.file]
+path = "/var/log/honeybeepf/events.jsonl"
+events = ["gpu_open"]
+
+[[custom_probes.kprobes]]
+name = "tcp_connect"
+function = "tcp_connect"
+args = [{ name = "sk", index = 0 }]
+"#,
+        )
+        .unwrap();
+
+        unsafe {
+            std::env::set_var("BUILTIN_PROBES__BLOCK_IO", "true");
+            std::env::remove_var("BUILTIN_PROBES__INTERVAL");
+        }
+
+        let settings = Settings::load(Some(&path)).expect("Failed to load settings");
+
+        // Env wins over the file, the file fills in everything else
+        assert_eq!(settings.builtin_probes.block_io, Some(true));
+        assert_eq!(settings.builtin_probes.gpu_open, Some(true));
+        assert_eq!(
+            settings.builtin_probes.block_io_mode,
+            Some(BlockIoMode::Histograms)
+        );
+        assert_eq!(settings.builtin_probes.interval, Some(5));
+        assert_eq!(settings.sinks.file.events, Some(vec![EventKind::GpuOpen]));
+
+        let custom = settings.custom_probes().unwrap().unwrap();
+        assert_eq!(custom.kprobes[0].function, "tcp_connect");
+        assert_eq!(custom.kprobes[0].args[0].index, 0);
+    }
+
+    #[test]
+    #[serial]
+    fn test_load_yaml_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("honeybeepf.yaml");
+        std::fs::write(
+            &path,
+            "builtin_probes:\n  network_latency: true\nmetrics:\n  port: 9000\n",
+        )
+        .unwrap();
+
+        unsafe {
+            std::env::set_var("BUILTIN_PROBES__BLOCK_IO", "true");
+        }
+
+        let settings = Settings::load(Some(&path)).expect("Failed to load settings");
+        assert_eq!(settings.builtin_probes.network_latency, Some(true));
+        assert_eq!(settings.metrics.port(), 9000);
+    }
+
+    #[test]
+    #[serial]
+    fn test_missing_config_file_is_an_error() {
+        assert!(Settings::load(Some(Path::new("/nonexistent/honeybeepf.toml"))).is_err());
+    }
+
+    #[test]
+    #[serial]
+    fn test_load_sink_event_lists() {
+        unsafe {
+            std::env::set_var("BUILTIN_PROBES__BLOCK_IO", "true");
+            std::env::set_var("SINKS__FILE__EVENTS", "block_io,gpu_open");
+            std::env::set_var("SINKS__FILE__PATH", "/tmp/honeybeepf-events.jsonl");
+            std::env::set_var("SINKS__LOG__BLOCK_IO_OPS", "write,flush");
+            std::env::set_var("SINKS__LOG__BLOCK_IO_FLAGS", "sync");
+        }
+
+        let settings = Settings::new().expect("Failed to load settings");
+
+        unsafe {
+            std::env::remove_var("SINKS__FILE__EVENTS");
+            std::env::remove_var("SINKS__FILE__PATH");
+            std::env::remove_var("SINKS__LOG__BLOCK_IO_OPS");
+            std::env::remove_var("SINKS__LOG__BLOCK_IO_FLAGS");
+        }
+
+        assert_eq!(
+            settings.sinks.file.events,
+            Some(vec![EventKind::BlockIo, EventKind::GpuOpen])
+        );
+        assert_eq!(
+            settings.sinks.file.path.as_deref(),
+            Some("/tmp/honeybeepf-events.jsonl")
+        );
+        assert_eq!(settings.sinks.log.events, None);
+        assert_eq!(
+            settings.sinks.log.block_io_ops,
+            Some(vec![BlockIoOp::Write, BlockIoOp::Flush])
+        );
+        assert_eq!(
+            settings.sinks.log.block_io_flags,
+            Some(vec![RwbsFlag::Sync])
+        );
+    }
+
+    #[test]
+    fn test_to_common_config() {
+        let settings = Settings {
+            otel_exporter_otlp_endpoint: None,
+            otel_exporter_otlp_protocol: None,
+            builtin_probes: BuiltinProbes {
+                block_io: Some(true),
+                block_io_mode: Some(BlockIoMode::Histograms),
+                network_latency: None, // Should default to false (0)
+                tcp_retransmit: None,  // Should default to false
+                tcp_traffic: Some(true),
+                gpu_open: None, // Should default to false
+                interval: None, // Should default to constant
+            },
+            custom_probe_config: None,
+            custom_probes: None,
+            metrics: MetricsSettings::default(),
+            sinks: SinksSettings::default(),
+            ring_buffers: RingBufferSettings::default(),
+            network_zones: None,
+            shutdown_timeout_seconds: None,
+        };
+
+        let common = settings.to_common_config();
+
+        assert_eq!(common.probe_block_io, 1);
+        assert_eq!(common.block_io_histograms, 1);
+        assert_eq!(common.probe_network_latency, 0);
+        assert_eq!(common.probe_tcp_retransmit, 0);
+        assert_eq!(common.probe_tcp_traffic, 1);
+        assert_eq!(common.probe_interval, DEFAULT_PROBE_INTERVAL_SECONDS);
+    }
+}
@@ -0,0 +1,573 @@
+use std::{fmt, path::Path};
+
+use config::{Config, ConfigError, Value, ValueKind};
+
+use super::{NetworkZone, Settings, SinkSettings};
+use crate::{
+    pipeline::zones::{Cidr, ZoneTable},
+    telemetry::otlp::{OtlpProtocol, validate_endpoint},
+};
+
+/// Longest accepted `builtin_probes.interval`, in seconds.
+pub const MAX_PROBE_INTERVAL_SECONDS: u32 = 86_400;
+
+/// Largest accepted `ring_buffers.*` size; each one is locked kernel memory.
+pub const MAX_RING_BUFFER_BYTES: u32 = 1 << 30;
+
+/// Origin the config crate assigns to values read from environment variables.
+const ENV_ORIGIN: &str = "the environment";
+
+/// Prefixes of top-level environment variables that belong to the agent. Every
+/// other variable in the process environment is expected and ignored.
+const ENV_PREFIXES: [&str; 8] = [
+    "builtin_probes",
+    "metrics",
+    "sinks",
+    "ring_buffers",
+    "network_zones",
+    "custom_probe",
+    "otel_exporter_otlp",
+    "shutdown",
+];
+
+/// A single problem found in the configuration.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigIssue {
+    /// Dotted settings key, when the problem is tied to one.
+    pub key: Option<String>,
+    /// Where the value came from: an environment variable, a file or the defaults.
+    pub source: String,
+    pub message: String,
+}
+
+impl fmt::Display for ConfigIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.key {
+            Some(key) => write!(f, "{} ({}): {}", key, self.source, self.message),
+            None => write!(f, "{}: {}", self.source, self.message),
+        }
+    }
+}
+
+/// Every problem found while loading the configuration.
+#[derive(Debug)]
+pub struct ValidationError {
+    pub issues: Vec<ConfigIssue>,
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid configuration ({} problem", self.issues.len())?;
+        if self.issues.len() != 1 {
+            write!(f, "s")?;
+        }
+        write!(f, ")")?;
+        for issue in &self.issues {
+            write!(f, "\n  - {}", issue)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ValidationError {}
+
+#[derive(Debug, Clone)]
+enum Segment {
+    Key(String),
+    Index(usize),
+}
+
+fn segments(path: &serde_ignored::Path, out: &mut Vec<Segment>) {
+    match path {
+        serde_ignored::Path::Root => {}
+        serde_ignored::Path::Seq { parent, index } => {
+            segments(parent, out);
+            out.push(Segment::Index(*index));
+        }
+        serde_ignored::Path::Map { parent, key } => {
+            segments(parent, out);
+            out.push(Segment::Key(key.clone()));
+        }
+        serde_ignored::Path::Some { parent }
+        | serde_ignored::Path::NewtypeStruct { parent }
+        | serde_ignored::Path::NewtypeVariant { parent } => segments(parent, out),
+    }
+}
+
+/// Renders a path as a settings key, e.g. `custom_probes.kprobes[0].name`.
+fn key_string(path: &[Segment]) -> String {
+    let mut key = String::new();
+    for segment in path {
+        match segment {
+            Segment::Key(name) => {
+                if !key.is_empty() {
+                    key.push('.');
+                }
+                key.push_str(name);
+            }
+            Segment::Index(index) => key.push_str(&format!("[{}]", index)),
+        }
+    }
+    key
+}
+
+/// Finds where a value came from, looking into tables built up from several leaves.
+fn origin(value: &Value) -> Option<String> {
+    if let Some(origin) = value.origin() {
+        return Some(origin.to_string());
+    }
+    match &value.kind {
+        ValueKind::Table(table) => table.values().find_map(origin),
+        ValueKind::Array(array) => array.iter().find_map(origin),
+        _ => None,
+    }
+}
+
+fn env_var_name(key: &str) -> String {
+    key.split('[')
+        .next()
+        .unwrap_or(key)
+        .replace('.', "__")
+        .to_ascii_uppercase()
+}
+
+struct Checker<'a> {
+    config: &'a Config,
+    issues: Vec<ConfigIssue>,
+}
+
+impl Checker<'_> {
+    fn lookup(&self, key: &str) -> Option<&Value> {
+        let mut value = &self.config.cache;
+        for part in key.split('.') {
+            let mut indices = part.split('[');
+            let name = indices.next().unwrap_or_default();
+            value = match &value.kind {
+                ValueKind::Table(table) => table.get(name)?,
+                _ => return None,
+            };
+            for index in indices {
+                let index: usize = index.trim_end_matches(']').parse().ok()?;
+                value = match &value.kind {
+                    ValueKind::Array(array) => array.get(index)?,
+                    _ => return None,
+                };
+            }
+        }
+        Some(value)
+    }
+
+    fn origin_of(&self, key: &str) -> Option<String> {
+        self.lookup(key).and_then(origin)
+    }
+
+    fn describe_origin(key: &str, origin: Option<&str>) -> String {
+        match origin {
+            Some(ENV_ORIGIN) => format!("environment variable {}", env_var_name(key)),
+            Some(path) => format!("file {}", path),
+            None => "default".to_string(),
+        }
+    }
+
+    fn push(&mut self, key: &str, message: impl Into<String>) {
+        let origin = self.origin_of(key);
+        self.issues.push(ConfigIssue {
+            key: Some(key.to_string()),
+            source: Self::describe_origin(key, origin.as_deref()),
+            message: message.into(),
+        });
+    }
+
+    fn unknown_key(&mut self, path: &[Segment]) {
+        let key = key_string(path);
+        let origin = self.origin_of(&key);
+
+        if path.len() == 1 && origin.as_deref() == Some(ENV_ORIGIN) {
+            let is_table = self
+                .lookup(&key)
+                .is_some_and(|value| matches!(value.kind, ValueKind::Table(_)));
+            let ours = ENV_PREFIXES.iter().any(|prefix| key.starts_with(prefix));
+            if !is_table && !ours {
+                return;
+            }
+        }
+
+        self.push(&key, "unknown key");
+    }
+
+    fn deserialize_error(&mut self, error: ConfigError) {
+        match error {
+            ConfigError::Type {
+                origin,
+                unexpected,
+                expected,
+                key: Some(key),
+            } => self.issues.push(ConfigIssue {
+                source: Self::describe_origin(&key, origin.as_deref()),
+                key: Some(key),
+                message: format!("invalid type: {}, expected {}", unexpected, expected),
+            }),
+            other => self.issues.push(ConfigIssue {
+                key: None,
+                source: "settings".to_string(),
+                message: other.to_string(),
+            }),
+        }
+    }
+
+    fn check(&mut self, settings: &Settings) {
+        if let Some(interval) = settings.builtin_probes.interval
+            && !(1..=MAX_PROBE_INTERVAL_SECONDS).contains(&interval)
+        {
+            self.push(
+                "builtin_probes.interval",
+                format!(
+                    "must be between 1 and {} seconds, got {}",
+                    MAX_PROBE_INTERVAL_SECONDS, interval
+                ),
+            );
+        }
+
+        if let Some(protocol) = settings.otel_exporter_otlp_protocol.as_deref()
+            && let Err(e) = protocol.parse::<OtlpProtocol>()
+        {
+            self.push("otel_exporter_otlp_protocol", e.to_string());
+        }
+
+        if let Some(endpoint) = settings.otel_exporter_otlp_endpoint.as_deref()
+            && !endpoint.trim().is_empty()
+            && let Err(e) = validate_endpoint(endpoint)
+        {
+            self.push("otel_exporter_otlp_endpoint", e.to_string());
+        }
+
+        if settings.metrics.port == Some(0) {
+            self.push("metrics.port", "must be between 1 and 65535");
+        }
+        if let Some(path) = settings.metrics.path.as_deref()
+            && !path.starts_with('/')
+        {
+            self.push(
+                "metrics.path",
+                format!("must start with '/', got '{}'", path),
+            );
+        }
+
+        let ring_buffers = &settings.ring_buffers;
+        let page_size = page_size();
+        for (name, size) in [
+            ("block_io", ring_buffers.block_io),
+            ("network_latency", ring_buffers.network_latency),
+            ("tcp_retransmit", ring_buffers.tcp_retransmit),
+            ("tcp_traffic", ring_buffers.tcp_traffic),
+            ("gpu_open", ring_buffers.gpu_open),
+            ("custom", ring_buffers.custom),
+        ] {
+            if let Some(size) = size
+                && let Err(message) = check_ring_buffer_size(size, page_size)
+            {
+                self.push(&format!("ring_buffers.{}", name), message);
+            }
+        }
+
+        if settings.shutdown_timeout_seconds == Some(0) {
+            self.push("shutdown_timeout_seconds", "must be at least 1 second");
+        }
+
+        let sinks = &settings.sinks;
+        for (name, sink) in [
+            ("log", &sinks.log),
+            ("metrics", &sinks.metrics),
+            ("otlp", &sinks.otlp),
+            ("file", &sinks.file),
+        ] {
+            self.check_sink(name, sink);
+        }
+        if sinks.file.enabled == Some(true) && sinks.file.path.is_none() {
+            self.push(
+                "sinks.file.enabled",
+                "the file sink requires sinks.file.path",
+            );
+        }
+
+        if let Some(zones) = &settings.network_zones {
+            self.check_zones(zones);
+        }
+
+        if let Some(raw) = settings.custom_probe_config.as_deref()
+            && !raw.trim().is_empty()
+        {
+            if let Err(e) = crate::probes::custom::CustomProbeConfig::parse(raw) {
+                self.push("custom_probe_config", format!("{:#}", e));
+            }
+        } else if let Some(custom) = &settings.custom_probes
+            && let Err(e) = custom.validate()
+        {
+            self.push("custom_probes", format!("{:#}", e));
+        }
+    }
+
+    fn check_zones(&mut self, zones: &[NetworkZone]) {
+        let issues = self.issues.len();
+        for (i, zone) in zones.iter().enumerate() {
+            if zone.name.trim().is_empty() {
+                self.push(&format!("network_zones[{}].name", i), "must not be empty");
+            }
+            for (j, cidr) in zone.cidrs.iter().enumerate() {
+                if let Err(e) = cidr.parse::<Cidr>() {
+                    self.push(&format!("network_zones[{}].cidrs[{}]", i, j), e.to_string());
+                }
+            }
+        }
+        // Ranges claimed by two zones, once every range parses
+        if self.issues.len() == issues
+            && let Err(e) = ZoneTable::new(zones)
+        {
+            self.push("network_zones", format!("{:#}", e));
+        }
+    }
+
+    fn check_sink(&mut self, name: &str, sink: &SinkSettings) {
+        if sink.queue_size == Some(0) {
+            self.push(
+                &format!("sinks.{}.queue_size", name),
+                "must be greater than 0",
+            );
+        }
+        if let Some(path) = sink.path.as_deref() {
+            if name != "file" {
+                self.push(
+                    &format!("sinks.{}.path", name),
+                    "only the file sink writes to a path",
+                );
+            } else if path.trim().is_empty() {
+                self.push("sinks.file.path", "must not be empty");
+            }
+        }
+    }
+}
+
+/// Loads settings and collects every unknown key and invalid value instead of stopping at the first.
+pub(super) fn load_validated(config_file: Option<&Path>) -> Result<Settings, ValidationError> {
+    let config = super::build_config(config_file).map_err(|e| ValidationError {
+        issues: vec![ConfigIssue {
+            key: None,
+            source: config_file
+                .map(|path| format!("file {}", path.display()))
+                .unwrap_or_else(|| "settings".to_string()),
+            message: e.to_string(),
+        }],
+    })?;
+
+    let mut unknown = Vec::new();
+    let result: Result<Settings, ConfigError> =
+        serde_ignored::deserialize(config.clone(), |path| {
+            let mut path_segments = Vec::new();
+            segments(&path, &mut path_segments);
+            unknown.push(path_segments);
+        });
+
+    let mut checker = Checker {
+        config: &config,
+        issues: Vec::new(),
+    };
+    for path in &unknown {
+        checker.unknown_key(path);
+    }
+
+    match result {
+        Ok(settings) => {
+            checker.check(&settings);
+            if checker.issues.is_empty() {
+                return Ok(settings);
+            }
+        }
+        Err(e) => checker.deserialize_error(e),
+    }
+
+    Err(ValidationError {
+        issues: checker.issues,
+    })
+}
+
+fn page_size() -> u32 {
+    let size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) };
+    if size > 0 { size as u32 } else { 4096 }
+}
+
+/// The kernel only accepts ringbufs that are a power-of-two multiple of the page size.
+fn check_ring_buffer_size(size: u32, page_size: u32) -> Result<(), String> {
+    if size < page_size || !size.is_power_of_two() {
+        return Err(format!(
+            "must be a power of two of at least the page size ({} bytes), got {}",
+            page_size, size
+        ));
+    }
+    if size > MAX_RING_BUFFER_BYTES {
+        return Err(format!(
+            "must be at most {} bytes, got {}",
+            MAX_RING_BUFFER_BYTES, size
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use serial_test::serial;
+
+    use super::*;
+
+    fn with_env<T>(vars: &[(&str, &str)], f: impl FnOnce() -> T) -> T {
+        unsafe {
+            for (key, value) in vars {
+                std::env::set_var(key, value);
+            }
+        }
+        let result = f();
+        unsafe {
+            for (key, _) in vars {
+                std::env::remove_var(key);
+            }
+        }
+        result
+    }
+
+    fn issue_for<'a>(error: &'a ValidationError, key: &str) -> &'a ConfigIssue {
+        error
+            .issues
+            .iter()
+            .find(|issue| issue.key.as_deref() == Some(key))
+            .unwrap_or_else(|| panic!("no issue for {} in {}", key, error))
+    }
+
+    #[test]
+    #[serial]
+    fn test_reports_every_problem_with_its_source() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("honeybeepf.toml");
+        std::fs::write(
+            &path,
+            "[builtin_probes]\ninterval = 0\n\n[sinks.log]\nqueue_sise = 10\n",
+        )
+        .unwrap();
+
+        // Left behind by other settings tests; it would shadow the file's interval
+        unsafe {
+            std::env::remove_var("BUILTIN_PROBES__INTERVAL");
+        }
+
+        let error = with_env(
+            &[
+                ("BUILTIN_PROBES__BLOCK_IO", "true"),
+                ("BUILTIN_PROBES__BLOK_IO", "true"),
+                ("OTEL_EXPORTER_OTLP_PROTOCOL", "http/json"),
+                ("OTEL_EXPORTER_OTLP_ENDPOINT", "https://collector:4317"),
+            ],
+            || Settings::load_validated(Some(&path)).unwrap_err(),
+        );
+
+        assert_eq!(error.issues.len(), 5, "{}", error);
+
+        let typo = issue_for(&error, "builtin_probes.blok_io");
+        assert_eq!(typo.source, "environment variable BUILTIN_PROBES__BLOK_IO");
+        assert_eq!(typo.message, "unknown key");
+
+        let interval = issue_for(&error, "builtin_probes.interval");
+        // The config crate reports file origins relative to the working directory
+        assert!(interval.source.starts_with("file "));
+        assert!(interval.source.ends_with("honeybeepf.toml"));
+
+        issue_for(&error, "sinks.log.queue_sise");
+        issue_for(&error, "otel_exporter_otlp_protocol");
+        issue_for(&error, "otel_exporter_otlp_endpoint");
+    }
+
+    #[test]
+    #[serial]
+    fn test_ignores_unrelated_environment() {
+        let settings = with_env(
+            &[
+                ("BUILTIN_PROBES__BLOCK_IO", "true"),
+                ("SOME_OTHER_TOOL_SETTING", "1"),
+            ],
+            || Settings::load_validated(None),
+        );
+        assert!(settings.is_ok(), "{}", settings.unwrap_err());
+    }
+
+    #[test]
+    #[serial]
+    fn test_reports_type_errors_with_key() {
+        let error = with_env(
+            &[
+                ("BUILTIN_PROBES__BLOCK_IO", "true"),
+                ("METRICS__PORT", "not-a-port"),
+            ],
+            || Settings::load_validated(None).unwrap_err(),
+        );
+
+        let port = issue_for(&error, "metrics.port");
+        assert_eq!(port.source, "environment variable METRICS__PORT");
+    }
+
+    #[test]
+    #[serial]
+    fn test_rejects_invalid_custom_probes() {
+        let error = with_env(
+            &[
+                ("BUILTIN_PROBES__BLOCK_IO", "true"),
+                ("CUSTOM_PROBE_CONFIG", r#"{"kprobes": [{"name": "a"}]}"#),
+            ],
+            || Settings::load_validated(None).unwrap_err(),
+        );
+
+        issue_for(&error, "custom_probe_config");
+    }
+
+    #[test]
+    #[serial]
+    fn test_rejects_invalid_network_zones() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("honeybeepf.toml");
+        let zones = |storage: &str| {
+            let config = format!(
+                "[builtin_probes]\nblock_io = true\n\n\
+                 [[network_zones]]\nname = \"pod\"\ncidrs = [\"10.244.0.0/16\"]\n\n\
+                 [[network_zones]]\nname = \"storage\"\ncidrs = [\"{}\"]\n",
+                storage
+            );
+            std::fs::write(&path, config).unwrap();
+            Settings::load_validated(Some(&path))
+        };
+
+        let settings = zones("10.244.7.0/24").unwrap();
+        let table = settings.zone_table().unwrap();
+        let zone = table.classify("10.244.7.1".parse().unwrap());
+        assert_eq!(zone.map(|zone| &**zone), Some("storage"));
+
+        let error = zones("10.244.7.0/16").unwrap_err();
+        let cidr = issue_for(&error, "network_zones[1].cidrs[0]");
+        assert!(cidr.source.starts_with("file "));
+        assert!(cidr.message.contains("host bits"), "{}", cidr.message);
+
+        let error = zones("10.244.0.0/16").unwrap_err();
+        let overlap = issue_for(&error, "network_zones");
+        assert!(
+            overlap
+                .message
+                .contains("in both zone pod and zone storage")
+        );
+    }
+
+    #[test]
+    fn test_ring_buffer_sizes() {
+        assert!(check_ring_buffer_size(4096, 4096).is_ok());
+        assert!(check_ring_buffer_size(4 << 20, 4096).is_ok());
+        assert!(check_ring_buffer_size(2048, 4096).is_err());
+        assert!(check_ring_buffer_size(3 << 20, 4096).is_err());
+        assert!(check_ring_buffer_size(1 << 31, 4096).is_err());
+        // 64 KiB pages, e.g. on some arm64 kernels
+        assert!(check_ring_buffer_size(16384, 65536).is_err());
+    }
+}
@@ -0,0 +1,57 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// Detects edits to the config file by comparing its contents, which also catches
+/// the symlink swap Kubernetes uses when it updates a mounted ConfigMap.
+pub struct ConfigWatcher {
+    path: PathBuf,
+    contents: Option<Vec<u8>>,
+}
+
+impl ConfigWatcher {
+    pub fn new(path: &Path) -> Self {
+        Self {
+            path: path.to_path_buf(),
+            contents: fs::read(path).ok(),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// True once per change since the previous call (or since creation).
+    pub fn changed(&mut self) -> bool {
+        let contents = fs::read(&self.path).ok();
+        if contents == self.contents {
+            return false;
+        }
+        self.contents = contents;
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detects_content_changes() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("honeybeepf.yaml");
+        fs::write(&path, "builtin_probes:\n  block_io: true\n").unwrap();
+
+        let mut watcher = ConfigWatcher::new(&path);
+        assert!(!watcher.changed());
+
+        fs::write(&path, "builtin_probes:\n  block_io: false\n").unwrap();
+        assert!(watcher.changed());
+        assert!(!watcher.changed());
+
+        // Rewriting identical contents is not a change
+        fs::write(&path, "builtin_probes:\n  block_io: false\n").unwrap();
+        assert!(!watcher.changed());
+    }
+}
@@ -0,0 +1,218 @@
+//! Histograms with power-of-two buckets, filled from counts the kernel has
+//! already aggregated instead of from individual observations.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{Arc, Mutex},
+};
+
+use anyhow::{Result, ensure};
+use honeybeepf_common::LOG2_SLOTS;
+use prometheus::{
+    Opts,
+    core::{Collector, Desc},
+    proto::{Bucket, Histogram, LabelPair, Metric, MetricFamily, MetricType},
+};
+
+const SLOTS: usize = LOG2_SLOTS as usize;
+
+/// A labelled histogram whose bucket `i` holds values in `[2^i, 2^(i+1))`, as
+/// produced by `honeybeepf_common::log2_slot`. The kernel counts whole units, so
+/// bucket `i` is exported with the inclusive bound `2^(i+1) - 1`.
+#[derive(Clone)]
+pub struct Log2HistogramVec {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    desc: Desc,
+    /// Multiplier from the kernel's unit to the exported one, e.g. 1e-9 for ns to s.
+    scale: f64,
+    series: Mutex<BTreeMap<Vec<String>, Series>>,
+}
+
+#[derive(Clone)]
+struct Series {
+    counts: [u64; SLOTS],
+    /// In the kernel's unit.
+    sum: u64,
+}
+
+impl Default for Series {
+    fn default() -> Self {
+        Self {
+            counts: [0; SLOTS],
+            sum: 0,
+        }
+    }
+}
+
+impl Log2HistogramVec {
+    pub fn new(opts: Opts, label_names: &[&str], scale: f64) -> Result<Self> {
+        let desc = Desc::new(
+            opts.fq_name(),
+            opts.help,
+            label_names.iter().map(ToString::to_string).collect(),
+            HashMap::new(),
+        )?;
+        Ok(Self {
+            inner: Arc::new(Inner {
+                desc,
+                scale,
+                series: Mutex::new(BTreeMap::new()),
+            }),
+        })
+    }
+
+    /// Adds `count` samples totalling `sum` to `slot` of the labelled series.
+    pub fn add(&self, label_values: &[&str], slot: usize, count: u64, sum: u64) -> Result<()> {
+        ensure!(
+            label_values.len() == self.inner.desc.variable_labels.len(),
+            "{} expects {} label values, got {}",
+            self.inner.desc.fq_name,
+            self.inner.desc.variable_labels.len(),
+            label_values.len()
+        );
+        ensure!(slot < SLOTS, "log2 slot {} out of range", slot);
+
+        let key = label_values.iter().map(ToString::to_string).collect();
+        let mut series = self.inner.series.lock().unwrap();
+        let series = series.entry(key).or_default();
+        series.counts[slot] += count;
+        series.sum += sum;
+        Ok(())
+    }
+
+    /// Drops the labelled series, if there is one.
+    pub fn remove(&self, label_values: &[&str]) {
+        let key: Vec<String> = label_values.iter().map(ToString::to_string).collect();
+        self.inner.series.lock().unwrap().remove(&key);
+    }
+
+    fn metric(&self, label_values: &[String], series: &Series) -> Metric {
+        let labels = self
+            .inner
+            .desc
+            .variable_labels
+            .iter()
+            .zip(label_values)
+            .map(|(name, value)| {
+                let mut label = LabelPair::default();
+                label.set_name(name.clone());
+                label.set_value(value.clone());
+                label
+            })
+            .collect();
+
+        // The last slot is open-ended, so it only shows up in the implicit +Inf bucket
+        let mut cumulative = 0;
+        let buckets = series.counts[..SLOTS - 1]
+            .iter()
+            .enumerate()
+            .map(|(slot, count)| {
+                cumulative += count;
+                let mut bucket = Bucket::default();
+                bucket.set_cumulative_count(cumulative);
+                bucket.set_upper_bound(upper_bound(slot) * self.inner.scale);
+                bucket
+            })
+            .collect();
+
+        let mut histogram = Histogram::default();
+        histogram.set_sample_count(series.counts.iter().sum());
+        histogram.set_sample_sum(series.sum as f64 * self.inner.scale);
+        histogram.set_bucket(buckets);
+
+        let mut metric = Metric::from_label(labels);
+        metric.set_histogram(histogram);
+        metric
+    }
+}
+
+/// The largest value `slot` holds, in the kernel's unit. Prometheus bounds are
+/// inclusive, and the kernel only counts whole units.
+fn upper_bound(slot: usize) -> f64 {
+    ((1u64 << (slot + 1)) - 1) as f64
+}
+
+impl Collector for Log2HistogramVec {
+    fn desc(&self) -> Vec<&Desc> {
+        vec![&self.inner.desc]
+    }
+
+    fn collect(&self) -> Vec<MetricFamily> {
+        let series = self.inner.series.lock().unwrap();
+        if series.is_empty() {
+            return Vec::new();
+        }
+
+        let mut family = MetricFamily::default();
+        family.set_name(self.inner.desc.fq_name.clone());
+        family.set_help(self.inner.desc.help.clone());
+        family.set_field_type(MetricType::HISTOGRAM);
+        family.set_metric(
+            series
+                .iter()
+                .map(|(labels, series)| self.metric(labels, series))
+                .collect(),
+        );
+        vec![family]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use honeybeepf_common::log2_slot;
+
+    use super::*;
+
+    #[test]
+    fn test_log2_slot_matches_bucket_bounds() {
+        assert_eq!(log2_slot(0), 0);
+        assert_eq!(log2_slot(1), 0);
+        assert_eq!(log2_slot(2), 1);
+        assert_eq!(log2_slot(3), 1);
+        assert_eq!(log2_slot(4096), 12);
+        assert_eq!(log2_slot(u64::MAX), LOG2_SLOTS - 1);
+        for value in [0u64, 1, 2, 7, 512, 4095, 4096, 1_000_000, 123_456_789_012] {
+            let slot = log2_slot(value) as usize;
+            assert!(
+                (value as f64) <= upper_bound(slot),
+                "{} above slot {}",
+                value,
+                slot
+            );
+            if slot > 0 {
+                assert!((value as f64) > upper_bound(slot - 1));
+            }
+        }
+    }
+
+    #[test]
+    fn test_collect_accumulates_and_scales() {
+        let histogram =
+            Log2HistogramVec::new(Opts::new("latency_seconds", "Latency"), &["device"], 1e-9)
+                .unwrap();
+        // Two intervals read from the kernel add up
+        histogram.add(&["259:1"], 10, 3, 3000).unwrap();
+        histogram.add(&["259:1"], 12, 1, 5000).unwrap();
+        histogram.add(&["259:1"], 10, 1, 1000).unwrap();
+        assert!(histogram.add(&["259:1", "extra"], 0, 1, 1).is_err());
+        assert!(histogram.add(&["259:1"], SLOTS, 1, 1).is_err());
+
+        let families = histogram.collect();
+        let metric = &families[0].get_metric()[0];
+        let h = metric.get_histogram();
+        assert_eq!(metric.get_label()[0].value(), "259:1");
+        assert_eq!(h.get_sample_count(), 5);
+        assert!((h.get_sample_sum() - 9e-6).abs() < 1e-12);
+        assert_eq!(h.get_bucket().len(), SLOTS - 1);
+        assert_eq!(h.get_bucket()[9].cumulative_count(), 0);
+        assert_eq!(h.get_bucket()[10].cumulative_count(), 4);
+        assert!((h.get_bucket()[10].upper_bound() - 2047e-9).abs() < 1e-15);
+        assert_eq!(h.get_bucket()[12].cumulative_count(), 5);
+
+        histogram.remove(&["259:1"]);
+        assert!(histogram.collect().is_empty());
+    }
+}
@@ -0,0 +1,791 @@
+use anyhow::Result;
+use honeybeepf_common::{
+    BlockIoEventType, BlockIoHistogramKey, BlockIoMeasure, BlockIoOp, Log2Bucket, TcpEventType,
+    dev_major, dev_minor, log2_slot,
+};
+use prometheus::{
+    Encoder, GaugeVec, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGaugeVec, Opts,
+    Registry, TextEncoder, proto::MetricFamily,
+};
+
+use crate::{
+    events::{BlockIo, Connection, Custom, GpuOpen, Tcp, TcpTraffic},
+    telemetry::log2::Log2HistogramVec,
+};
+
+const NAMESPACE: &str = "honeybeepf";
+
+/// Request size buckets in bytes, from 512B sectors up to 4MiB requests.
+const BLOCK_IO_SIZE_BUCKETS: [f64; 8] = [
+    512.0, 4096.0, 16384.0, 65536.0, 131072.0, 524288.0, 1048576.0, 4194304.0,
+];
+
+/// Completion latency buckets in seconds, from NVMe reads (~50us) to stalled disks.
+const BLOCK_IO_LATENCY_BUCKETS: [f64; 15] = [
+    0.00005, 0.0001, 0.00025, 0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0,
+    5.0,
+];
+
+/// connect() latency buckets in seconds, from loopback (~20us) to SYN retries
+/// running into the default timeout.
+const NETWORK_CONNECT_LATENCY_BUCKETS: [f64; 14] = [
+    0.0001, 0.00025, 0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 1.0, 3.0, 30.0,
+];
+
+/// Queue statistics of one block device over a sampling interval.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct BlockQueueStats {
+    /// Requests in flight at the last sample.
+    pub depth: u64,
+    /// Most requests in flight at any sample of the interval.
+    pub max_depth: u64,
+    /// Time-weighted mean number of requests in flight.
+    pub avg_depth: f64,
+    /// Fraction of the interval the device had requests in flight.
+    pub utilization: f64,
+    /// Requests in flight at each sample of the interval.
+    pub depth_samples: Vec<u64>,
+}
+
+/// Prometheus instruments fed by the builtin probes.
+pub struct ProbeMetrics {
+    registry: Registry,
+    block_io_events: IntCounterVec,
+    block_io_bytes: IntCounterVec,
+    block_io_request_size: HistogramVec,
+    block_io_latency: HistogramVec,
+    block_io_latency_log2: Log2HistogramVec,
+    block_io_size_log2: Log2HistogramVec,
+    block_io_errors: IntCounterVec,
+    block_io_queue_depth: IntGaugeVec,
+    block_io_queue_depth_max: IntGaugeVec,
+    block_io_queue_depth_avg: GaugeVec,
+    block_io_queue_depth_samples: Log2HistogramVec,
+    block_io_utilization: GaugeVec,
+    network_connections: IntCounterVec,
+    network_connect_latency: HistogramVec,
+    tcp_retransmits: IntCounterVec,
+    tcp_resets: IntCounterVec,
+    tcp_sent_bytes: IntCounterVec,
+    tcp_received_bytes: IntCounterVec,
+    gpu_opens: IntCounterVec,
+    custom_probe_hits: IntCounterVec,
+    kernel_events: IntCounterVec,
+    pipeline_dropped: IntCounterVec,
+    otlp_dropped: IntCounterVec,
+}
+
+impl ProbeMetrics {
+    pub fn new() -> Result<Self> {
+        let registry = Registry::new();
+
+        let block_io_events = IntCounterVec::new(
+            Opts::new("block_io_events_total", "Block IO tracepoint hits").namespace(NAMESPACE),
+            &["cgroup_id", "device", "event_type"],
+        )?;
+        let block_io_bytes = IntCounterVec::new(
+            Opts::new(
+                "block_io_bytes_total",
+                "Bytes transferred by completed block IO",
+            )
+            .namespace(NAMESPACE),
+            &["cgroup_id", "device", "op"],
+        )?;
+        let block_io_request_size = HistogramVec::new(
+            HistogramOpts::new(
+                "block_io_request_bytes",
+                "Size of completed block IO requests",
+            )
+            .namespace(NAMESPACE)
+            .buckets(BLOCK_IO_SIZE_BUCKETS.to_vec()),
+            &["device"],
+        )?;
+        let block_io_latency = HistogramVec::new(
+            HistogramOpts::new(
+                "block_io_latency_seconds",
+                "Time from block IO start to completion",
+            )
+            .namespace(NAMESPACE)
+            .buckets(BLOCK_IO_LATENCY_BUCKETS.to_vec()),
+            &["cgroup_id", "device", "op"],
+        )?;
+        // Filled from the kernel's histograms in `BlockIoMode::Histograms`
+        let block_io_latency_log2 = Log2HistogramVec::new(
+            Opts::new(
+                "block_io_latency_log2_seconds",
+                "Time from block IO start to completion, aggregated in the kernel",
+            )
+            .namespace(NAMESPACE),
+            &["cgroup_id", "device", "op"],
+            1e-9,
+        )?;
+        let block_io_size_log2 = Log2HistogramVec::new(
+            Opts::new(
+                "block_io_request_log2_bytes",
+                "Size of completed block IO requests, aggregated in the kernel",
+            )
+            .namespace(NAMESPACE),
+            &["cgroup_id", "device", "op"],
+            1.0,
+        )?;
+        let block_io_errors = IntCounterVec::new(
+            Opts::new("block_io_errors_total", "Block IO requests that failed")
+                .namespace(NAMESPACE),
+            &["cgroup_id", "device", "op", "error"],
+        )?;
+        let block_io_queue_depth = IntGaugeVec::new(
+            Opts::new("block_io_queue_depth", "Block IO requests in flight").namespace(NAMESPACE),
+            &["device"],
+        )?;
+        let block_io_queue_depth_max = IntGaugeVec::new(
+            Opts::new(
+                "block_io_queue_depth_max",
+                "Most block IO requests in flight during the last interval",
+            )
+            .namespace(NAMESPACE),
+            &["device"],
+        )?;
+        let block_io_queue_depth_avg = GaugeVec::new(
+            Opts::new(
+                "block_io_queue_depth_avg",
+                "Mean block IO requests in flight during the last interval",
+            )
+            .namespace(NAMESPACE),
+            &["device"],
+        )?;
+        let block_io_queue_depth_samples = Log2HistogramVec::new(
+            Opts::new(
+                "block_io_queue_depth_samples",
+                "Block IO requests in flight, sampled every 100ms",
+            )
+            .namespace(NAMESPACE),
+            &["device"],
+            1.0,
+        )?;
+        let block_io_utilization = GaugeVec::new(
+            Opts::new(
+                "block_io_utilization_ratio",
+                "Fraction of the last interval the device was busy with block IO",
+            )
+            .namespace(NAMESPACE),
+            &["device"],
+        )?;
+        let network_connections = IntCounterVec::new(
+            Opts::new("network_connect_total", "Outgoing connect() calls").namespace(NAMESPACE),
+            &["cgroup_id", "address_family"],
+        )?;
+        let network_connect_latency = HistogramVec::new(
+            HistogramOpts::new(
+                "network_connect_duration_seconds",
+                "Time until an outgoing connect() succeeded or failed",
+            )
+            .namespace(NAMESPACE)
+            .buckets(NETWORK_CONNECT_LATENCY_BUCKETS.to_vec()),
+            &["cgroup_id", "destination", "zone", "result"],
+        )?;
+        let tcp_retransmits = IntCounterVec::new(
+            Opts::new(
+                "network_tcp_retransmits_total",
+                "TCP segments retransmitted",
+            )
+            .namespace(NAMESPACE),
+            &["cgroup_id", "destination", "zone"],
+        )?;
+        let tcp_resets = IntCounterVec::new(
+            Opts::new("network_tcp_resets_total", "TCP connections reset").namespace(NAMESPACE),
+            &["cgroup_id", "destination", "zone", "direction"],
+        )?;
+        let tcp_sent_bytes = IntCounterVec::new(
+            Opts::new("network_tcp_sent_bytes_total", "Bytes sent over TCP").namespace(NAMESPACE),
+            &["cgroup_id", "destination", "zone"],
+        )?;
+        let tcp_received_bytes = IntCounterVec::new(
+            Opts::new(
+                "network_tcp_received_bytes_total",
+                "Bytes received over TCP",
+            )
+            .namespace(NAMESPACE),
+            &["cgroup_id", "destination", "zone"],
+        )?;
+        let gpu_opens = IntCounterVec::new(
+            Opts::new("gpu_open_total", "Opens of GPU device files").namespace(NAMESPACE),
+            &["cgroup_id", "gpu_index", "gpu_type"],
+        )?;
+        let custom_probe_hits = IntCounterVec::new(
+            Opts::new("custom_probe_events_total", "Hits on user-defined probes")
+                .namespace(NAMESPACE),
+            &["cgroup_id", "probe"],
+        )?;
+        let kernel_events = IntCounterVec::new(
+            Opts::new(
+                "kernel_events_total",
+                "Ringbuf outcomes counted by the eBPF programs",
+            )
+            .namespace(NAMESPACE),
+            &["probe", "outcome"],
+        )?;
+
+        let pipeline_dropped = IntCounterVec::new(
+            Opts::new(
+                "pipeline_dropped_events_total",
+                "Events dropped because a pipeline stage was full",
+            )
+            .namespace(NAMESPACE),
+            &["sink"],
+        )?;
+        let otlp_dropped = IntCounterVec::new(
+            Opts::new(
+                "otlp_dropped_records_total",
+                "OTLP log records that never reached the collector",
+            )
+            .namespace(NAMESPACE),
+            &["reason"],
+        )?;
+
+        registry.register(Box::new(block_io_events.clone()))?;
+        registry.register(Box::new(block_io_bytes.clone()))?;
+        registry.register(Box::new(block_io_request_size.clone()))?;
+        registry.register(Box::new(block_io_latency.clone()))?;
+        registry.register(Box::new(block_io_latency_log2.clone()))?;
+        registry.register(Box::new(block_io_size_log2.clone()))?;
+        registry.register(Box::new(block_io_errors.clone()))?;
+        registry.register(Box::new(block_io_queue_depth.clone()))?;
+        registry.register(Box::new(block_io_queue_depth_max.clone()))?;
+        registry.register(Box::new(block_io_queue_depth_avg.clone()))?;
+        registry.register(Box::new(block_io_queue_depth_samples.clone()))?;
+        registry.register(Box::new(block_io_utilization.clone()))?;
+        registry.register(Box::new(network_connections.clone()))?;
+        registry.register(Box::new(network_connect_latency.clone()))?;
+        registry.register(Box::new(tcp_retransmits.clone()))?;
+        registry.register(Box::new(tcp_resets.clone()))?;
+        registry.register(Box::new(tcp_sent_bytes.clone()))?;
+        registry.register(Box::new(tcp_received_bytes.clone()))?;
+        registry.register(Box::new(gpu_opens.clone()))?;
+        registry.register(Box::new(custom_probe_hits.clone()))?;
+        registry.register(Box::new(kernel_events.clone()))?;
+        registry.register(Box::new(pipeline_dropped.clone()))?;
+        registry.register(Box::new(otlp_dropped.clone()))?;
+
+        Ok(Self {
+            registry,
+            block_io_events,
+            block_io_bytes,
+            block_io_request_size,
+            block_io_latency,
+            block_io_latency_log2,
+            block_io_size_log2,
+            block_io_errors,
+            block_io_queue_depth,
+            block_io_queue_depth_max,
+            block_io_queue_depth_avg,
+            block_io_queue_depth_samples,
+            block_io_utilization,
+            network_connections,
+            network_connect_latency,
+            tcp_retransmits,
+            tcp_resets,
+            tcp_sent_bytes,
+            tcp_received_bytes,
+            gpu_opens,
+            custom_probe_hits,
+            kernel_events,
+            pipeline_dropped,
+            otlp_dropped,
+        })
+    }
+
+    pub fn record_block_io(&self, event: &BlockIo) {
+        let cgroup_id = event.metadata.cgroup_id.to_string();
+        let device = event.device();
+
+        self.block_io_events
+            .with_label_values(&[&cgroup_id, &device, event.event_type.as_str()])
+            .inc();
+
+        // Only count completed requests so that a single IO is not accounted twice
+        if event.event_type == BlockIoEventType::Done {
+            self.block_io_bytes
+                .with_label_values(&[&cgroup_id, &device, event.op.as_str()])
+                .inc_by(event.bytes as u64);
+            self.block_io_request_size
+                .with_label_values(&[&device])
+                .observe(event.bytes as f64);
+            if let Some(latency_ns) = event.latency_ns {
+                self.block_io_latency
+                    .with_label_values(&[&cgroup_id, &device, event.op.as_str()])
+                    .observe(latency_ns as f64 / 1e9);
+            }
+        }
+        if let Some(error) = event.error_name() {
+            self.block_io_errors
+                .with_label_values(&[&cgroup_id, &device, event.op.as_str(), &error])
+                .inc();
+        }
+    }
+
+    /// Adds one interval's worth of a kernel histogram slot.
+    pub fn record_block_io_bucket(
+        &self,
+        key: &BlockIoHistogramKey,
+        bucket: &Log2Bucket,
+    ) -> Result<()> {
+        let histogram = if key.measure == BlockIoMeasure::Latency as u8 {
+            &self.block_io_latency_log2
+        } else {
+            &self.block_io_size_log2
+        };
+        let cgroup_id = key.cgroup_id.to_string();
+        let device = format!("{}:{}", dev_major(key.dev), dev_minor(key.dev));
+        histogram.add(
+            &[&cgroup_id, &device, BlockIoOp::from(key.op).as_str()],
+            key.slot as usize,
+            bucket.count,
+            bucket.sum,
+        )
+    }
+
+    pub fn record_block_io_queue(&self, device: &str, stats: &BlockQueueStats) -> Result<()> {
+        self.block_io_queue_depth
+            .with_label_values(&[device])
+            .set(stats.depth as i64);
+        self.block_io_queue_depth_max
+            .with_label_values(&[device])
+            .set(stats.max_depth as i64);
+        self.block_io_queue_depth_avg
+            .with_label_values(&[device])
+            .set(stats.avg_depth);
+        self.block_io_utilization
+            .with_label_values(&[device])
+            .set(stats.utilization);
+        for &depth in &stats.depth_samples {
+            let slot = log2_slot(depth) as usize;
+            self.block_io_queue_depth_samples
+                .add(&[device], slot, 1, depth)?;
+        }
+        Ok(())
+    }
+
+    /// Drops the queue gauges of a device that is no longer tracked.
+    pub fn remove_block_io_queue(&self, device: &str) {
+        for gauge in [&self.block_io_queue_depth, &self.block_io_queue_depth_max] {
+            let _ = gauge.remove_label_values(&[device]);
+        }
+        for gauge in [&self.block_io_queue_depth_avg, &self.block_io_utilization] {
+            let _ = gauge.remove_label_values(&[device]);
+        }
+        self.block_io_queue_depth_samples.remove(&[device]);
+    }
+
+    pub fn record_connection(&self, event: &Connection) {
+        let address_family = match event.address_family as i32 {
+            libc::AF_INET => "inet",
+            libc::AF_INET6 => "inet6",
+            libc::AF_UNIX => "unix",
+            _ => "other",
+        };
+
+        let cgroup_id = event.metadata.cgroup_id.to_string();
+        self.network_connections
+            .with_label_values(&[&cgroup_id, address_family])
+            .inc();
+        // Local sockets connect without a handshake worth timing. Only the address
+        // labels the histogram, as a series per port would multiply its buckets.
+        if let Some(destination) = event.destination {
+            let destination = destination.ip().to_string();
+            let zone = event.zone.as_deref().unwrap_or_default();
+            self.network_connect_latency
+                .with_label_values(&[&cgroup_id, &destination, zone, &event.result()])
+                .observe(event.latency_ns as f64 / 1e9);
+        }
+    }
+
+    /// Counts retransmits and resets per workload and remote address; the port
+    /// is left out, as it is ephemeral for accepted connections. Sockets whose
+    /// owner was not seen count under cgroup 0.
+    pub fn record_tcp(&self, event: &Tcp) {
+        let cgroup_id = event.metadata.cgroup_id.to_string();
+        let destination = event.destination.ip().to_string();
+        let zone = event.zone.as_deref().unwrap_or_default();
+        let direction = match event.event_type {
+            TcpEventType::Retransmit => {
+                self.tcp_retransmits
+                    .with_label_values(&[&cgroup_id, &destination, zone])
+                    .inc_by(event.count.into());
+                return;
+            }
+            TcpEventType::ResetSent => "sent",
+            TcpEventType::ResetReceived => "received",
+            TcpEventType::Unknown => return,
+        };
+        self.tcp_resets
+            .with_label_values(&[&cgroup_id, &destination, zone, direction])
+            .inc();
+    }
+
+    /// Adds a reporting interval of traffic between a workload and a peer. Peers
+    /// outside every configured network zone have an empty zone.
+    pub fn record_tcp_traffic(&self, event: &TcpTraffic) {
+        let labels = [
+            event.metadata.cgroup_id.to_string(),
+            event.destination.to_string(),
+            event.zone.as_deref().unwrap_or_default().to_string(),
+        ];
+        self.tcp_sent_bytes
+            .with_label_values(&labels)
+            .inc_by(event.sent_bytes);
+        self.tcp_received_bytes
+            .with_label_values(&labels)
+            .inc_by(event.received_bytes);
+    }
+
+    pub fn record_gpu_open(&self, event: &GpuOpen) {
+        self.gpu_opens
+            .with_label_values(&[
+                &event.metadata.cgroup_id.to_string(),
+                &event.gpu_index.to_string(),
+                event.gpu_type,
+            ])
+            .inc();
+    }
+
+    pub fn record_custom(&self, event: &Custom) {
+        self.custom_probe_hits
+            .with_label_values(&[&event.metadata.cgroup_id.to_string(), &event.probe])
+            .inc();
+    }
+
+    /// Counter for one ringbuf outcome (e.g. `reserve_failed`) of a probe's eBPF programs.
+    pub fn kernel_events(&self, probe: &str, outcome: &str) -> IntCounter {
+        self.kernel_events.with_label_values(&[probe, outcome])
+    }
+
+    /// Drop counter for a pipeline stage (a sink name, or the shared ingress queue).
+    pub fn pipeline_dropped(&self, sink: &str) -> IntCounter {
+        self.pipeline_dropped.with_label_values(&[sink])
+    }
+
+    /// Drop counter for OTLP log records, by reason (`queue_full` or `export_failed`).
+    pub fn otlp_dropped(&self, reason: &str) -> IntCounter {
+        self.otlp_dropped.with_label_values(&[reason])
+    }
+
+    pub fn gather(&self) -> Vec<MetricFamily> {
+        self.registry.gather()
+    }
+
+    /// Renders every registered metric in the Prometheus text exposition format.
+    pub fn encode(&self) -> Result<String> {
+        let mut buffer = Vec::new();
+        TextEncoder::new().encode(&self.gather(), &mut buffer)?;
+        Ok(String::from_utf8(buffer)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use honeybeepf_common::{BlockIoEvent, EventMetadata, MINORBITS, RwbsFlag};
+
+    use super::*;
+    use crate::events::Metadata;
+
+    fn block_io_event(
+        event_type: BlockIoEventType,
+        bytes: u32,
+        latency_ns: Option<u64>,
+    ) -> BlockIo {
+        BlockIo {
+            metadata: Metadata {
+                pid: 1,
+                cgroup_id: 42,
+                timestamp: 0,
+            },
+            event_type,
+            major: 259,
+            minor: 1,
+            block_device: None,
+            sector: 0,
+            nr_sector: bytes / 512,
+            bytes,
+            rwbs: "WS".to_string(),
+            op: BlockIoOp::Write,
+            flags: [RwbsFlag::Sync].into_iter().collect(),
+            comm: String::new(),
+            latency_ns,
+            error: None,
+        }
+    }
+
+    #[test]
+    fn test_record_block_io() {
+        let metrics = ProbeMetrics::new().unwrap();
+
+        metrics.record_block_io(&block_io_event(BlockIoEventType::Start, 4096, None));
+        metrics.record_block_io(&block_io_event(BlockIoEventType::Done, 4096, Some(300_000)));
+
+        let output = metrics.encode().unwrap();
+        assert!(output.contains(
+            r#"honeybeepf_block_io_events_total{cgroup_id="42",device="259:1",event_type="start"} 1"#
+        ));
+        assert!(output.contains(
+            r#"honeybeepf_block_io_bytes_total{cgroup_id="42",device="259:1",op="write"} 4096"#
+        ));
+        assert!(output.contains(r#"honeybeepf_block_io_request_bytes_count{device="259:1"} 1"#));
+        assert!(output.contains(
+            r#"honeybeepf_block_io_latency_seconds_bucket{cgroup_id="42",device="259:1",op="write",le="0.0005"} 1"#
+        ));
+        assert!(output.contains(
+            r#"honeybeepf_block_io_latency_seconds_bucket{cgroup_id="42",device="259:1",op="write",le="0.00025"} 0"#
+        ));
+    }
+
+    #[test]
+    fn test_record_kernel_block_io_latency() {
+        let metrics = ProbeMetrics::new().unwrap();
+        let mut raw = BlockIoEvent {
+            metadata: EventMetadata {
+                pid: 1,
+                cgroup_id: 42,
+                ..Default::default()
+            },
+            dev: (259 << MINORBITS) | 1,
+            sector: 0,
+            latency_ns: 3_000_000,
+            nr_sector: 8,
+            bytes: 4096,
+            rwbs: *b"R\0\0\0\0\0\0\0",
+            comm: [0; 16],
+            error: 0,
+            event_type: BlockIoEventType::Done as u8,
+        };
+        metrics.record_block_io(&BlockIo::from(&raw));
+        // An unmatched completion carries no latency
+        raw.latency_ns = 0;
+        metrics.record_block_io(&BlockIo::from(&raw));
+
+        let output = metrics.encode().unwrap();
+        assert!(output.contains(
+            r#"honeybeepf_block_io_latency_seconds_bucket{cgroup_id="42",device="259:1",op="read",le="0.0025"} 0"#
+        ));
+        assert!(output.contains(
+            r#"honeybeepf_block_io_latency_seconds_bucket{cgroup_id="42",device="259:1",op="read",le="0.005"} 1"#
+        ));
+        assert!(output.contains(
+            r#"honeybeepf_block_io_latency_seconds_sum{cgroup_id="42",device="259:1",op="read"} 0.003"#
+        ));
+        assert!(output.contains(
+            r#"honeybeepf_block_io_latency_seconds_count{cgroup_id="42",device="259:1",op="read"} 1"#
+        ));
+        assert!(output.contains(
+            r#"honeybeepf_block_io_bytes_total{cgroup_id="42",device="259:1",op="read"} 8192"#
+        ));
+    }
+
+    #[test]
+    fn test_record_block_io_error() {
+        let metrics = ProbeMetrics::new().unwrap();
+
+        let mut event = block_io_event(BlockIoEventType::Error, 4096, None);
+        event.error = Some(-libc::ETIMEDOUT);
+        metrics.record_block_io(&event);
+        event.error = Some(-1000);
+        metrics.record_block_io(&event);
+
+        let output = metrics.encode().unwrap();
+        assert!(output.contains(
+            r#"honeybeepf_block_io_errors_total{cgroup_id="42",device="259:1",error="ETIMEDOUT",op="write"} 1"#
+        ));
+        assert!(output.contains(
+            r#"honeybeepf_block_io_errors_total{cgroup_id="42",device="259:1",error="-1000",op="write"} 1"#
+        ));
+        // The done event that follows accounts the bytes
+        assert!(!output.contains("honeybeepf_block_io_bytes_total"));
+    }
+
+    #[test]
+    fn test_record_connection_latency() {
+        let metrics = ProbeMetrics::new().unwrap();
+        let mut event = Connection {
+            metadata: Metadata {
+                pid: 1,
+                cgroup_id: 42,
+                timestamp: 0,
+            },
+            address_family: libc::AF_INET as u16,
+            destination: Some("10.0.0.1:443".parse().unwrap()),
+            zone: Some("service".into()),
+            latency_ns: 2_000_000,
+            error: None,
+        };
+        metrics.record_connection(&event);
+        event.latency_ns = 3_000_000_000;
+        event.error = Some(-libc::ETIMEDOUT);
+        metrics.record_connection(&event);
+        event.destination = None;
+        event.address_family = libc::AF_UNIX as u16;
+        metrics.record_connection(&event);
+
+        let output = metrics.encode().unwrap();
+        assert!(output.contains(
+            r#"honeybeepf_network_connect_duration_seconds_bucket{cgroup_id="42",destination="10.0.0.1",result="ok",zone="service",le="0.0025"} 1"#
+        ));
+        assert!(output.contains(
+            r#"honeybeepf_network_connect_duration_seconds_bucket{cgroup_id="42",destination="10.0.0.1",result="ETIMEDOUT",zone="service",le="1"} 0"#
+        ));
+        assert!(output.contains(
+            r#"honeybeepf_network_connect_duration_seconds_count{cgroup_id="42",destination="10.0.0.1",result="ETIMEDOUT",zone="service"} 1"#
+        ));
+        assert!(output.contains(
+            r#"honeybeepf_network_connect_total{address_family="unix",cgroup_id="42"} 1"#
+        ));
+    }
+
+    #[test]
+    fn test_record_tcp() {
+        let metrics = ProbeMetrics::new().unwrap();
+        let mut event = Tcp {
+            metadata: Metadata {
+                pid: 1,
+                cgroup_id: 42,
+                timestamp: 0,
+            },
+            event_type: TcpEventType::Retransmit,
+            state: Some("ESTABLISHED"),
+            count: 1,
+            source: "10.0.0.2:40000".parse().unwrap(),
+            destination: "10.0.0.9:29500".parse().unwrap(),
+            zone: Some("pod".into()),
+        };
+        metrics.record_tcp(&event);
+        // Another connection to the same peer adds to the same series, with the
+        // retransmits throttled since its previous event
+        event.destination = "10.0.0.9:29501".parse().unwrap();
+        event.count = 3;
+        metrics.record_tcp(&event);
+        event.count = 1;
+        event.event_type = TcpEventType::ResetReceived;
+        metrics.record_tcp(&event);
+        // Peers outside every zone
+        event.zone = None;
+        metrics.record_tcp(&event);
+
+        let output = metrics.encode().unwrap();
+        assert!(output.contains(
+            r#"honeybeepf_network_tcp_retransmits_total{cgroup_id="42",destination="10.0.0.9",zone="pod"} 4"#
+        ));
+        assert!(output.contains(
+            r#"honeybeepf_network_tcp_resets_total{cgroup_id="42",destination="10.0.0.9",direction="received",zone="pod"} 1"#
+        ));
+        assert!(output.contains(
+            r#"honeybeepf_network_tcp_resets_total{cgroup_id="42",destination="10.0.0.9",direction="received",zone=""} 1"#
+        ));
+    }
+
+    #[test]
+    fn test_record_tcp_traffic() {
+        let metrics = ProbeMetrics::new().unwrap();
+        let mut event = TcpTraffic {
+            metadata: Metadata {
+                pid: 0,
+                cgroup_id: 42,
+                timestamp: 0,
+            },
+            destination: "10.0.0.9".parse().unwrap(),
+            zone: Some("on_prem".into()),
+            sent_bytes: 1500,
+            received_bytes: 200,
+            connections_closed: 1,
+        };
+        metrics.record_tcp_traffic(&event);
+        metrics.record_tcp_traffic(&event);
+        event.zone = None;
+        metrics.record_tcp_traffic(&event);
+
+        let output = metrics.encode().unwrap();
+        assert!(output.contains(
+            r#"honeybeepf_network_tcp_sent_bytes_total{cgroup_id="42",destination="10.0.0.9",zone="on_prem"} 3000"#
+        ));
+        assert!(output.contains(
+            r#"honeybeepf_network_tcp_received_bytes_total{cgroup_id="42",destination="10.0.0.9",zone="on_prem"} 400"#
+        ));
+        assert!(output.contains(
+            r#"honeybeepf_network_tcp_sent_bytes_total{cgroup_id="42",destination="10.0.0.9",zone=""} 1500"#
+        ));
+    }
+
+    #[test]
+    fn test_record_block_io_queue() {
+        let metrics = ProbeMetrics::new().unwrap();
+        let stats = BlockQueueStats {
+            depth: 0,
+            max_depth: 4,
+            avg_depth: 1.22,
+            utilization: 0.42,
+            depth_samples: vec![0, 4, 4, 1, 0],
+        };
+        metrics.record_block_io_queue("259:1", &stats).unwrap();
+
+        let output = metrics.encode().unwrap();
+        assert!(output.contains(
+            r#"honeybeepf_block_io_queue_depth_samples_bucket{device="259:1",le="1"} 3"#
+        ));
+        assert!(output.contains(
+            r#"honeybeepf_block_io_queue_depth_samples_bucket{device="259:1",le="3"} 3"#
+        ));
+        assert!(output.contains(
+            r#"honeybeepf_block_io_queue_depth_samples_bucket{device="259:1",le="7"} 5"#
+        ));
+        assert!(
+            output.contains(r#"honeybeepf_block_io_queue_depth_samples_sum{device="259:1"} 9"#)
+        );
+        assert!(output.contains(r#"honeybeepf_block_io_queue_depth_max{device="259:1"} 4"#));
+
+        metrics.remove_block_io_queue("259:1");
+        assert!(!metrics.encode().unwrap().contains("device=\"259:1\""));
+    }
+
+    #[test]
+    fn test_record_block_io_bucket() {
+        let metrics = ProbeMetrics::new().unwrap();
+        let key = |measure: BlockIoMeasure, slot: u8| BlockIoHistogramKey {
+            cgroup_id: 42,
+            dev: (259 << honeybeepf_common::MINORBITS) | 1,
+            op: BlockIoOp::Read as u8,
+            measure: measure as u8,
+            slot,
+            _pad: 0,
+        };
+
+        // ~300us is slot 18, 4KiB requests slot 12
+        let latency = Log2Bucket {
+            count: 2,
+            sum: 600_000,
+        };
+        let size = Log2Bucket {
+            count: 2,
+            sum: 8192,
+        };
+        metrics
+            .record_block_io_bucket(&key(BlockIoMeasure::Latency, 18), &latency)
+            .unwrap();
+        metrics
+            .record_block_io_bucket(&key(BlockIoMeasure::Size, 12), &size)
+            .unwrap();
+
+        let output = metrics.encode().unwrap();
+        assert!(output.contains(
+            r#"honeybeepf_block_io_latency_log2_seconds_bucket{cgroup_id="42",device="259:1",op="read",le="0.000524287"} 2"#
+        ));
+        assert!(output.contains(
+            r#"honeybeepf_block_io_latency_log2_seconds_count{cgroup_id="42",device="259:1",op="read"} 2"#
+        ));
+        assert!(output.contains(
+            r#"honeybeepf_block_io_request_log2_bytes_bucket{cgroup_id="42",device="259:1",op="read",le="4095"} 0"#
+        ));
+        assert!(output.contains(
+            r#"honeybeepf_block_io_request_log2_bytes_bucket{cgroup_id="42",device="259:1",op="read",le="8191"} 2"#
+        ));
+        assert!(output.contains(
+            r#"honeybeepf_block_io_request_log2_bytes_sum{cgroup_id="42",device="259:1",op="read"} 8192"#
+        ));
+    }
+}
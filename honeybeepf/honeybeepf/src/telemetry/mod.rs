@@ -0,0 +1,29 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub mod log2;
+pub mod metrics;
+pub mod otlp;
+pub mod server;
+
+pub fn unix_now_nanos() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos() as u64)
+        .unwrap_or(0)
+}
+
+/// Converts a `bpf_ktime_get_ns` timestamp (CLOCK_MONOTONIC) into nanoseconds since the epoch.
+pub fn ktime_to_unix_nanos(ktime_ns: u64) -> u64 {
+    let mut ts = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    let ret = unsafe { libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut ts) };
+    if ret != 0 {
+        return unix_now_nanos();
+    }
+    let monotonic_now = ts.tv_sec as u64 * 1_000_000_000 + ts.tv_nsec as u64;
+    unix_now_nanos()
+        .saturating_sub(monotonic_now)
+        .saturating_add(ktime_ns)
+}
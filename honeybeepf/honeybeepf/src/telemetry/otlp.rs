@@ -0,0 +1,851 @@
+use std::{fmt, str::FromStr, sync::Arc, time::Duration};
+
+use anyhow::{Context, Result, anyhow, bail};
+use bytes::Bytes;
+use http_body_util::Full;
+use hyper::{Method, Request, StatusCode, Uri, header};
+use hyper_util::{
+    client::legacy::{Client, connect::HttpConnector},
+    rt::TokioExecutor,
+};
+use log::{debug, info, warn};
+use opentelemetry_proto::tonic::{
+    collector::{
+        logs::v1::{ExportLogsServiceRequest, logs_service_client::LogsServiceClient},
+        metrics::v1::{ExportMetricsServiceRequest, metrics_service_client::MetricsServiceClient},
+    },
+    common::v1::{AnyValue, InstrumentationScope, KeyValue, any_value},
+    logs::v1::{LogRecord, ResourceLogs, ScopeLogs, SeverityNumber},
+    metrics::v1::{
+        self as otlp_metrics, AggregationTemporality, HistogramDataPoint, NumberDataPoint,
+        ResourceMetrics, ScopeMetrics, metric, number_data_point,
+    },
+    resource::v1::Resource,
+};
+use prometheus::{
+    IntCounter,
+    proto::{MetricFamily, MetricType},
+};
+use prost::Message;
+use tokio::{sync::mpsc, task::JoinHandle, time::Instant};
+use tonic::{Code, transport::Channel};
+
+use crate::telemetry::{metrics::ProbeMetrics, unix_now_nanos};
+
+const SERVICE_NAME: &str = "honeybeepf";
+const LOGS_PATH: &str = "/v1/logs";
+const METRICS_PATH: &str = "/v1/metrics";
+const PROTOBUF_CONTENT_TYPE: &str = "application/x-protobuf";
+
+/// Reasons log records are counted as dropped.
+const QUEUE_FULL: &str = "queue_full";
+const EXPORT_FAILED: &str = "export_failed";
+
+const DEFAULT_QUEUE_SIZE: usize = 4096;
+const DEFAULT_BATCH_SIZE: usize = 512;
+const DEFAULT_FLUSH_INTERVAL: Duration = Duration::from_secs(5);
+const DEFAULT_METRICS_INTERVAL: Duration = Duration::from_secs(15);
+const DEFAULT_MAX_RETRIES: u32 = 5;
+const DEFAULT_INITIAL_BACKOFF: Duration = Duration::from_millis(250);
+const DEFAULT_RETRY_BUDGET: Duration = Duration::from_secs(10);
+const MAX_BACKOFF: Duration = Duration::from_secs(8);
+const EXPORT_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OtlpProtocol {
+    Grpc,
+    HttpProtobuf,
+}
+
+impl FromStr for OtlpProtocol {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        // Values follow OTEL_EXPORTER_OTLP_PROTOCOL from the OpenTelemetry specification
+        match s.trim().to_ascii_lowercase().as_str() {
+            "grpc" => Ok(Self::Grpc),
+            "http/protobuf" | "http" => Ok(Self::HttpProtobuf),
+            other => bail!(
+                "Unsupported OTLP protocol '{}' (expected 'grpc' or 'http/protobuf')",
+                other
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct OtlpConfig {
+    pub endpoint: String,
+    pub protocol: OtlpProtocol,
+    pub queue_size: usize,
+    pub batch_size: usize,
+    pub flush_interval: Duration,
+    pub metrics_interval: Duration,
+    pub max_retries: u32,
+    pub initial_backoff: Duration,
+    /// Longest time spent exporting one batch, retries included. Logs queue up
+    /// while the worker waits, so an unreachable collector must not hold it long.
+    pub retry_budget: Duration,
+}
+
+impl OtlpConfig {
+    pub fn new(endpoint: &str, protocol: OtlpProtocol) -> Self {
+        Self {
+            endpoint: normalize_endpoint(endpoint),
+            protocol,
+            queue_size: DEFAULT_QUEUE_SIZE,
+            batch_size: DEFAULT_BATCH_SIZE,
+            flush_interval: DEFAULT_FLUSH_INTERVAL,
+            metrics_interval: DEFAULT_METRICS_INTERVAL,
+            max_retries: DEFAULT_MAX_RETRIES,
+            initial_backoff: DEFAULT_INITIAL_BACKOFF,
+            retry_budget: DEFAULT_RETRY_BUDGET,
+        }
+    }
+}
+
+/// The chart configures endpoints as `host:port`, which neither tonic nor hyper accept.
+fn normalize_endpoint(endpoint: &str) -> String {
+    let endpoint = endpoint.trim().trim_end_matches('/');
+    if endpoint.contains("://") {
+        endpoint.to_string()
+    } else {
+        format!("http://{}", endpoint)
+    }
+}
+
+/// Checks that an endpoint is something the exporter can connect to.
+pub fn validate_endpoint(endpoint: &str) -> Result<()> {
+    let normalized = normalize_endpoint(endpoint);
+    let uri: Uri = normalized
+        .parse()
+        .with_context(|| format!("'{}' is not a valid URL", endpoint))?;
+    match uri.scheme_str() {
+        Some("http") => {}
+        Some("https") => bail!("TLS is not supported; use an http:// endpoint"),
+        Some(other) => bail!("unsupported scheme '{}' (expected http)", other),
+        None => bail!("'{}' has no scheme", endpoint),
+    }
+    if uri.host().is_none_or(str::is_empty) {
+        bail!("'{}' has no host", endpoint);
+    }
+    Ok(())
+}
+
+/// Handle used by probes to queue event logs; batching and delivery happen on a background task.
+pub struct OtlpExporter {
+    logs_tx: mpsc::Sender<LogRecord>,
+    dropped: IntCounter,
+    worker: JoinHandle<()>,
+}
+
+impl OtlpExporter {
+    /// Starts the export task. Must be called from within a tokio runtime.
+    pub fn spawn(config: OtlpConfig, metrics: Arc<ProbeMetrics>) -> Result<Self> {
+        let transport = Transport::new(&config)?;
+        let (logs_tx, logs_rx) = mpsc::channel(config.queue_size);
+        let dropped = metrics.otlp_dropped(QUEUE_FULL);
+        let export_failed = metrics.otlp_dropped(EXPORT_FAILED);
+
+        info!(
+            "Exporting OTLP logs and metrics to {} ({:?})",
+            config.endpoint, config.protocol
+        );
+
+        let worker = Worker {
+            resource: resource(),
+            start_time_unix_nano: unix_now_nanos(),
+            config,
+            transport,
+            metrics,
+            export_failed,
+        };
+        let worker = tokio::spawn(worker.run(logs_rx));
+
+        Ok(Self {
+            logs_tx,
+            dropped,
+            worker,
+        })
+    }
+
+    /// Queues a log record without blocking; records are dropped when the queue is full.
+    pub fn emit(&self, record: LogRecord) {
+        if self.logs_tx.try_send(record).is_err() {
+            self.dropped.inc();
+            let dropped = self.dropped.get();
+            if dropped.is_power_of_two() {
+                warn!("OTLP log queue full; {} records dropped so far", dropped);
+            }
+        }
+    }
+
+    /// Records dropped because the queue was full.
+    pub fn dropped(&self) -> u64 {
+        self.dropped.get()
+    }
+
+    /// Exports the queued records and a final round of metrics, then stops the worker.
+    pub async fn shutdown(self) {
+        drop(self.logs_tx);
+        if let Err(e) = self.worker.await {
+            warn!("OTLP export task failed: {}", e);
+        }
+    }
+}
+
+/// Builds a log record for a single probe event.
+pub fn event_record(
+    event_name: &str,
+    time_unix_nano: u64,
+    body: String,
+    attributes: Vec<KeyValue>,
+) -> LogRecord {
+    LogRecord {
+        time_unix_nano,
+        observed_time_unix_nano: unix_now_nanos(),
+        severity_number: SeverityNumber::Info as i32,
+        severity_text: "INFO".to_string(),
+        event_name: event_name.to_string(),
+        body: Some(string_value(body)),
+        attributes,
+        ..Default::default()
+    }
+}
+
+pub fn string_attr(key: &str, value: impl Into<String>) -> KeyValue {
+    KeyValue {
+        key: key.to_string(),
+        value: Some(string_value(value.into())),
+        ..Default::default()
+    }
+}
+
+pub fn int_attr(key: &str, value: i64) -> KeyValue {
+    KeyValue {
+        key: key.to_string(),
+        value: Some(AnyValue {
+            value: Some(any_value::Value::IntValue(value)),
+        }),
+        ..Default::default()
+    }
+}
+
+pub fn bool_attr(key: &str, value: bool) -> KeyValue {
+    KeyValue {
+        key: key.to_string(),
+        value: Some(AnyValue {
+            value: Some(any_value::Value::BoolValue(value)),
+        }),
+        ..Default::default()
+    }
+}
+
+fn string_value(value: String) -> AnyValue {
+    AnyValue {
+        value: Some(any_value::Value::StringValue(value)),
+    }
+}
+
+fn resource() -> Resource {
+    let mut attributes = vec![string_attr("service.name", SERVICE_NAME)];
+    if let Ok(hostname) = std::fs::read_to_string("/proc/sys/kernel/hostname") {
+        attributes.push(string_attr("host.name", hostname.trim()));
+    }
+    Resource {
+        attributes,
+        ..Default::default()
+    }
+}
+
+fn scope() -> InstrumentationScope {
+    InstrumentationScope {
+        name: SERVICE_NAME.to_string(),
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        ..Default::default()
+    }
+}
+
+struct Worker {
+    config: OtlpConfig,
+    transport: Transport,
+    metrics: Arc<ProbeMetrics>,
+    /// Records given up on after the collector kept failing.
+    export_failed: IntCounter,
+    resource: Resource,
+    start_time_unix_nano: u64,
+}
+
+impl Worker {
+    async fn run(mut self, mut logs_rx: mpsc::Receiver<LogRecord>) {
+        let mut batch = Vec::with_capacity(self.config.batch_size);
+        let mut flush_tick = tokio::time::interval(self.config.flush_interval);
+        let mut metrics_tick = tokio::time::interval(self.config.metrics_interval);
+
+        loop {
+            tokio::select! {
+                received = logs_rx.recv() => match received {
+                    Some(record) => {
+                        batch.push(record);
+                        if batch.len() >= self.config.batch_size {
+                            self.export_logs(std::mem::take(&mut batch)).await;
+                        }
+                    }
+                    None => break,
+                },
+                _ = flush_tick.tick() => {
+                    if !batch.is_empty() {
+                        self.export_logs(std::mem::take(&mut batch)).await;
+                    }
+                }
+                _ = metrics_tick.tick() => self.export_metrics().await,
+            }
+        }
+
+        if !batch.is_empty() {
+            self.export_logs(batch).await;
+        }
+        self.export_metrics().await;
+    }
+
+    async fn export_logs(&mut self, log_records: Vec<LogRecord>) {
+        let count = log_records.len();
+        let request = ExportLogsServiceRequest {
+            resource_logs: vec![ResourceLogs {
+                resource: Some(self.resource.clone()),
+                scope_logs: vec![ScopeLogs {
+                    scope: Some(scope()),
+                    log_records,
+                    ..Default::default()
+                }],
+                ..Default::default()
+            }],
+        };
+
+        match self.send_with_retry(Payload::Logs(request)).await {
+            Ok(()) => debug!("Exported {} OTLP log records", count),
+            Err(e) => {
+                self.export_failed.inc_by(count as u64);
+                warn!("Dropping {} OTLP log records: {:#}", count, e);
+            }
+        }
+    }
+
+    async fn export_metrics(&mut self) {
+        let metrics = convert_families(
+            &self.metrics.gather(),
+            self.start_time_unix_nano,
+            unix_now_nanos(),
+        );
+        if metrics.is_empty() {
+            return;
+        }
+
+        let request = ExportMetricsServiceRequest {
+            resource_metrics: vec![ResourceMetrics {
+                resource: Some(self.resource.clone()),
+                scope_metrics: vec![ScopeMetrics {
+                    scope: Some(scope()),
+                    metrics,
+                    ..Default::default()
+                }],
+                ..Default::default()
+            }],
+        };
+
+        if let Err(e) = self.send_with_retry(Payload::Metrics(request)).await {
+            warn!("Failed to export OTLP metrics: {:#}", e);
+        }
+    }
+
+    /// Retries with exponential backoff until `max_retries` or the retry budget
+    /// runs out, whichever comes first.
+    async fn send_with_retry(&mut self, payload: Payload) -> Result<()> {
+        let deadline = Instant::now() + self.config.retry_budget;
+        let mut backoff = self.config.initial_backoff;
+        let mut attempt = 0;
+        loop {
+            let sent = tokio::time::timeout_at(deadline, self.transport.send(&payload))
+                .await
+                .unwrap_or_else(|_| Err(ExportError::new(false, "retry budget exhausted")));
+            match sent {
+                Ok(()) => return Ok(()),
+                Err(e)
+                    if e.retryable
+                        && attempt < self.config.max_retries
+                        && Instant::now() + backoff < deadline =>
+                {
+                    attempt += 1;
+                    debug!(
+                        "OTLP export failed (attempt {}/{}), retrying in {:?}: {}",
+                        attempt, self.config.max_retries, backoff, e
+                    );
+                    tokio::time::sleep(backoff).await;
+                    backoff = (backoff * 2).min(MAX_BACKOFF);
+                }
+                Err(e) => return Err(anyhow!("{} after {} retries", e, attempt)),
+            }
+        }
+    }
+}
+
+enum Payload {
+    Logs(ExportLogsServiceRequest),
+    Metrics(ExportMetricsServiceRequest),
+}
+
+#[derive(Debug)]
+struct ExportError {
+    retryable: bool,
+    message: String,
+}
+
+impl ExportError {
+    fn new(retryable: bool, message: impl Into<String>) -> Self {
+        Self {
+            retryable,
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for ExportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+enum Transport {
+    Grpc {
+        logs: LogsServiceClient<Channel>,
+        metrics: MetricsServiceClient<Channel>,
+    },
+    Http {
+        client: Client<HttpConnector, Full<Bytes>>,
+        logs_uri: Uri,
+        metrics_uri: Uri,
+    },
+}
+
+impl Transport {
+    fn new(config: &OtlpConfig) -> Result<Self> {
+        match config.protocol {
+            OtlpProtocol::Grpc => {
+                let channel = Channel::from_shared(config.endpoint.clone())
+                    .with_context(|| format!("Invalid OTLP endpoint {}", config.endpoint))?
+                    .timeout(EXPORT_TIMEOUT)
+                    .connect_lazy();
+                Ok(Self::Grpc {
+                    logs: LogsServiceClient::new(channel.clone()),
+                    metrics: MetricsServiceClient::new(channel),
+                })
+            }
+            OtlpProtocol::HttpProtobuf => {
+                if config.endpoint.starts_with("https://") {
+                    bail!(
+                        "TLS is not supported for OTLP over HTTP: {}",
+                        config.endpoint
+                    );
+                }
+                let parse = |path: &str| -> Result<Uri> {
+                    format!("{}{}", config.endpoint, path)
+                        .parse()
+                        .with_context(|| format!("Invalid OTLP endpoint {}", config.endpoint))
+                };
+                Ok(Self::Http {
+                    client: Client::builder(TokioExecutor::new()).build_http(),
+                    logs_uri: parse(LOGS_PATH)?,
+                    metrics_uri: parse(METRICS_PATH)?,
+                })
+            }
+        }
+    }
+
+    async fn send(&mut self, payload: &Payload) -> Result<(), ExportError> {
+        match self {
+            Self::Grpc { logs, metrics } => {
+                let result = match payload {
+                    Payload::Logs(request) => logs.export(request.clone()).await.map(|_| ()),
+                    Payload::Metrics(request) => metrics.export(request.clone()).await.map(|_| ()),
+                };
+                result.map_err(|status| {
+                    ExportError::new(is_retryable_code(status.code()), status.to_string())
+                })
+            }
+            Self::Http {
+                client,
+                logs_uri,
+                metrics_uri,
+            } => {
+                let (uri, body) = match payload {
+                    Payload::Logs(request) => (logs_uri.clone(), request.encode_to_vec()),
+                    Payload::Metrics(request) => (metrics_uri.clone(), request.encode_to_vec()),
+                };
+                let request = Request::builder()
+                    .method(Method::POST)
+                    .uri(uri)
+                    .header(header::CONTENT_TYPE, PROTOBUF_CONTENT_TYPE)
+                    .body(Full::new(Bytes::from(body)))
+                    .map_err(|e| ExportError::new(false, e.to_string()))?;
+
+                let response = tokio::time::timeout(EXPORT_TIMEOUT, client.request(request))
+                    .await
+                    .map_err(|_| ExportError::new(true, "request timed out"))?
+                    .map_err(|e| ExportError::new(true, e.to_string()))?;
+
+                let status = response.status();
+                if status.is_success() {
+                    Ok(())
+                } else {
+                    Err(ExportError::new(
+                        is_retryable_status(status),
+                        format!("collector responded with {}", status),
+                    ))
+                }
+            }
+        }
+    }
+}
+
+/// Retryable gRPC codes as listed in the OTLP specification.
+fn is_retryable_code(code: Code) -> bool {
+    matches!(
+        code,
+        Code::Cancelled
+            | Code::DeadlineExceeded
+            | Code::ResourceExhausted
+            | Code::Aborted
+            | Code::OutOfRange
+            | Code::Unavailable
+            | Code::DataLoss
+    )
+}
+
+fn is_retryable_status(status: StatusCode) -> bool {
+    matches!(
+        status,
+        StatusCode::TOO_MANY_REQUESTS
+            | StatusCode::BAD_GATEWAY
+            | StatusCode::SERVICE_UNAVAILABLE
+            | StatusCode::GATEWAY_TIMEOUT
+    )
+}
+
+/// Translates the Prometheus registry into cumulative OTLP metrics.
+fn convert_families(
+    families: &[MetricFamily],
+    start_time_unix_nano: u64,
+    time_unix_nano: u64,
+) -> Vec<otlp_metrics::Metric> {
+    families
+        .iter()
+        .filter_map(|family| {
+            let samples = family.get_metric();
+            let attributes = |sample: &prometheus::proto::Metric| -> Vec<KeyValue> {
+                sample
+                    .get_label()
+                    .iter()
+                    .map(|label| string_attr(label.name(), label.value()))
+                    .collect()
+            };
+            let number_point = |sample: &prometheus::proto::Metric, value: f64| NumberDataPoint {
+                attributes: attributes(sample),
+                start_time_unix_nano,
+                time_unix_nano,
+                value: Some(number_data_point::Value::AsDouble(value)),
+                ..Default::default()
+            };
+
+            let data = match family.get_field_type() {
+                MetricType::COUNTER => metric::Data::Sum(otlp_metrics::Sum {
+                    data_points: samples
+                        .iter()
+                        .map(|s| number_point(s, s.get_counter().get_value()))
+                        .collect(),
+                    aggregation_temporality: AggregationTemporality::Cumulative as i32,
+                    is_monotonic: true,
+                }),
+                MetricType::GAUGE => metric::Data::Gauge(otlp_metrics::Gauge {
+                    data_points: samples
+                        .iter()
+                        .map(|s| number_point(s, s.get_gauge().get_value()))
+                        .collect(),
+                }),
+                MetricType::HISTOGRAM => metric::Data::Histogram(otlp_metrics::Histogram {
+                    data_points: samples
+                        .iter()
+                        .map(|s| {
+                            let histogram = s.get_histogram();
+                            let buckets = histogram.get_bucket();
+                            let mut bucket_counts = Vec::with_capacity(buckets.len() + 1);
+                            let mut previous = 0;
+                            for bucket in buckets {
+                                bucket_counts.push(bucket.cumulative_count() - previous);
+                                previous = bucket.cumulative_count();
+                            }
+                            // OTLP carries the implicit +Inf bucket explicitly
+                            bucket_counts.push(histogram.get_sample_count() - previous);
+
+                            HistogramDataPoint {
+                                attributes: attributes(s),
+                                start_time_unix_nano,
+                                time_unix_nano,
+                                count: histogram.get_sample_count(),
+                                sum: Some(histogram.get_sample_sum()),
+                                bucket_counts,
+                                explicit_bounds: buckets.iter().map(|b| b.upper_bound()).collect(),
+                                ..Default::default()
+                            }
+                        })
+                        .collect(),
+                    aggregation_temporality: AggregationTemporality::Cumulative as i32,
+                }),
+                _ => return None,
+            };
+
+            Some(otlp_metrics::Metric {
+                name: family.name().to_string(),
+                description: family.help().to_string(),
+                data: Some(data),
+                ..Default::default()
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        convert::Infallible,
+        net::SocketAddr,
+        sync::{
+            Mutex,
+            atomic::{AtomicU64, Ordering},
+        },
+    };
+
+    use honeybeepf_common::{BlockIoEvent, BlockIoEventType, EventMetadata};
+    use http_body_util::BodyExt;
+    use hyper::{Response, body::Incoming, server::conn::http1, service::service_fn};
+    use hyper_util::rt::TokioIo;
+    use opentelemetry_proto::tonic::collector::logs::v1::{
+        ExportLogsServiceResponse,
+        logs_service_server::{LogsService, LogsServiceServer},
+    };
+    use tokio::net::TcpListener;
+    use tokio_stream::wrappers::TcpListenerStream;
+
+    use super::*;
+    use crate::events::BlockIo;
+
+    fn test_config(endpoint: &str, protocol: OtlpProtocol) -> OtlpConfig {
+        OtlpConfig {
+            batch_size: 2,
+            flush_interval: Duration::from_millis(50),
+            metrics_interval: Duration::from_secs(3600),
+            initial_backoff: Duration::from_millis(10),
+            ..OtlpConfig::new(endpoint, protocol)
+        }
+    }
+
+    fn test_record(name: &str) -> LogRecord {
+        event_record(name, 0, name.to_string(), vec![int_attr("pid", 1)])
+    }
+
+    async fn wait_for<T>(received: &Mutex<Vec<T>>, count: usize) {
+        for _ in 0..100 {
+            if received.lock().unwrap().len() >= count {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        panic!("mock collector did not receive {} requests", count);
+    }
+
+    #[test]
+    fn test_parse_protocol() {
+        assert_eq!("grpc".parse::<OtlpProtocol>().unwrap(), OtlpProtocol::Grpc);
+        assert_eq!(
+            "http/protobuf".parse::<OtlpProtocol>().unwrap(),
+            OtlpProtocol::HttpProtobuf
+        );
+        assert!("http/json".parse::<OtlpProtocol>().is_err());
+        assert_eq!(
+            normalize_endpoint("otel-collector.monitoring.svc:4317"),
+            "http://otel-collector.monitoring.svc:4317"
+        );
+    }
+
+    #[test]
+    fn test_validate_endpoint() {
+        assert!(validate_endpoint("otel-collector.monitoring.svc:4317").is_ok());
+        assert!(validate_endpoint("http://localhost:4318/").is_ok());
+        assert!(validate_endpoint("https://collector:4317").is_err());
+        assert!(validate_endpoint("ftp://collector:4317").is_err());
+        assert!(validate_endpoint("http://:4317").is_err());
+        assert!(validate_endpoint("otel collector:4317").is_err());
+    }
+
+    #[derive(Default)]
+    struct MockLogsService {
+        received: Arc<Mutex<Vec<ExportLogsServiceRequest>>>,
+    }
+
+    #[tonic::async_trait]
+    impl LogsService for MockLogsService {
+        async fn export(
+            &self,
+            request: tonic::Request<ExportLogsServiceRequest>,
+        ) -> Result<tonic::Response<ExportLogsServiceResponse>, tonic::Status> {
+            self.received.lock().unwrap().push(request.into_inner());
+            Ok(tonic::Response::new(ExportLogsServiceResponse::default()))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_grpc_exports_batched_logs() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let service = MockLogsService::default();
+        let received = service.received.clone();
+        tokio::spawn(
+            tonic::transport::Server::builder()
+                .add_service(LogsServiceServer::new(service))
+                .serve_with_incoming(TcpListenerStream::new(listener)),
+        );
+
+        let metrics = Arc::new(ProbeMetrics::new().unwrap());
+        let exporter =
+            OtlpExporter::spawn(test_config(&addr.to_string(), OtlpProtocol::Grpc), metrics)
+                .unwrap();
+        exporter.emit(test_record("first"));
+        exporter.emit(test_record("second"));
+
+        wait_for(&received, 1).await;
+        let request = &received.lock().unwrap()[0];
+        let records = &request.resource_logs[0].scope_logs[0].log_records;
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].event_name, "first");
+    }
+
+    type HttpRequests = Arc<Mutex<Vec<(String, Bytes)>>>;
+
+    /// Serves OTLP/HTTP, answering the first `unavailable` log exports with a 503.
+    /// Metrics exports are accepted and ignored.
+    async fn http_collector(unavailable: u64) -> (SocketAddr, HttpRequests, Arc<AtomicU64>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let received: HttpRequests = Arc::default();
+        let attempts = Arc::new(AtomicU64::new(0));
+
+        let server_received = received.clone();
+        let server_attempts = attempts.clone();
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                let received = server_received.clone();
+                let attempts = server_attempts.clone();
+                let service = service_fn(move |req: Request<Incoming>| {
+                    let received = received.clone();
+                    let attempts = attempts.clone();
+                    async move {
+                        let path = req.uri().path().to_string();
+                        let body = req.into_body().collect().await.unwrap().to_bytes();
+                        let status = if path != LOGS_PATH {
+                            StatusCode::OK
+                        } else if attempts.fetch_add(1, Ordering::SeqCst) < unavailable {
+                            StatusCode::SERVICE_UNAVAILABLE
+                        } else {
+                            received.lock().unwrap().push((path, body));
+                            StatusCode::OK
+                        };
+                        let mut response = Response::new(Full::new(Bytes::new()));
+                        *response.status_mut() = status;
+                        Ok::<_, Infallible>(response)
+                    }
+                });
+                tokio::spawn(http1::Builder::new().serve_connection(TokioIo::new(stream), service));
+            }
+        });
+        (addr, received, attempts)
+    }
+
+    #[tokio::test]
+    async fn test_http_retries_unavailable_collector() {
+        // Reject the first attempt to exercise the retry path
+        let (addr, received, attempts) = http_collector(1).await;
+
+        let metrics = Arc::new(ProbeMetrics::new().unwrap());
+        let exporter = OtlpExporter::spawn(
+            test_config(&format!("http://{}", addr), OtlpProtocol::HttpProtobuf),
+            metrics,
+        )
+        .unwrap();
+        exporter.emit(test_record("retried"));
+
+        wait_for(&received, 1).await;
+        assert_eq!(attempts.load(Ordering::SeqCst), 2);
+        let (path, body) = &received.lock().unwrap()[0];
+        assert_eq!(path, LOGS_PATH);
+        let request = ExportLogsServiceRequest::decode(body.clone()).unwrap();
+        assert_eq!(
+            request.resource_logs[0].scope_logs[0].log_records[0].event_name,
+            "retried"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_retries_stop_at_budget() {
+        let (addr, _, attempts) = http_collector(u64::MAX).await;
+
+        let metrics = Arc::new(ProbeMetrics::new().unwrap());
+        let config = OtlpConfig {
+            max_retries: u32::MAX,
+            retry_budget: Duration::from_millis(200),
+            ..test_config(&format!("http://{}", addr), OtlpProtocol::HttpProtobuf)
+        };
+        let exporter = OtlpExporter::spawn(config, metrics.clone()).unwrap();
+        exporter.emit(test_record("lost"));
+
+        let failed = metrics.otlp_dropped(EXPORT_FAILED);
+        for _ in 0..50 {
+            if failed.get() > 0 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        assert_eq!(failed.get(), 1);
+        assert!(attempts.load(Ordering::SeqCst) > 1);
+        assert_eq!(exporter.dropped(), 0);
+    }
+
+    #[test]
+    fn test_convert_histogram_buckets() {
+        let metrics = ProbeMetrics::new().unwrap();
+        metrics.record_block_io(&BlockIo::from(&BlockIoEvent {
+            metadata: EventMetadata::default(),
+            dev: 0,
+            sector: 0,
+            latency_ns: 0,
+            nr_sector: 8,
+            bytes: 4096,
+            rwbs: [0u8; 8],
+            comm: [0u8; 16],
+            error: 0,
+            event_type: BlockIoEventType::Done as u8,
+        }));
+
+        let converted = convert_families(&metrics.gather(), 1, 2);
+        let histogram = converted
+            .iter()
+            .find_map(|m| match &m.data {
+                Some(metric::Data::Histogram(h)) => Some(h),
+                _ => None,
+            })
+            .unwrap();
+        let point = &histogram.data_points[0];
+        assert_eq!(point.count, 1);
+        assert_eq!(point.bucket_counts.len(), point.explicit_bounds.len() + 1);
+        assert_eq!(point.bucket_counts.iter().sum::<u64>(), 1);
+    }
+}
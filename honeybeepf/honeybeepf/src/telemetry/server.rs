@@ -0,0 +1,136 @@
+use std::{convert::Infallible, net::SocketAddr, sync::Arc, time::Duration};
+
+use anyhow::{Context, Result};
+use bytes::Bytes;
+use http_body_util::Full;
+use hyper::{
+    Method, Request, Response, StatusCode, body::Incoming, header, server::conn::http1,
+    service::service_fn,
+};
+use hyper_util::rt::TokioIo;
+use log::{info, warn};
+use tokio::net::TcpListener;
+
+use crate::telemetry::metrics::ProbeMetrics;
+
+const TEXT_FORMAT: &str = "text/plain; version=0.0.4; charset=utf-8";
+/// Pause after a failed accept, so that running out of descriptors does not spin.
+const ACCEPT_BACKOFF: Duration = Duration::from_millis(100);
+
+/// Binds the metrics listener and serves scrapes until the task is dropped.
+pub async fn serve(addr: SocketAddr, path: String, metrics: Arc<ProbeMetrics>) -> Result<()> {
+    let listener = TcpListener::bind(addr)
+        .await
+        .with_context(|| format!("Failed to bind metrics listener on {}", addr))?;
+    info!("Serving Prometheus metrics on http://{}{}", addr, path);
+    serve_listener(listener, path, metrics).await
+}
+
+/// Serves scrapes on `listener`. Failed accepts are logged and retried, so that
+/// running out of descriptors only interrupts the endpoint until some are freed.
+pub async fn serve_listener(
+    listener: TcpListener,
+    path: String,
+    metrics: Arc<ProbeMetrics>,
+) -> Result<()> {
+    let path: Arc<str> = path.into();
+    loop {
+        let (stream, peer) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                // Out of descriptors or an aborted handshake; the listener itself is fine
+                warn!("Failed to accept metrics connection: {}", e);
+                tokio::time::sleep(ACCEPT_BACKOFF).await;
+                continue;
+            }
+        };
+        let path = path.clone();
+        let metrics = metrics.clone();
+
+        tokio::spawn(async move {
+            let service = service_fn(move |req| {
+                let response = handle(&req, &path, &metrics);
+                async move { Ok::<_, Infallible>(response) }
+            });
+            if let Err(e) = http1::Builder::new()
+                .serve_connection(TokioIo::new(stream), service)
+                .await
+            {
+                warn!("Metrics connection from {} failed: {}", peer, e);
+            }
+        });
+    }
+}
+
+fn handle(req: &Request<Incoming>, path: &str, metrics: &ProbeMetrics) -> Response<Full<Bytes>> {
+    if req.method() != Method::GET || req.uri().path() != path {
+        return respond(StatusCode::NOT_FOUND, "text/plain", "Not Found\n".into());
+    }
+
+    match metrics.encode() {
+        Ok(body) => respond(StatusCode::OK, TEXT_FORMAT, body),
+        Err(e) => {
+            warn!("Failed to encode metrics: {}", e);
+            respond(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "text/plain",
+                format!("{}\n", e),
+            )
+        }
+    }
+}
+
+fn respond(status: StatusCode, content_type: &str, body: String) -> Response<Full<Bytes>> {
+    let mut response = Response::new(Full::new(Bytes::from(body)));
+    *response.status_mut() = status;
+    if let Ok(value) = content_type.parse() {
+        response.headers_mut().insert(header::CONTENT_TYPE, value);
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use honeybeepf_common::{ConnectionEvent, EventMetadata};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    use super::*;
+    use crate::events::Connection;
+
+    async fn get(addr: SocketAddr, path: &str) -> String {
+        let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        let request = format!(
+            "GET {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+            path
+        );
+        stream.write_all(request.as_bytes()).await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        response
+    }
+
+    #[tokio::test]
+    async fn test_serves_metrics_path_only() {
+        let metrics = Arc::new(ProbeMetrics::new().unwrap());
+        metrics.record_connection(&Connection::from(&ConnectionEvent {
+            metadata: EventMetadata::default(),
+            dest_addr: [0; 16],
+            scope_id: 0,
+            dest_port: 0,
+            address_family: libc::AF_INET as u16,
+            latency_ns: 0,
+            error: 0,
+            _pad: 0,
+        }));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(serve_listener(listener, "/metrics".to_string(), metrics));
+
+        let response = get(addr, "/metrics").await;
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(response.contains("# TYPE honeybeepf_network_connect_total counter"));
+
+        let response = get(addr, "/other").await;
+        assert!(response.starts_with("HTTP/1.1 404 Not Found"));
+    }
+}